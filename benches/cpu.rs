@@ -0,0 +1,62 @@
+//! Runs a tight loop of common instructions and prints how fast the emulator gets through them.
+//! Rendering is off, but the PPU and APU still run alongside the CPU. Run it with `cargo bench`.
+
+use std::time::Instant;
+
+use nesty::nes::{Powerable, NES};
+
+#[path = "../src/test_rom.rs"]
+#[allow(dead_code)]
+mod test_rom;
+
+use test_rom::TestRom;
+
+const INSTRUCTIONS: u64 = 5_000_000;
+/// NTSC CPU clock
+const CPU_HZ: f64 = 1_789_773.0;
+
+/// Loaded at $C000, loops forever through most addressing modes
+const PROGRAM: &[u8] = &[
+    0xA2, 0x00, //       LDX #$00
+    0xBD, 0x00, 0x03, // loop: LDA $0300,X
+    0x69, 0x01, //       ADC #$01
+    0x9D, 0x00, 0x03, // STA $0300,X
+    0xE6, 0x10, //       INC $10
+    0xA4, 0x10, //       LDY $10
+    0xB1, 0x20, //       LDA ($20),Y
+    0x0A, //             ASL A
+    0x48, //             PHA
+    0x68, //             PLA
+    0xE8, //             INX
+    0xD0, 0xEC, //       BNE loop
+    0x4C, 0x02, 0xC0, // JMP loop
+];
+
+fn main() {
+    let mut nes = NES::default();
+    nes.power_on();
+    nes.load_rom(
+        TestRom::new(1)
+            .code(0xC000, PROGRAM)
+            .reset_vector(0xC000)
+            .build(),
+    )
+    .expect("the benchmark ROM should load");
+
+    let start = Instant::now();
+    for _ in 0..INSTRUCTIONS {
+        nes.step_instruction()
+            .expect("the benchmark shouldn't touch unmapped memory");
+    }
+    let seconds = start.elapsed().as_secs_f64();
+    let cycles = nes.cpu().cycle;
+    println!(
+        "{} instructions, {} cycles in {:.3}s: {:.2}M instructions/s, {:.1} ns/instruction, {:.1}x real time",
+        INSTRUCTIONS,
+        cycles,
+        seconds,
+        INSTRUCTIONS as f64 / seconds / 1e6,
+        seconds * 1e9 / INSTRUCTIONS as f64,
+        cycles as f64 / CPU_HZ / seconds
+    );
+}
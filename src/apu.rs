@@ -1,11 +1,355 @@
+pub mod dmc;
+pub mod envelope;
+pub mod length_counter;
+pub mod noise;
+pub mod pulse;
+pub mod resampler;
+pub mod triangle;
+
 use crate::nes::Powerable;
+use crate::region::Region;
+
+use dmc::Dmc;
+use noise::Noise;
+use pulse::Pulse;
+use resampler::Resampler;
+use triangle::Triangle;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+/// CPU cycles at which the frame counter clocks the envelopes and linear counter (quarter
+/// frames), and also the length counters and sweeps (half frames). In 4-step mode the last half
+/// frame is the cycle after the IRQ, and the sequence ends the cycle after that. In 5-step mode
+/// it ends the cycle after the last half frame.
+struct FrameSequence {
+    quarter_frame_1: u32,
+    half_frame_1: u32,
+    quarter_frame_3: u32,
+    four_step_irq: u32,
+    five_step_half_frame: u32,
+}
+
+const NTSC_FRAME_SEQUENCE: FrameSequence = FrameSequence {
+    quarter_frame_1: 7457,
+    half_frame_1: 14913,
+    quarter_frame_3: 22371,
+    four_step_irq: 29828,
+    five_step_half_frame: 37281,
+};
+const PAL_FRAME_SEQUENCE: FrameSequence = FrameSequence {
+    quarter_frame_1: 8313,
+    half_frame_1: 16627,
+    quarter_frame_3: 24939,
+    four_step_irq: 33252,
+    five_step_half_frame: 41565,
+};
+
+pub struct APU {
+    pulse_1: Pulse,
+    pulse_2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+
+    region: Region,
+    cycle: u64,
+    frame_cycle: u32,
+    five_step_mode: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    /// CPU cycles until a $4017 write resets the frame counter
+    frame_reset_delay: u8,
+
+    resampler: Resampler,
+}
+
+impl Default for APU {
+    fn default() -> Self {
+        APU {
+            pulse_1: Pulse::new(true),
+            pulse_2: Pulse::new(false),
+            triangle: Triangle::default(),
+            noise: Noise::default(),
+            dmc: Dmc::default(),
+            region: Region::Ntsc,
+            cycle: 0,
+            frame_cycle: 0,
+            five_step_mode: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            frame_reset_delay: 0,
+            resampler: Resampler::new(Region::Ntsc.cpu_clock_rate(), DEFAULT_SAMPLE_RATE),
+        }
+    }
+}
+
+impl APU {
+    pub fn frame_irq(&self) -> bool {
+        self.frame_irq
+    }
+
+    pub fn dmc_irq(&self) -> bool {
+        self.dmc.irq()
+    }
+
+    pub fn take_dmc_dma_request(&mut self) -> Option<u16> {
+        self.dmc.take_dma_request()
+    }
+
+    /// Whether the DMC still waits for the byte of its last DMA request
+    pub fn dmc_dma_pending(&self) -> bool {
+        self.dmc.dma_pending()
+    }
+
+    /// Hands the DMC the sample byte its DMA request fetched
+    pub fn load_dmc_sample(&mut self, value: u8) {
+        self.dmc.load_sample(value);
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.resampler = Resampler::new(self.region.cpu_clock_rate(), sample_rate);
+    }
+
+    /// PAL has its own frame counter timing and noise and DMC periods, the Dendy uses the NTSC
+    /// ones
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.noise.set_pal(region == Region::Pal);
+        self.dmc.set_pal(region == Region::Pal);
+        self.set_sample_rate(self.sample_rate());
+    }
+
+    fn frame_sequence(&self) -> &'static FrameSequence {
+        match self.region {
+            Region::Pal => &PAL_FRAME_SEQUENCE,
+            Region::Ntsc | Region::Dendy => &NTSC_FRAME_SEQUENCE,
+        }
+    }
 
-#[derive(Default)]
-pub struct APU {}
+    pub fn sample_rate(&self) -> u32 {
+        self.resampler.sample_rate()
+    }
 
-impl APU {}
+    /// Audio produced since the last call, mono f32 samples at the sample rate
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.resampler.take_samples()
+    }
+
+    /// Reads $4015, bit 5 isn't driven
+    pub fn read_status(&mut self, open_bus: u8) -> u8 {
+        let value = (self.pulse_1.length_counter.active() as u8)
+            | (self.pulse_2.length_counter.active() as u8) << 1
+            | (self.triangle.length_counter.active() as u8) << 2
+            | (self.noise.length_counter.active() as u8) << 3
+            | (self.dmc.active() as u8) << 4
+            | (open_bus & 0x20)
+            | (self.frame_irq as u8) << 6
+            | (self.dmc.irq() as u8) << 7;
+        self.frame_irq = false;
+        value
+    }
+
+    pub fn write_reg(&mut self, address: u16, value: u8) {
+        match address {
+            0x4000..=0x4003 => self.pulse_1.write_reg(address & 3, value),
+            0x4004..=0x4007 => self.pulse_2.write_reg(address & 3, value),
+            0x4008..=0x400B => self.triangle.write_reg(address & 3, value),
+            0x400C..=0x400F => self.noise.write_reg(address & 3, value),
+            0x4010..=0x4013 => self.dmc.write_reg(address & 3, value),
+            0x4015 => self.write_status(value),
+            0x4017 => self.write_frame_counter(value),
+            // OAM DMA and the controller ports, handled by the bus
+            _ => {}
+        }
+    }
+
+    fn write_status(&mut self, value: u8) {
+        self.pulse_1.length_counter.set_enabled(value & 0x01 != 0);
+        self.pulse_2.length_counter.set_enabled(value & 0x02 != 0);
+        self.triangle.length_counter.set_enabled(value & 0x04 != 0);
+        self.noise.length_counter.set_enabled(value & 0x08 != 0);
+        self.dmc.set_enabled(value & 0x10 != 0);
+    }
+
+    fn write_frame_counter(&mut self, value: u8) {
+        self.five_step_mode = value & 0x80 != 0;
+        self.irq_inhibit = value & 0x40 != 0;
+        if self.irq_inhibit {
+            self.frame_irq = false;
+        }
+        // The reset happens 3 CPU cycles later when written during an APU cycle, 4 otherwise
+        self.frame_reset_delay = if self.cycle.is_multiple_of(2) { 3 } else { 4 };
+    }
+
+    /// Advances the APU by one CPU cycle
+    pub fn tick(&mut self) {
+        self.cycle += 1;
+        if self.cycle.is_multiple_of(2) {
+            self.pulse_1.clock_timer();
+            self.pulse_2.clock_timer();
+        }
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        self.clock_frame_counter();
+
+        let sample = self.mix();
+        self.resampler.push(sample);
+    }
+
+    fn clock_frame_counter(&mut self) {
+        if self.frame_reset_delay > 0 {
+            self.frame_reset_delay -= 1;
+            if self.frame_reset_delay == 0 {
+                self.frame_cycle = 0;
+                if self.five_step_mode {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+            }
+        }
+
+        self.frame_cycle += 1;
+        let sequence = self.frame_sequence();
+        match (self.five_step_mode, self.frame_cycle) {
+            (_, cycle)
+                if cycle == sequence.quarter_frame_1 || cycle == sequence.quarter_frame_3 =>
+            {
+                self.clock_quarter_frame()
+            }
+            (five_step, cycle)
+                if cycle == sequence.half_frame_1
+                    || (five_step && cycle == sequence.five_step_half_frame) =>
+            {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            (false, cycle) if cycle == sequence.four_step_irq => self.set_frame_irq(),
+            (false, cycle) if cycle == sequence.four_step_irq + 1 => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+                self.set_frame_irq();
+            }
+            (false, cycle) if cycle == sequence.four_step_irq + 2 => {
+                self.set_frame_irq();
+                self.frame_cycle = 0;
+            }
+            (true, cycle) if cycle == sequence.five_step_half_frame + 1 => self.frame_cycle = 0,
+            _ => {}
+        }
+    }
+
+    fn set_frame_irq(&mut self) {
+        if !self.irq_inhibit {
+            self.frame_irq = true;
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse_1.envelope.clock();
+        self.pulse_2.envelope.clock();
+        self.triangle.clock_linear_counter();
+        self.noise.envelope.clock();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulse_1.length_counter.clock();
+        self.pulse_1.clock_sweep();
+        self.pulse_2.length_counter.clock();
+        self.pulse_2.clock_sweep();
+        self.triangle.length_counter.clock();
+        self.noise.length_counter.clock();
+    }
+
+    /// The non-linear DAC mix of the channels, between 0 and 1
+    fn mix(&self) -> f32 {
+        let pulse = (self.pulse_1.output() + self.pulse_2.output()) as f32;
+        let pulse_out = match pulse {
+            0.0 => 0.0,
+            _ => 95.88 / (8128.0 / pulse + 100.0),
+        };
+        let tnd = self.triangle.output() as f32 / 8227.0
+            + self.noise.output() as f32 / 12241.0
+            + self.dmc.output() as f32 / 22638.0;
+        let tnd_out = match tnd {
+            0.0 => 0.0,
+            _ => 159.79 / (1.0 / tnd + 100.0),
+        };
+        pulse_out + tnd_out
+    }
+}
 
 impl Powerable for APU {
-    fn power_on(&mut self) {}
-    fn reset(&mut self) {}
+    fn power_on(&mut self) {
+        let sample_rate = self.sample_rate();
+        let region = self.region;
+        *self = APU::default();
+        self.set_region(region);
+        self.set_sample_rate(sample_rate);
+    }
+    fn reset(&mut self) {
+        // Silenced, and the frame counter restarts as if $4017 was written again
+        self.write_status(0);
+        self.frame_irq = false;
+        self.write_frame_counter((self.five_step_mode as u8) << 7 | (self.irq_inhibit as u8) << 6);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ticks until the frame IRQ is raised and returns the number of cycles it took
+    fn cycles_until_frame_irq(apu: &mut APU, limit: u32) -> Option<u32> {
+        (1..=limit).find(|_| {
+            apu.tick();
+            apu.frame_irq()
+        })
+    }
+
+    #[test]
+    fn raises_frame_irq_in_4_step_mode() {
+        let mut apu = APU::default();
+        // Written on an even cycle, the sequence restarts 3 cycles later
+        apu.write_reg(0x4017, 0x00);
+        assert_eq!(cycles_until_frame_irq(&mut apu, 40_000), Some(29830));
+        // Reading $4015 acknowledges it, the two cycles after set it again
+        apu.read_status(0);
+        apu.tick();
+        assert!(apu.frame_irq());
+        apu.read_status(0);
+        apu.tick();
+        assert!(apu.frame_irq());
+        apu.read_status(0);
+        apu.tick();
+        assert!(!apu.frame_irq());
+        // Once per sequence
+        assert_eq!(cycles_until_frame_irq(&mut apu, 40_000), Some(29827));
+    }
+
+    #[test]
+    fn inhibit_keeps_the_frame_irq_from_being_raised() {
+        let mut apu = APU::default();
+        apu.write_reg(0x4017, 0x00);
+        assert!(cycles_until_frame_irq(&mut apu, 40_000).is_some());
+        // Setting the inhibit flag also clears the IRQ
+        apu.write_reg(0x4017, 0x40);
+        assert!(!apu.frame_irq());
+        assert_eq!(cycles_until_frame_irq(&mut apu, 100_000), None);
+    }
+
+    #[test]
+    fn never_raises_frame_irq_in_5_step_mode() {
+        let mut apu = APU::default();
+        apu.write_reg(0x4017, 0x80);
+        assert_eq!(cycles_until_frame_irq(&mut apu, 100_000), None);
+    }
+
+    #[test]
+    fn ignores_the_io_registers() {
+        let mut apu = APU::default();
+        apu.write_reg(0x4014, 0x02);
+        apu.write_reg(0x4016, 0x01);
+        assert_eq!(apu.read_status(0), 0);
+    }
 }
@@ -0,0 +1,186 @@
+/// Timer periods in CPU cycles
+const NTSC_RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const PAL_RATE_TABLE: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+/// The delta modulation channel at $4010-$4013, which plays 1 bit delta samples fetched from
+/// memory by DMA
+pub struct Dmc {
+    irq_enabled: bool,
+    irq: bool,
+    looping: bool,
+    rate_table: &'static [u16; 16],
+    /// Index into `rate_table` from the last rate write
+    rate_index: u8,
+    period: u16,
+    timer: u16,
+    output_level: u8,
+
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+    /// Set while a DMA fetch for the sample buffer is in flight
+    dma_pending: bool,
+    dma_request: Option<u16>,
+
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+}
+
+impl Default for Dmc {
+    fn default() -> Self {
+        Dmc {
+            irq_enabled: false,
+            irq: false,
+            looping: false,
+            rate_table: &NTSC_RATE_TABLE,
+            rate_index: 0,
+            period: NTSC_RATE_TABLE[0],
+            timer: 0,
+            output_level: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            dma_pending: false,
+            dma_request: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+        }
+    }
+}
+
+impl Dmc {
+    /// Switches the rate table, the current rate is looked up again right away
+    pub fn set_pal(&mut self, pal: bool) {
+        self.rate_table = if pal {
+            &PAL_RATE_TABLE
+        } else {
+            &NTSC_RATE_TABLE
+        };
+        self.period = self.rate_table[self.rate_index as usize];
+    }
+
+    pub fn write_reg(&mut self, reg: u16, value: u8) {
+        match reg {
+            0 => {
+                self.irq_enabled = value & 0x80 != 0;
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+                self.looping = value & 0x40 != 0;
+                self.rate_index = value & 0x0F;
+                self.period = self.rate_table[self.rate_index as usize];
+            }
+            1 => self.output_level = value & 0x7F,
+            2 => self.sample_address = 0xC000 + value as u16 * 64,
+            _ => self.sample_length = value as u16 * 16 + 1,
+        }
+    }
+
+    /// Enabling through $4015 restarts the sample if it had finished, disabling it cancels a
+    /// fetch that hasn't happened yet
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+            self.dma_pending = false;
+            self.dma_request = None;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+            self.request_dma();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    fn request_dma(&mut self) {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 && !self.dma_pending {
+            self.dma_pending = true;
+            self.dma_request = Some(self.current_address);
+        }
+    }
+
+    pub fn take_dma_request(&mut self) -> Option<u16> {
+        self.dma_request.take()
+    }
+
+    /// Whether a fetch was requested and its byte hasn't arrived yet
+    pub fn dma_pending(&self) -> bool {
+        self.dma_pending
+    }
+
+    pub fn load_sample(&mut self, value: u8) {
+        // The channel was disabled while the fetch was on its way
+        if !self.dma_pending || self.bytes_remaining == 0 {
+            return;
+        }
+        self.dma_pending = false;
+        self.sample_buffer = Some(value);
+        // Wraps around to $8000
+        self.current_address = self.current_address.checked_add(1).unwrap_or(0x8000);
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period - 1;
+
+        if !self.silence {
+            if self.shift_register & 1 != 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift_register = sample;
+                }
+                None => self.silence = true,
+            }
+            self.request_dma();
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    pub fn irq(&self) -> bool {
+        self.irq
+    }
+
+    pub fn output(&self) -> u8 {
+        self.output_level
+    }
+}
@@ -0,0 +1,51 @@
+/// Volume control of the pulse and noise channels, a constant volume or a decaying sawtooth
+#[derive(Default)]
+pub struct Envelope {
+    start: bool,
+    divider: u8,
+    decay_level: u8,
+    looping: bool,
+    constant_volume: bool,
+    /// The constant volume, or the period of the divider
+    volume: u8,
+}
+
+impl Envelope {
+    /// Takes the low 6 bits of $4000/$4004/$400C
+    pub fn write_control(&mut self, value: u8) {
+        self.looping = value & 0x20 != 0;
+        self.constant_volume = value & 0x10 != 0;
+        self.volume = value & 0x0F;
+    }
+
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    /// Clocked by the frame counter's quarter frames
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay_level = 15;
+            self.divider = self.volume;
+            return;
+        }
+        if self.divider > 0 {
+            self.divider -= 1;
+            return;
+        }
+        self.divider = self.volume;
+        if self.decay_level > 0 {
+            self.decay_level -= 1;
+        } else if self.looping {
+            self.decay_level = 15;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        match self.constant_volume {
+            true => self.volume,
+            false => self.decay_level,
+        }
+    }
+}
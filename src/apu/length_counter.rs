@@ -0,0 +1,82 @@
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+/// Silences a channel once the note's duration runs out
+#[derive(Default)]
+pub struct LengthCounter {
+    enabled: bool,
+    halted: bool,
+    counter: u8,
+}
+
+impl LengthCounter {
+    /// Disabling the channel through $4015 clears the counter and keeps it from being loaded
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    pub fn set_halted(&mut self, halted: bool) {
+        self.halted = halted;
+    }
+
+    /// Takes the 5 bit index written to the upper bits of $4003/$4007/$400B/$400F
+    pub fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(index & 0x1F) as usize];
+        }
+    }
+
+    /// Clocked by the frame counter's half frames
+    pub fn clock(&mut self) {
+        if self.counter > 0 && !self.halted {
+            self.counter -= 1;
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.counter > 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_only_while_enabled() {
+        let mut length_counter = LengthCounter::default();
+        length_counter.load(1);
+        assert!(!length_counter.active());
+        length_counter.set_enabled(true);
+        length_counter.load(1);
+        assert_eq!(length_counter.counter, 254);
+        // Disabling clears it
+        length_counter.set_enabled(false);
+        assert!(!length_counter.active());
+    }
+
+    #[test]
+    fn halting_stops_the_countdown() {
+        let mut length_counter = LengthCounter::default();
+        length_counter.set_enabled(true);
+        length_counter.load(3);
+        assert_eq!(length_counter.counter, 2);
+        length_counter.set_halted(true);
+        for _ in 0..4 {
+            length_counter.clock();
+        }
+        assert_eq!(length_counter.counter, 2);
+        length_counter.set_halted(false);
+        length_counter.clock();
+        assert!(length_counter.active());
+        length_counter.clock();
+        assert!(!length_counter.active());
+        length_counter.clock();
+        assert_eq!(length_counter.counter, 0);
+    }
+}
@@ -0,0 +1,91 @@
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+
+/// Timer periods in CPU cycles
+const NTSC_PERIOD_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const PAL_PERIOD_TABLE: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+/// The pseudo-random noise channel at $400C-$400F
+pub struct Noise {
+    pub envelope: Envelope,
+    pub length_counter: LengthCounter,
+
+    /// Short mode takes the feedback from bit 6 instead of bit 1, giving a 93 step sequence
+    short_mode: bool,
+    period_table: &'static [u16; 16],
+    /// Index into `period_table` from the last period write
+    period_index: u8,
+    period: u16,
+    timer: u16,
+    shift_register: u16,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Noise {
+            envelope: Envelope::default(),
+            length_counter: LengthCounter::default(),
+            short_mode: false,
+            period_table: &NTSC_PERIOD_TABLE,
+            period_index: 0,
+            period: NTSC_PERIOD_TABLE[0],
+            timer: 0,
+            // Loaded with 1 on power up
+            shift_register: 1,
+        }
+    }
+}
+
+impl Noise {
+    /// Switches the period table, the current period is looked up again right away
+    pub fn set_pal(&mut self, pal: bool) {
+        self.period_table = if pal {
+            &PAL_PERIOD_TABLE
+        } else {
+            &NTSC_PERIOD_TABLE
+        };
+        self.period = self.period_table[self.period_index as usize];
+    }
+
+    pub fn write_reg(&mut self, reg: u16, value: u8) {
+        match reg {
+            0 => {
+                self.length_counter.set_halted(value & 0x20 != 0);
+                self.envelope.write_control(value);
+            }
+            1 => {}
+            2 => {
+                self.short_mode = value & 0x80 != 0;
+                self.period_index = value & 0x0F;
+                self.period = self.period_table[self.period_index as usize];
+            }
+            _ => {
+                self.length_counter.load(value >> 3);
+                self.envelope.restart();
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period - 1;
+        let tap = if self.short_mode { 6 } else { 1 };
+        let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+        self.shift_register = (self.shift_register >> 1) | feedback << 14;
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.shift_register & 1 != 0 || !self.length_counter.active() {
+            return 0;
+        }
+        self.envelope.output()
+    }
+}
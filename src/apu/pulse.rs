@@ -0,0 +1,150 @@
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+/// The two square wave channels at $4000-$4003 and $4004-$4007
+#[derive(Default)]
+pub struct Pulse {
+    /// Pulse 1 negates its sweep with the ones' complement, pulse 2 with the two's complement
+    ones_complement: bool,
+    pub envelope: Envelope,
+    pub length_counter: LengthCounter,
+
+    duty: u8,
+    step: u8,
+    period: u16,
+    timer: u16,
+
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    pub fn new(ones_complement: bool) -> Self {
+        Pulse {
+            ones_complement,
+            ..Default::default()
+        }
+    }
+
+    pub fn write_reg(&mut self, reg: u16, value: u8) {
+        match reg {
+            0 => {
+                self.duty = value >> 6;
+                self.length_counter.set_halted(value & 0x20 != 0);
+                self.envelope.write_control(value);
+            }
+            1 => {
+                self.sweep_enabled = value & 0x80 != 0;
+                self.sweep_period = (value >> 4) & 7;
+                self.sweep_negate = value & 0x08 != 0;
+                self.sweep_shift = value & 7;
+                self.sweep_reload = true;
+            }
+            2 => self.period = (self.period & 0x700) | value as u16,
+            _ => {
+                self.period = (self.period & 0xFF) | ((value & 7) as u16) << 8;
+                self.length_counter.load(value >> 3);
+                self.step = 0;
+                self.envelope.restart();
+            }
+        }
+    }
+
+    /// Clocked every APU cycle (every other CPU cycle)
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) & 7;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Clocked by the frame counter's half frames
+    pub fn clock_sweep(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.period = self.target_period();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn target_period(&self) -> u16 {
+        let change = self.period >> self.sweep_shift;
+        match (self.sweep_negate, self.ones_complement) {
+            (false, _) => self.period + change,
+            (true, true) => self.period.saturating_sub(change + 1),
+            (true, false) => self.period.saturating_sub(change),
+        }
+    }
+
+    /// The sweep unit mutes the channel even when it's disabled
+    fn muted(&self) -> bool {
+        self.period < 8 || self.target_period() > 0x7FF
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.muted()
+            || !self.length_counter.active()
+            || DUTY_TABLE[self.duty as usize][self.step as usize] == 0
+        {
+            return 0;
+        }
+        self.envelope.output()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pulse 2 with `period` and `sweep` written to its registers
+    fn pulse(period: u16, sweep: u8) -> Pulse {
+        let mut pulse = Pulse::new(false);
+        pulse.write_reg(1, sweep);
+        pulse.write_reg(2, period as u8);
+        pulse.write_reg(3, (period >> 8) as u8);
+        pulse
+    }
+
+    #[test]
+    fn sweep_mutes_periods_below_8() {
+        assert!(pulse(7, 0x00).muted());
+        assert!(!pulse(8, 0x00).muted());
+    }
+
+    #[test]
+    fn sweep_mutes_target_periods_above_7ff() {
+        // Even with the sweep disabled, a shift of 0 adds the whole period
+        assert!(pulse(0x400, 0x00).muted());
+        assert!(!pulse(0x3FF, 0x00).muted());
+        assert!(pulse(0x700, 0x02).muted());
+        assert!(!pulse(0x600, 0x02).muted());
+        // Negating never overflows
+        assert!(!pulse(0x7FF, 0x08).muted());
+    }
+
+    #[test]
+    fn muted_channels_keep_their_period() {
+        // Enabled, divider period 0, shift 1
+        let mut pulse = pulse(0x600, 0x81);
+        pulse.clock_sweep();
+        assert_eq!(pulse.period, 0x600);
+        assert_eq!(pulse.output(), 0);
+    }
+}
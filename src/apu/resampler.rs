@@ -0,0 +1,65 @@
+use std::f32::consts::PI;
+
+/// Cutoff of the high-pass filter that removes the DC offset of the mixer output
+const HIGH_PASS_CUTOFF: f32 = 90.0;
+
+/// Turns the once-per-CPU-cycle mixer output into a stream at the host's sample rate by averaging
+/// all the values that fall into each output sample
+pub struct Resampler {
+    sample_rate: u32,
+    cycles_per_sample: f64,
+    cycles: f64,
+    sum: f32,
+    count: u32,
+    high_pass: f32,
+    previous_input: f32,
+    previous_output: f32,
+    samples: Vec<f32>,
+}
+
+impl Resampler {
+    pub fn new(clock_rate: f64, sample_rate: u32) -> Self {
+        Resampler {
+            sample_rate,
+            cycles_per_sample: clock_rate / sample_rate as f64,
+            cycles: 0.0,
+            sum: 0.0,
+            count: 0,
+            high_pass: (-2.0 * PI * HIGH_PASS_CUTOFF / sample_rate as f32).exp(),
+            previous_input: 0.0,
+            previous_output: 0.0,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn push(&mut self, value: f32) {
+        self.sum += value;
+        self.count += 1;
+        self.cycles += 1.0;
+        if self.cycles < self.cycles_per_sample {
+            return;
+        }
+        self.cycles -= self.cycles_per_sample;
+
+        let average = self.sum / self.count as f32;
+        self.sum = 0.0;
+        self.count = 0;
+        let output = average - self.previous_input + self.high_pass * self.previous_output;
+        self.previous_input = average;
+        self.previous_output = output;
+
+        // Keep at most a second of audio when nobody is pulling it
+        if self.samples.len() >= self.sample_rate as usize {
+            self.samples.drain(..self.samples.len() / 2);
+        }
+        self.samples.push(output);
+    }
+
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+}
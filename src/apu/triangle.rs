@@ -0,0 +1,70 @@
+use super::length_counter::LengthCounter;
+
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+/// The triangle wave channel at $4008-$400B
+#[derive(Default)]
+pub struct Triangle {
+    pub length_counter: LengthCounter,
+
+    step: u8,
+    period: u16,
+    timer: u16,
+
+    /// Also halts the length counter
+    control: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+}
+
+impl Triangle {
+    pub fn write_reg(&mut self, reg: u16, value: u8) {
+        match reg {
+            0 => {
+                self.control = value & 0x80 != 0;
+                self.length_counter.set_halted(self.control);
+                self.linear_reload_value = value & 0x7F;
+            }
+            1 => {}
+            2 => self.period = (self.period & 0x700) | value as u16,
+            _ => {
+                self.period = (self.period & 0xFF) | ((value & 7) as u16) << 8;
+                self.length_counter.load(value >> 3);
+                self.linear_reload = true;
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period;
+        if self.linear_counter > 0 && self.length_counter.active() {
+            self.step = (self.step + 1) & 31;
+        }
+    }
+
+    /// Clocked by the frame counter's quarter frames
+    pub fn clock_linear_counter(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    /// The channel keeps outputting its current step when it's silenced
+    pub fn output(&self) -> u8 {
+        SEQUENCE[self.step as usize]
+    }
+}
@@ -0,0 +1,138 @@
+//! Runs test ROMs that report through blargg's protocol in PRG-RAM: $6000 holds the status, $80
+//! while running, $81 when the test wants the console reset, or the result code once done (0 for
+//! a pass). $6001-$6003 hold $DE $B0 $61 once the status is valid, and $6004 on has the text the
+//! test printed, zero terminated.
+
+use crate::cpu::Jam;
+use crate::error::NesError;
+use crate::nes::{Powerable, StopReason, NES};
+
+const STATUS_ADDR: u16 = 0x6000;
+const SIGNATURE_ADDR: u16 = 0x6001;
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const MESSAGE_ADDR: u16 = 0x6004;
+const MESSAGE_END: u16 = 0x7FFF;
+
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_NEEDS_RESET: u8 = 0x81;
+/// Resets have to wait at least 100ms after the test asks for one
+const RESET_DELAY_FRAMES: u64 = 6;
+
+/// A minute of emulated time, enough for the slowest tests
+pub const DEFAULT_TIMEOUT_FRAMES: u64 = 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestOutcome {
+    Passed,
+    /// The result code the test reported
+    Failed(u8),
+    /// The test never reported a result
+    TimedOut,
+    Jammed(Jam),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestReport {
+    pub outcome: TestOutcome,
+    /// What the test printed, usually the name of the test and why it failed
+    pub message: String,
+    pub frames: u64,
+}
+
+/// Runs a test ROM headlessly until it reports a result, or for `timeout_frames` frames
+pub fn run_test_rom(ines: Vec<u8>, timeout_frames: u64) -> Result<TestReport, NesError> {
+    let mut nes = NES::default();
+    nes.power_on();
+    nes.load_rom(ines)?;
+
+    let mut reset_frame = None;
+    let outcome = loop {
+        if nes.frame() >= timeout_frames {
+            break TestOutcome::TimedOut;
+        }
+        if let StopReason::Jammed(jam) = nes.run_frame()? {
+            break TestOutcome::Jammed(jam);
+        }
+        match status(&mut nes) {
+            None | Some(STATUS_RUNNING) => {}
+            Some(STATUS_NEEDS_RESET) => {
+                let frame = *reset_frame.get_or_insert(nes.frame() + RESET_DELAY_FRAMES);
+                if nes.frame() >= frame {
+                    nes.reset();
+                    reset_frame = None;
+                }
+            }
+            Some(0) => break TestOutcome::Passed,
+            Some(code) => break TestOutcome::Failed(code),
+        }
+    };
+    Ok(TestReport {
+        outcome,
+        message: message(&mut nes),
+        frames: nes.frame(),
+    })
+}
+
+/// The status byte, once the signature says it's valid
+fn status(nes: &mut NES) -> Option<u8> {
+    let signature = [0, 1, 2].map(|i| nes.peek(SIGNATURE_ADDR + i));
+    (signature == SIGNATURE).then(|| nes.peek(STATUS_ADDR))
+}
+
+fn message(nes: &mut NES) -> String {
+    let bytes: Vec<u8> = (MESSAGE_ADDR..=MESSAGE_END)
+        .map(|address| nes.peek(address))
+        .take_while(|&byte| byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom::TestRom;
+
+    /// An NROM image whose program stores each `(address, value)` pair and then loops forever
+    fn rom(writes: &[(u16, u8)]) -> Vec<u8> {
+        let mut program = Vec::new();
+        for &(address, value) in writes {
+            // LDA #value, STA address
+            program.extend([0xA9, value, 0x8D, address as u8, (address >> 8) as u8]);
+        }
+        // JMP to itself
+        let end = 0x8000 + program.len() as u16;
+        program.extend([0x4C, end as u8, (end >> 8) as u8]);
+        TestRom::new(2).code(0x8000, &program).build()
+    }
+
+    /// The writes of a test that prints `message` and finishes with `status`
+    fn report(message: &str, status: u8) -> Vec<(u16, u8)> {
+        let mut writes: Vec<(u16, u8)> = (MESSAGE_ADDR..).zip(message.bytes().chain([0])).collect();
+        writes.push((STATUS_ADDR, STATUS_RUNNING));
+        writes.extend((SIGNATURE_ADDR..).zip(SIGNATURE));
+        writes.push((STATUS_ADDR, status));
+        writes
+    }
+
+    #[test]
+    fn reports_a_pass() {
+        let report = run_test_rom(rom(&report("All tests passed\n", 0)), 10).unwrap();
+        assert_eq!(report.outcome, TestOutcome::Passed);
+        assert_eq!(report.message, "All tests passed");
+        assert!(report.frames < 10);
+    }
+
+    #[test]
+    fn reports_a_failure_code_and_message() {
+        let report = run_test_rom(rom(&report("LDA\nFailed #3", 3)), 10).unwrap();
+        assert_eq!(report.outcome, TestOutcome::Failed(3));
+        assert_eq!(report.message, "LDA\nFailed #3");
+    }
+
+    #[test]
+    fn ignores_the_status_without_the_signature() {
+        let report = run_test_rom(rom(&[(STATUS_ADDR, 0)]), 5).unwrap();
+        assert_eq!(report.outcome, TestOutcome::TimedOut);
+        assert_eq!(report.frames, 5);
+    }
+}
@@ -1,37 +1,94 @@
+use crate::mapper::{Mapper, Mirroring};
 use crate::nes::Powerable;
 
-const MEMORY_SIZE: usize = 0xFFFF - 0x4020;
+const FOUR_SCREEN_VRAM_SIZE: usize = 2 * 1024;
 
+/// The cartridge slot, empty until a ROM gets loaded
 #[derive(Default)]
 pub struct Cartridge {
-    memory: Vec<u8>,
+    mapper: Option<Box<dyn Mapper>>,
+    /// The extra nametable memory of four-screen boards, for the third and fourth nametables
+    vram: Vec<u8>,
 }
 
 impl Cartridge {
-    pub fn load_at(&mut self, address: u16, mem: Vec<u8>) {
-        self.memory
-            .splice(address as usize..MEMORY_SIZE, mem.iter().cloned());
+    pub fn insert(&mut self, mapper: Box<dyn Mapper>) {
+        self.vram = match mapper.mirroring() {
+            Mirroring::FourScreen => vec![0; FOUR_SCREEN_VRAM_SIZE],
+            _ => Vec::new(),
+        };
+        self.mapper = Some(mapper);
     }
 
-    pub fn read_mem(&self, address: u16) -> u8 {
-        *self
-            .memory
-            .get(address as usize)
-            .expect("Memory address out of range")
+    pub fn read_vram(&self, offset: usize) -> u8 {
+        self.vram.get(offset).copied().unwrap_or(0)
     }
 
-    pub fn write_mem(&mut self, address: u16, value: u8) {
-        let elem = self
-            .memory
-            .get_mut(address as usize)
-            .expect("Memory address out of range");
-        *elem = value;
+    pub fn write_vram(&mut self, offset: usize, value: u8) {
+        if let Some(elem) = self.vram.get_mut(offset) {
+            *elem = value;
+        }
+    }
+
+    /// Reads from $4020-$FFFF, `None` when the cartridge leaves the data bus floating
+    pub fn read_mem(&mut self, address: u16) -> Option<u8> {
+        self.mapper.as_mut()?.cpu_read(address)
+    }
+
+    /// Writes to $4020-$FFFF, false when the cartridge doesn't respond to the address
+    pub fn write_mem(&mut self, address: u16, value: u8) -> bool {
+        self.mapper
+            .as_mut()
+            .is_some_and(|mapper| mapper.cpu_write(address, value))
+    }
+
+    /// Reads from the pattern tables at $0000-$1FFF of the PPU address space
+    pub fn ppu_read(&mut self, address: u16) -> u8 {
+        match self.mapper.as_mut() {
+            Some(mapper) => mapper.ppu_read(address & 0x1FFF),
+            None => 0,
+        }
+    }
+
+    pub fn ppu_write(&mut self, address: u16, value: u8) {
+        if let Some(mapper) = self.mapper.as_mut() {
+            mapper.ppu_write(address & 0x1FFF, value);
+        }
+    }
+
+    pub fn notify_ppu_address(&mut self, address: u16) {
+        if let Some(mapper) = self.mapper.as_mut() {
+            mapper.notify_ppu_address(address);
+        }
+    }
+
+    pub fn cpu_tick(&mut self) {
+        if let Some(mapper) = self.mapper.as_mut() {
+            mapper.cpu_tick();
+        }
+    }
+
+    pub fn mirroring(&self) -> Mirroring {
+        self.mapper
+            .as_ref()
+            .map_or(Mirroring::Horizontal, |mapper| mapper.mirroring())
+    }
+
+    /// Level of the cartridge's IRQ output
+    pub fn irq(&self) -> bool {
+        self.mapper.as_ref().is_some_and(|mapper| mapper.irq())
+    }
+
+    pub fn save_ram(&self) -> Option<&[u8]> {
+        self.mapper.as_ref()?.save_ram()
+    }
+
+    pub fn save_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.mapper.as_mut()?.save_ram_mut()
     }
 }
 
 impl Powerable for Cartridge {
-    fn power_on(&mut self) {
-        self.memory = vec![0; MEMORY_SIZE];
-    }
+    fn power_on(&mut self) {}
     fn reset(&mut self) {}
 }
@@ -0,0 +1,81 @@
+/// How many master clock cycles a CPU cycle and a PPU dot take
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockDividers {
+    pub cpu: u64,
+    pub ppu: u64,
+}
+
+/// 21.477272 MHz master clock, 3 dots per CPU cycle
+pub const NTSC_DIVIDERS: ClockDividers = ClockDividers { cpu: 12, ppu: 4 };
+/// 26.601712 MHz master clock, 3.2 dots per CPU cycle
+pub const PAL_DIVIDERS: ClockDividers = ClockDividers { cpu: 16, ppu: 5 };
+/// Same master clock as PAL, but 3 dots per CPU cycle like NTSC
+pub const DENDY_DIVIDERS: ClockDividers = ClockDividers { cpu: 15, ppu: 5 };
+
+/// Keeps the CPU and the PPU in step. The CPU drives the clock one cycle at a time and the PPU
+/// then runs the dots it owes, which adds up to 3 or 3.2 per CPU cycle depending on the region.
+#[derive(Debug, Clone)]
+pub struct MasterClock {
+    dividers: ClockDividers,
+    /// Master cycles elapsed up to the start of the current CPU cycle
+    cycle: u64,
+    /// Master cycle the PPU has been run up to
+    ppu_cycle: u64,
+    /// How many master cycles the PPU is ahead of the CPU, less than a CPU cycle. Consoles power
+    /// on with different alignments, which a few test ROMs can detect.
+    ppu_phase: u64,
+}
+
+impl Default for MasterClock {
+    fn default() -> Self {
+        MasterClock {
+            dividers: NTSC_DIVIDERS,
+            cycle: 0,
+            ppu_cycle: 0,
+            ppu_phase: 0,
+        }
+    }
+}
+
+impl MasterClock {
+    pub fn dividers(&self) -> ClockDividers {
+        self.dividers
+    }
+
+    pub fn set_dividers(&mut self, dividers: ClockDividers) {
+        self.dividers = dividers;
+        self.ppu_phase %= dividers.cpu;
+    }
+
+    pub fn ppu_phase(&self) -> u64 {
+        self.ppu_phase
+    }
+
+    /// Sets the CPU-PPU alignment, wrapped to less than a CPU cycle
+    pub fn set_ppu_phase(&mut self, ppu_phase: u64) {
+        self.ppu_phase = ppu_phase % self.dividers.cpu;
+    }
+
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Moves on to the next CPU cycle
+    pub fn advance_cpu(&mut self) {
+        self.cycle += self.dividers.cpu;
+    }
+
+    /// Returns how many dots the PPU has to run to catch up with the CPU, and counts them as run
+    pub fn take_ppu_dots(&mut self) -> u64 {
+        let target = self.cycle + self.ppu_phase;
+        let dots = target.saturating_sub(self.ppu_cycle) / self.dividers.ppu;
+        self.ppu_cycle += dots * self.dividers.ppu;
+        dots
+    }
+
+    /// Restarts counting, keeping the dividers and the alignment
+    pub fn restart(&mut self) {
+        self.cycle = 0;
+        self.ppu_cycle = 0;
+    }
+}
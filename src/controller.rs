@@ -0,0 +1,171 @@
+use bitfield_struct::bitfield;
+
+/// Buttons of a standard controller, in the order they're reported
+#[bitfield(u8)]
+pub struct ButtonState {
+    pub a: bool,
+    pub b: bool,
+    pub select: bool,
+    pub start: bool,
+    pub up: bool,
+    pub down: bool,
+    pub left: bool,
+    pub right: bool,
+}
+
+/// The controller ports, read through $4016 and $4017
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Port {
+    One,
+    Two,
+}
+
+/// A device plugged into one of the controller ports
+pub trait Controller {
+    /// Level of the OUT0 line, bit 0 of writes to $4016
+    fn write_strobe(&mut self, strobe: bool);
+
+    /// Bits 0-4 of reads from $4016/$4017
+    fn read(&mut self) -> u8;
+
+    /// For devices with buttons
+    fn set_buttons(&mut self, _buttons: ButtonState) {}
+}
+
+/// The standard controller, a shift register loaded with the buttons while strobe is high
+#[derive(Default)]
+pub struct Joypad {
+    buttons: ButtonState,
+    strobe: bool,
+    shift_register: u8,
+}
+
+impl Controller for Joypad {
+    fn write_strobe(&mut self, strobe: bool) {
+        self.strobe = strobe;
+        if strobe {
+            self.shift_register = self.buttons.into_bits();
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        if self.strobe {
+            return self.buttons.a() as u8;
+        }
+        let bit = self.shift_register & 1;
+        // Official controllers return 1 after the 8 buttons
+        self.shift_register = (self.shift_register >> 1) | 0x80;
+        bit
+    }
+
+    fn set_buttons(&mut self, buttons: ButtonState) {
+        self.buttons = buttons;
+        if self.strobe {
+            self.shift_register = buttons.into_bits();
+        }
+    }
+}
+
+/// One of the two controller ports, with a joypad plugged in by default
+pub struct ControllerPort {
+    controller: Option<Box<dyn Controller>>,
+}
+
+impl Default for ControllerPort {
+    fn default() -> Self {
+        ControllerPort {
+            controller: Some(Box::new(Joypad::default())),
+        }
+    }
+}
+
+impl ControllerPort {
+    pub fn plug(&mut self, controller: Option<Box<dyn Controller>>) {
+        self.controller = controller;
+    }
+
+    pub fn controller_mut(&mut self) -> Option<&mut (dyn Controller + 'static)> {
+        self.controller.as_deref_mut()
+    }
+
+    pub fn write_strobe(&mut self, strobe: bool) {
+        if let Some(controller) = self.controller.as_mut() {
+            controller.write_strobe(strobe);
+        }
+    }
+
+    /// Only the low 5 bits are driven, the rest is open bus
+    pub fn read(&mut self, open_bus: u8) -> u8 {
+        let data = match self.controller.as_mut() {
+            Some(controller) => controller.read() & 0x1F,
+            None => 0,
+        };
+        (open_bus & 0xE0) | data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn joypad(buttons: ButtonState) -> Joypad {
+        let mut joypad = Joypad::default();
+        joypad.set_buttons(buttons);
+        joypad
+    }
+
+    fn read_bits(joypad: &mut Joypad, count: usize) -> Vec<u8> {
+        (0..count).map(|_| joypad.read()).collect()
+    }
+
+    #[test]
+    fn reloads_the_buttons_while_strobe_is_high() {
+        let mut joypad = joypad(ButtonState::new().with_a(true));
+        joypad.write_strobe(true);
+        // Every read returns A
+        assert_eq!(read_bits(&mut joypad, 3), [1, 1, 1]);
+        joypad.set_buttons(ButtonState::new());
+        assert_eq!(joypad.read(), 0);
+        // The state at the falling edge is what gets shifted out
+        joypad.set_buttons(ButtonState::new().with_b(true));
+        joypad.write_strobe(false);
+        joypad.set_buttons(ButtonState::new());
+        assert_eq!(read_bits(&mut joypad, 2), [0, 1]);
+    }
+
+    #[test]
+    fn shifts_out_the_buttons_in_order() {
+        let buttons = ButtonState::new()
+            .with_a(true)
+            .with_start(true)
+            .with_up(true)
+            .with_right(true);
+        let mut joypad = joypad(buttons);
+        joypad.write_strobe(true);
+        joypad.write_strobe(false);
+        assert_eq!(read_bits(&mut joypad, 8), [1, 0, 0, 1, 1, 0, 0, 1]);
+    }
+
+    #[test]
+    fn reads_1_after_the_eighth_button() {
+        let mut joypad = joypad(ButtonState::new());
+        joypad.write_strobe(true);
+        joypad.write_strobe(false);
+        assert_eq!(read_bits(&mut joypad, 8), [0; 8]);
+        assert_eq!(read_bits(&mut joypad, 4), [1; 4]);
+    }
+
+    #[test]
+    fn leaves_the_upper_bits_to_open_bus() {
+        let mut port = ControllerPort::default();
+        port.controller_mut()
+            .unwrap()
+            .set_buttons(ButtonState::new().with_a(true));
+        port.write_strobe(true);
+        assert_eq!(port.read(0x40), 0x41);
+        assert_eq!(port.read(0xFF), 0xE1);
+        // An empty port only has open bus
+        port.plug(None);
+        assert_eq!(port.read(0x40), 0x40);
+    }
+}
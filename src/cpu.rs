@@ -1,17 +1,87 @@
-use crate::instructions::addressing::MemoryOp;
-use crate::instructions::{
-    addressing, get_addr_mode, get_inst_type, get_num_of_operands, logic, AddressingMode,
-    Instruction, InstructionType,
-};
+use crate::instructions::addressing::dummy_read_pc;
+use crate::instructions::logic;
+use crate::instructions::opcodes::{Opcode, OPCODES};
 use crate::interconnect::Interconnect;
 use crate::nes::Powerable;
-use crate::utils::{build_u16, get_lsb, get_msb};
 use bitfield_struct::bitfield;
-use std::collections::VecDeque;
 
+pub const NMI_VECTOR_ADDR: u16 = 0xFFFA;
 pub const RESET_VECTOR_ADDR: u16 = 0xFFFC;
 pub const IRQ_VECTOR_ADDR: u16 = 0xFFFE;
 
+// The reset sequence behaves like an interrupt whose stack writes are turned into reads
+const RESET: &[MicroOp] = &[
+    dummy_read_pc,
+    dummy_read_pc,
+    reset_stack,
+    reset_stack,
+    reset_stack,
+    reset_idle,
+    reset_vector,
+];
+// The first cycle of an interrupt is the discarded opcode fetch
+const INTERRUPT: &[MicroOp] = &[
+    dummy_read_pc,
+    logic::brk_1,
+    logic::brk_2,
+    logic::interrupt_3,
+    logic::brk_4,
+    logic::brk_5,
+];
+
+fn reset_stack(cpu: &mut CPU, bus: &mut Interconnect) {
+    // The pushes of PC and P are suppressed, but the stack pointer still moves
+    bus.read_mem(0x100 + cpu.reg_s as u16);
+    cpu.reg_s = cpu.reg_s.wrapping_sub(1);
+}
+
+fn reset_idle(_cpu: &mut CPU, _bus: &mut Interconnect) {}
+
+fn reset_vector(cpu: &mut CPU, bus: &mut Interconnect) {
+    cpu.reg_pc = match cpu.start_pc {
+        Some(start_pc) => start_pc,
+        None => bus.read_mem_word(RESET_VECTOR_ADDR),
+    };
+}
+
+/// Devices that can pull the shared IRQ line low. The line stays asserted as long as any
+/// of them is set.
+#[bitfield(u8)]
+pub struct IrqSources {
+    #[bits(1)]
+    pub frame_counter: bool,
+    #[bits(1)]
+    pub dmc: bool,
+    #[bits(1)]
+    pub mapper: bool,
+    #[bits(1)]
+    pub external: bool,
+    #[bits(4)]
+    __: u8,
+}
+
+/// What the CPU does when it runs into one of the KIL/JAM opcodes
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum JamPolicy {
+    /// Lock up like the real CPU does, only a reset gets it going again
+    #[default]
+    Halt,
+    /// Treat the opcode as a one byte NOP
+    Skip,
+}
+
+/// The opcode that locked up the CPU and where it was fetched from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Jam {
+    pub opcode: u8,
+    pub pc: u16,
+}
+
+/// A single cycle of an instruction, performing exactly one bus access
+pub type MicroOp = fn(&mut CPU, &mut Interconnect);
+/// The part of an instruction that works on the registers and `CPU::value`
+pub type Operation = fn(&mut CPU);
+
 #[bitfield(u8)]
 pub struct Status {
     #[bits(1)]
@@ -35,16 +105,26 @@ pub struct Status {
 #[derive(Default)]
 pub struct CPU {
     pub cycle: u64,
-    cycle_debug: u64,
-    curr_inst_byte: Option<u8>,
-    pub curr_inst: Option<Instruction>,
-    pub operands: Vec<u8>,
-    pub num_operands: usize,
+    /// The opcode of the instruction in progress
+    pub opcode: u8,
+    program: &'static [MicroOp],
+    step: usize,
     pub value: u8,
     pub addr: u16,
-    pub write: u8,
-    pub inst_queue: VecDeque<(fn(&mut CPU), u32)>,
-    printed: bool,
+    pub pointer: u8,
+    pub page_crossed: bool,
+    start_pc: Option<u16>,
+
+    pub interrupt_vector: u16,
+    nmi_line: bool,
+    nmi_pending: bool,
+    prev_nmi_pending: bool,
+    irq_sources: IrqSources,
+    run_irq: bool,
+    prev_run_irq: bool,
+
+    jam_policy: JamPolicy,
+    jam: Option<Jam>,
 
     pub reg_a: u8,
     pub reg_x: u8,
@@ -52,374 +132,178 @@ pub struct CPU {
     pub reg_pc: u16,
     pub reg_s: u8,
     pub status: Status,
-
-    pub ic: Interconnect,
 }
 
 impl CPU {
-    pub fn load_rom(&mut self, ines: Vec<u8>) {
-        self.ic.load_rom(ines)
+    /// Overrides the address execution starts at once the reset sequence is done, instead of
+    /// the one stored in the reset vector. Used for automation runs like nestest's $C000.
+    pub fn set_start_pc(&mut self, start_pc: Option<u16>) {
+        self.start_pc = start_pc;
     }
 
-    pub fn run(&mut self) {
-        for _ in 0..2000 {
-            self.do_cycle();
-        }
+    pub fn set_jam_policy(&mut self, jam_policy: JamPolicy) {
+        self.jam_policy = jam_policy;
+    }
+
+    pub fn jam_policy(&self) -> JamPolicy {
+        self.jam_policy
+    }
+
+    /// Locks up the CPU on the current KIL opcode
+    pub fn halt(&mut self) {
+        let jam = Jam {
+            opcode: self.opcode,
+            pc: self.reg_pc.wrapping_sub(1),
+        };
+        self.jam = Some(jam);
+    }
+
+    /// Returns the opcode that halted the CPU, if any
+    pub fn jam(&self) -> Option<Jam> {
+        self.jam
+    }
+
+    /// Drives the IRQ input of the expansion port. The other sources are sampled from the
+    /// devices on the bus every cycle.
+    pub fn set_external_irq(&mut self, asserted: bool) {
+        self.irq_sources.set_external(asserted);
+    }
+
+    pub fn irq_sources(&self) -> IrqSources {
+        self.irq_sources
+    }
+
+    /// Whether the current instruction (or interrupt or reset sequence) is finished, so the next
+    /// cycle fetches an opcode
+    pub fn instruction_done(&self) -> bool {
+        self.step >= self.program.len()
     }
 
-    fn do_cycle(&mut self) {
-        if self.cycle > 0 {
-            self.fetch();
-            let only_free = self.decode();
-            self.execute(only_free);
+    /// Runs one cycle of the current instruction, then clocks the rest of the system on `bus`
+    pub fn do_cycle(&mut self, bus: &mut Interconnect) {
+        if self.jam.is_some() {
+            // Stuck until the next reset
+        } else if let Some(&micro_op) = self.program.get(self.step) {
+            self.step += 1;
+            micro_op(self, bus);
         } else {
-            //self.reg_pc = self.ic.read_mem_word(self.reg_pc); // jump to start of code
-            self.reg_pc = 0xC000;
-            println!("Started execution at {:#02X}", self.reg_pc);
-            self.cycle = 6;
+            self.fetch(bus);
         }
+        bus.tick();
+        self.poll_interrupts(bus);
 
-        self.cycle += 1;
+        // Cycles the CPU spent halted by DMA during this one
+        self.cycle += 1 + bus.take_dma_cycles();
     }
 
-    pub fn push_to_stack(&mut self, value: u8) {
-        self.ic.write_mem(0x100 + self.reg_s as u16, value);
-        self.reg_s -= 1;
+    fn poll_interrupts(&mut self, bus: &mut Interconnect) {
+        // NMI is edge sensitive, the detector latches a low to high transition of the line
+        let nmi_line = bus.nmi_line();
+        self.prev_nmi_pending = self.nmi_pending;
+        if nmi_line && !self.nmi_line {
+            self.nmi_pending = true;
+        }
+        self.nmi_line = nmi_line;
+
+        // IRQ is level sensitive, it's only taken if it's still asserted when polled
+        let external = self.irq_sources.external();
+        self.irq_sources = bus.irq_sources().with_external(external);
+        self.prev_run_irq = self.run_irq;
+        self.run_irq = self.irq_sources.into_bits() != 0 && !self.status.interrupt_disable();
     }
 
-    pub fn pull_from_stack(&mut self) -> u8 {
-        self.reg_s += 1;
-        self.ic.read_mem(0x100 + self.reg_s as u16)
+    /// Interrupts are polled at the end of the second to last cycle of an instruction. This is
+    /// why CLI, SEI and PLP only affect IRQs after the following instruction.
+    fn interrupt_requested(&self) -> bool {
+        self.prev_nmi_pending || self.prev_run_irq
     }
 
-    fn fetch(&mut self) {
-        if self.inst_queue.len() > 0 {
-            return;
+    /// A taken branch that doesn't cross a page doesn't poll for interrupts in its last cycle,
+    /// so an IRQ that just showed up has to wait for one more instruction.
+    pub fn ignore_new_irq(&mut self) {
+        if self.run_irq && !self.prev_run_irq {
+            self.run_irq = false;
         }
-        let byte = self.ic.read_mem(self.reg_pc);
-        if self.curr_inst.is_some() && self.operands.len() < self.num_operands {
-            self.operands.push(byte);
-            self.reg_pc += 1;
+    }
+
+    /// Picks the vector of an interrupt sequence at the cycle where P gets pushed. An NMI
+    /// that gets detected before this point hijacks a running BRK or IRQ.
+    pub fn select_interrupt_vector(&mut self) {
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            self.prev_nmi_pending = false;
+            self.interrupt_vector = NMI_VECTOR_ADDR;
         } else {
-            self.curr_inst_byte = Some(byte);
-            self.cycle_debug = self.cycle;
-            self.reg_pc += 1;
+            self.interrupt_vector = IRQ_VECTOR_ADDR;
         }
     }
 
-    fn decode(&mut self) -> bool {
-        let Some(inst_byte) = self.curr_inst_byte else {
-            return false;
-        };
+    fn begin_reset_sequence(&mut self) {
+        self.run_program(RESET);
+        self.cycle = 0;
 
-        if self.operands.len() == 0 {
-            let inst_type = get_inst_type(inst_byte);
-            let addr_mode = get_addr_mode(inst_byte);
-            let inst = Instruction {
-                inst_type: inst_type,
-                addr_mode: addr_mode,
-            };
-            self.num_operands = get_num_of_operands(&inst.addr_mode);
-            self.curr_inst = Some(inst);
-            if self.num_operands > 0 {
-                return false;
-            }
-        }
+        self.jam = None;
+        self.nmi_pending = false;
+        self.prev_nmi_pending = false;
+        self.run_irq = false;
+        self.prev_run_irq = false;
+    }
 
-        if self.operands.len() < self.num_operands {
-            return false;
-        }
+    fn run_program(&mut self, program: &'static [MicroOp]) {
+        self.program = program;
+        self.step = 0;
+    }
 
-        self.curr_inst_byte = None;
-
-        match self.curr_inst.as_mut().unwrap().inst_type {
-            InstructionType::Illegal => {
-                //println!("Illegal instruction {:?}", inst);
-            }
-            InstructionType::ADC => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::adc_1, 0));
-            }
-            InstructionType::AND => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::and_1, 0));
-            }
-            InstructionType::ASL => {
-                self.inst_queue.push_back((logic::asl_1, 0));
-            }
-            InstructionType::BCC => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::bcc_1, 0));
-            }
-            InstructionType::BCS => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::bcs_1, 0));
-            }
-            InstructionType::BEQ => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::beq_1, 0));
-            }
-            InstructionType::BIT => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::bit_1, 0));
-            }
-            InstructionType::BMI => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::bmi_1, 0));
-            }
-            InstructionType::BNE => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::bne_1, 0));
-            }
-            InstructionType::BPL => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::bpl_1, 0));
-            }
-            InstructionType::BRK => {
-                // TODO why only 5 cycles instead of 7?
-                self.inst_queue.push_back((logic::brk_1, 1));
-                self.inst_queue.push_back((logic::brk_2, 1));
-                self.inst_queue.push_back((logic::brk_3, 1));
-                self.inst_queue.push_back((logic::brk_4, 1));
-            }
-            InstructionType::BVC => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::bvc_1, 0));
-            }
-            InstructionType::BVS => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::bvs_1, 0));
-            }
-            InstructionType::CLC => {
-                self.inst_queue.push_back((logic::clc_1, 0));
-            }
-            InstructionType::CLD => {
-                self.inst_queue.push_back((logic::cld_1, 0));
-            }
-            InstructionType::CLI => {
-                self.inst_queue.push_back((logic::cli_1, 0));
-            }
-            InstructionType::CLV => {
-                self.inst_queue.push_back((logic::clv_1, 0));
-            }
-            InstructionType::CMP => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::cmp_1, 0));
-            }
-            InstructionType::CPX => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::cpx_1, 0));
-            }
-            InstructionType::CPY => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::cpy_1, 0));
-            }
-            InstructionType::DEC => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::dec_1, 1));
-                self.inst_queue.push_back((logic::dec_2, 1));
-            }
-            InstructionType::DEX => {
-                self.inst_queue.push_back((logic::dex_1, 0));
-            }
-            InstructionType::DEY => {
-                self.inst_queue.push_back((logic::dey_1, 0));
-            }
-            InstructionType::EOR => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::eor_1, 0));
-            }
-            InstructionType::INC => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::inc_1, 1));
-                self.inst_queue.push_back((logic::inc_2, 1));
-            }
-            InstructionType::INX => {
-                self.inst_queue.push_back((logic::inx_1, 0));
-            }
-            InstructionType::INY => {
-                self.inst_queue.push_back((logic::iny_1, 0));
-            }
-            InstructionType::JMP => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::jmp_1, 0));
-            }
-            InstructionType::JSR => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::jsr_1, 1));
-                self.inst_queue.push_back((logic::jsr_2, 1));
-            }
-            InstructionType::LDA => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::lda_1, 0));
-            }
-            InstructionType::LDX => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::ldx_1, 0));
-            }
-            InstructionType::LDY => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::ldy_1, 0));
-            }
-            InstructionType::LSR => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::lsr_1, 0));
-            }
-            InstructionType::NOP => {
-                // this already gets handled by the fact that the instruction has no operands
-            }
-            InstructionType::ORA => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::ora_1, 0));
-            }
-            InstructionType::PHA => {
-                self.inst_queue.push_back((logic::pha_1, 1));
-            }
-            InstructionType::PHP => {
-                self.inst_queue.push_back((logic::php_1, 1));
-            }
-            InstructionType::PLA => {
-                self.inst_queue.push_back((logic::pla_1, 1));
-                self.inst_queue.push_back((logic::nop, 1));
-            }
-            InstructionType::PLP => {
-                self.inst_queue.push_back((logic::plp_1, 1));
-                self.inst_queue.push_back((logic::nop, 1));
-            }
-            InstructionType::ROL => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::rol_1, 0));
-            }
-            InstructionType::ROR => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::ror_1, 0));
-            }
-            InstructionType::RTI => {
-                self.inst_queue.push_back((logic::rti_1, 1));
-                self.inst_queue.push_back((logic::rti_2, 1));
-                self.inst_queue.push_back((logic::rti_3, 1));
-            }
-            InstructionType::RTS => {
-                self.inst_queue.push_back((logic::rts_1, 1));
-                self.inst_queue.push_back((logic::nop, 1));
-                self.inst_queue.push_back((logic::rts_2, 1));
-                self.inst_queue.push_back((logic::nop, 1));
-            }
-            InstructionType::SBC => {
-                addressing::queue_push_memory_op(self, MemoryOp::Read);
-                self.inst_queue.push_back((logic::sbc_1, 0));
-            }
-            InstructionType::SEC => {
-                self.inst_queue.push_back((logic::sec_1, 0));
-            }
-            InstructionType::SED => {
-                self.inst_queue.push_back((logic::sed_1, 0));
-            }
-            InstructionType::SEI => {
-                self.inst_queue.push_back((logic::sei_1, 0));
-            }
-            InstructionType::STA => {
-                self.write = self.reg_a;
-                addressing::queue_push_memory_op(self, MemoryOp::Write);
-            }
-            InstructionType::STX => {
-                self.write = self.reg_x;
-                addressing::queue_push_memory_op(self, MemoryOp::Write);
-            }
-            InstructionType::STY => {
-                self.write = self.reg_y;
-                addressing::queue_push_memory_op(self, MemoryOp::Write);
-            }
-            InstructionType::TAX => {
-                self.inst_queue.push_back((logic::tax_1, 0));
-            }
-            InstructionType::TAY => {
-                self.inst_queue.push_back((logic::tay_1, 0));
-            }
-            InstructionType::TSX => {
-                self.inst_queue.push_back((logic::tsx_1, 0));
-            }
-            InstructionType::TXA => {
-                self.inst_queue.push_back((logic::txa_1, 0));
-            }
-            InstructionType::TXS => {
-                self.inst_queue.push_back((logic::txs_1, 0));
-            }
-            InstructionType::TYA => {
-                self.inst_queue.push_back((logic::tya_1, 0));
-            }
-        }
-        if self.num_operands == 0 {
-            self.inst_queue.push_back((logic::nop, 1));
-        }
-        true
+    /// Skips the remaining cycles of the current instruction
+    pub fn end_instruction(&mut self) {
+        self.step = self.program.len();
     }
 
-    fn execute(&mut self, only_free: bool) {
-        if self.operands.len() < self.num_operands {
-            return;
-        }
-        if !self.printed {
-            println!(
-                "{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
-                self.reg_pc - 1 - self.num_operands as u16,
-                self.reg_a,
-                self.reg_x,
-                self.reg_y,
-                self.status.into_bits(),
-                self.reg_s,
-                self.cycle_debug
-            );
-            self.printed = true;
-
-            // println!(
-            // "{:04X} {:?} val: {:02X} addr: {:02X}                A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
-            // self.reg_pc - 1 - self.num_operands as u16,
-            // self.curr_inst.as_mut().unwrap(),
-            // self.value,
-            // self.addr,
-            // self.reg_a,
-            // self.reg_x,
-            // self.reg_y,
-            // self.status.into_bits(),
-            // self.reg_s,
-            // self.cycle_debug
-            // );
-        }
-        let mut total_cost = 0;
-        let max = match only_free {
-            true => 1,
-            false => 2,
-        };
-        while self.inst_queue.front().is_some()
-            && total_cost + self.inst_queue.front().unwrap().1 < max
-        {
-            let (func, cost) = self
-                .inst_queue
-                .pop_front()
-                .expect("Queue empty after fetch");
-            func(self);
-            total_cost += cost;
-        }
+    pub fn push_to_stack(&mut self, bus: &mut Interconnect, value: u8) {
+        bus.write_mem(0x100 + self.reg_s as u16, value);
+        self.reg_s = self.reg_s.wrapping_sub(1);
+    }
+
+    pub fn pull_from_stack(&mut self, bus: &mut Interconnect) -> u8 {
+        self.reg_s = self.reg_s.wrapping_add(1);
+        bus.read_mem(0x100 + self.reg_s as u16)
+    }
 
-        if self.inst_queue.len() == 0 {
-            self.printed = false;
-            self.curr_inst = None;
-            self.operands.clear();
+    /// First cycle of every instruction
+    fn fetch(&mut self, bus: &mut Interconnect) {
+        let interrupt = self.interrupt_requested();
+        if !interrupt {
+            bus.trace_instruction(self);
+        }
+        let byte = bus.read_mem(self.reg_pc);
+        if interrupt {
+            // The fetched opcode is thrown away and the interrupt sequence runs instead
+            self.run_program(INTERRUPT);
             return;
         }
+        self.reg_pc = self.reg_pc.wrapping_add(1);
+        self.opcode = byte;
+        self.run_program(self.current_opcode().program);
+    }
+
+    pub fn current_opcode(&self) -> &'static Opcode {
+        &OPCODES[self.opcode as usize]
+    }
+
+    /// Runs the addressing mode independent part of the current instruction
+    pub fn execute(&mut self) {
+        (self.current_opcode().operation)(self);
     }
 }
 
 impl Powerable for CPU {
     fn power_on(&mut self) {
-        self.ic.power_on();
-
         self.reg_a = 0;
         self.reg_x = 0;
         self.reg_y = 0;
         self.reg_pc = RESET_VECTOR_ADDR;
-        self.reg_s = 0xFD;
+        // The reset sequence decrements this to $FD
+        self.reg_s = 0;
         self.status.set_carry(false);
         self.status.set_zero(false);
         self.status.set_interrupt_disable(true);
@@ -428,19 +312,129 @@ impl Powerable for CPU {
         self.status.set_overflow(false);
         self.status.set_negative(false);
 
-        self.operands = vec![];
-        self.inst_queue = VecDeque::new();
-        self.cycle = 0;
+        self.begin_reset_sequence();
     }
     fn reset(&mut self) {
-        self.ic.reset();
-
-        self.reg_pc = RESET_VECTOR_ADDR;
-        self.reg_s -= 3;
         self.status.set_interrupt_disable(true);
 
-        self.operands = vec![];
-        self.inst_queue = VecDeque::new();
-        self.cycle = 0;
+        self.begin_reset_sequence();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom::TestRom;
+
+    /// Powers on with `program` at $8000. The NMI handler at $9000 counts in X and returns, the
+    /// IRQ/BRK handler at $A000 counts in Y and then loops with I set.
+    fn boot(program: &[u8]) -> (CPU, Interconnect) {
+        let rom = TestRom::new(2)
+            .code(0x8000, program)
+            .nmi_vector(0x9000)
+            .code(0x9000, &[0xE8, 0x40])
+            .irq_vector(0xA000)
+            .code(0xA000, &[0xC8, 0x4C, 0x01, 0xA0])
+            .build();
+        let mut bus = Interconnect::default();
+        bus.load_rom(rom).unwrap();
+        bus.power_on();
+        let mut cpu = CPU::default();
+        cpu.power_on();
+        while !cpu.instruction_done() {
+            cpu.do_cycle(&mut bus);
+        }
+        (cpu, bus)
+    }
+
+    fn step_instruction(cpu: &mut CPU, bus: &mut Interconnect) {
+        cpu.do_cycle(bus);
+        while !cpu.instruction_done() {
+            cpu.do_cycle(bus);
+        }
+    }
+
+    /// Runs the rest of the system up to vblank, leaving NMIs disabled so the tests can raise
+    /// the line with $2000
+    fn wait_for_vblank(bus: &mut Interconnect) {
+        bus.write_mem(0x2000, 0x80);
+        while !bus.nmi_line() {
+            bus.tick();
+        }
+        bus.write_mem(0x2000, 0x00);
+    }
+
+    #[test]
+    fn takes_a_latched_nmi_once() {
+        let (mut cpu, mut bus) = boot(&[0x4C, 0x00, 0x80]);
+        wait_for_vblank(&mut bus);
+        // The line goes high and low again in the middle of the JMP
+        cpu.do_cycle(&mut bus);
+        bus.write_mem(0x2000, 0x80);
+        cpu.do_cycle(&mut bus);
+        bus.write_mem(0x2000, 0x00);
+        for _ in 0..20 {
+            step_instruction(&mut cpu, &mut bus);
+        }
+        assert_eq!(cpu.reg_x, 1);
+    }
+
+    #[test]
+    fn keeps_irq_asserted_while_any_source_holds_it() {
+        // CLI, then loop
+        let (mut cpu, mut bus) = boot(&[0x58, 0x4C, 0x01, 0x80]);
+        // I is still set from the reset
+        while !bus.irq_sources().frame_counter() {
+            bus.tick();
+        }
+        // The flag gets set again on the two cycles after
+        bus.tick();
+        bus.tick();
+        cpu.set_external_irq(true);
+        // Acknowledging the frame counter IRQ leaves the external one
+        bus.read_mem(0x4015);
+        for _ in 0..4 {
+            step_instruction(&mut cpu, &mut bus);
+        }
+        assert_eq!(
+            cpu.irq_sources().into_bits(),
+            IrqSources::new().with_external(true).into_bits()
+        );
+        assert_eq!(cpu.reg_y, 1);
+    }
+
+    #[test]
+    fn nmi_hijacks_brk() {
+        let (mut cpu, mut bus) = boot(&[0x00]);
+        wait_for_vblank(&mut bus);
+        // Opcode and padding byte
+        cpu.do_cycle(&mut bus);
+        cpu.do_cycle(&mut bus);
+        bus.write_mem(0x2000, 0x80);
+        while !cpu.instruction_done() {
+            cpu.do_cycle(&mut bus);
+        }
+        assert_eq!(cpu.reg_pc, 0x9000);
+        // The pushed P still comes from a BRK
+        assert_eq!(bus.peek(0x100 + cpu.reg_s as u16 + 1) & 0x10, 0x10);
+
+        // INX, RTI, then the NOP after the padding byte without another NMI
+        for _ in 0..3 {
+            step_instruction(&mut cpu, &mut bus);
+        }
+        assert_eq!(cpu.reg_pc, 0x8003);
+        assert_eq!(cpu.reg_x, 1);
+        assert_eq!(cpu.reg_y, 0);
+    }
+
+    #[test]
+    fn takes_irq_one_instruction_after_cli() {
+        let (mut cpu, mut bus) = boot(&[0x58, 0xEA, 0xEA]);
+        cpu.set_external_irq(true);
+        step_instruction(&mut cpu, &mut bus);
+        step_instruction(&mut cpu, &mut bus);
+        assert_eq!(cpu.reg_pc, 0x8002);
+        step_instruction(&mut cpu, &mut bus);
+        assert_eq!(cpu.reg_pc, 0xA000);
     }
 }
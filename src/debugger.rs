@@ -0,0 +1,763 @@
+//! An interactive debugger on top of `NES`, driven by commands read line by line, see `HELP`.
+//!
+//! Watchpoints see every access on the CPU bus through a tracer, including the PPU and APU
+//! registers, mapper registers and dummy reads. Like breakpoints, they stop execution at the end
+//! of the instruction that triggered them.
+
+mod condition;
+
+use std::cell::RefCell;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+use crate::cpu::{Status, CPU};
+use crate::disasm::{find_start_before, parse_address, Instruction, Symbols};
+use crate::error::NesError;
+use crate::instructions::InstructionType;
+use crate::interconnect::Interconnect;
+use crate::nes::{Powerable, StopReason, NES};
+use crate::trace::{AccessKind, Tracer};
+
+use condition::Condition;
+
+const PROMPT: &str = "(nesty) ";
+const DEFAULT_DUMP_LENGTH: u16 = 64;
+const DISASSEMBLY_BEFORE: usize = 4;
+const DISASSEMBLY_LINES: usize = 12;
+
+pub const HELP: &str = "\
+Addresses and values are hexadecimal, with or without a $, or symbol names. Counts are decimal.
+An empty line repeats the last command.
+
+  step [n]            s  Run n instructions, 1 by default, into subroutines and interrupts
+  next                n  Run one instruction, or a whole subroutine call
+  finish              f  Run until the current subroutine or interrupt handler returns
+  continue [frames]   c  Run until something stops execution, or for a number of frames
+  break <addr> [if <condition>]
+                      b  Stop before executing the instruction at addr
+  watch <r|w|rw|x> <addr>[-<end>] [if <condition>]
+                      w  Stop after an instruction reads, writes or executes an address
+  breaks              bl List breakpoints and watchpoints
+  delete [id]         d  Delete a breakpoint or watchpoint, or all of them
+  regs                r  Show the registers
+  set <reg> <value>      Change A, X, Y, S, P or PC
+  mem <addr> [length] m  Show memory, PPU and I/O registers read as FF
+  poke <addr> <value>... Write memory like the CPU would, registers included
+  disasm [addr] [n]   l  Disassemble n instructions at addr, or around PC
+  reset                  Press the reset button
+  help                h  Show this
+  quit                q  Leave the debugger
+
+Conditions compare registers (A, X, Y, S, P, PC) and flags (N, V, D, I, Z, C) with
+==, !=, <, <=, > and >=, combined with && and ||, e.g. \"A == 0 && (C || X >= 80)\".
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+    Execute,
+}
+
+impl WatchKind {
+    fn parse(text: &str) -> Option<WatchKind> {
+        match text {
+            "r" => Some(WatchKind::Read),
+            "w" => Some(WatchKind::Write),
+            "rw" => Some(WatchKind::ReadWrite),
+            "x" => Some(WatchKind::Execute),
+            _ => None,
+        }
+    }
+
+    fn matches(self, kind: AccessKind) -> bool {
+        matches!(
+            (self, kind),
+            (WatchKind::Read | WatchKind::ReadWrite, AccessKind::Read)
+                | (WatchKind::Write | WatchKind::ReadWrite, AccessKind::Write)
+        )
+    }
+}
+
+/// A breakpoint is a watchpoint that executes a single address
+struct Watchpoint {
+    id: usize,
+    kind: WatchKind,
+    start: u16,
+    end: u16,
+    condition: Option<Condition>,
+}
+
+impl Watchpoint {
+    fn contains(&self, address: u16) -> bool {
+        (self.start..=self.end).contains(&address)
+    }
+
+    fn condition_holds(&self, cpu: &CPU) -> bool {
+        self.condition
+            .as_ref()
+            .is_none_or(|condition| condition.holds(cpu))
+    }
+
+    fn describe(&self) -> String {
+        let mut text = match self.kind {
+            WatchKind::Execute if self.start == self.end => format!("break {:04X}", self.start),
+            kind => {
+                let kind = match kind {
+                    WatchKind::Read => "r",
+                    WatchKind::Write => "w",
+                    WatchKind::ReadWrite => "rw",
+                    WatchKind::Execute => "x",
+                };
+                match self.start == self.end {
+                    true => format!("watch {} {:04X}", kind, self.start),
+                    false => format!("watch {} {:04X}-{:04X}", kind, self.start, self.end),
+                }
+            }
+        };
+        if let Some(condition) = &self.condition {
+            write!(text, " if {}", condition).unwrap();
+        }
+        text
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Access {
+    address: u16,
+    value: u8,
+    kind: AccessKind,
+}
+
+/// Shared between the debugger and its tracer
+#[derive(Default)]
+struct Watches {
+    watchpoints: Vec<Watchpoint>,
+    /// Accesses by the instruction in progress that some watchpoint covers
+    accesses: Vec<Access>,
+    /// What stopped execution
+    hit: Option<String>,
+}
+
+impl Watches {
+    /// Checked between instructions, true if a watchpoint triggered
+    fn check(&mut self, cpu: &CPU) -> bool {
+        let accesses = std::mem::take(&mut self.accesses);
+        for access in accesses {
+            let watchpoint = self.watchpoints.iter().find(|watchpoint| {
+                watchpoint.kind.matches(access.kind)
+                    && watchpoint.contains(access.address)
+                    && watchpoint.condition_holds(cpu)
+            });
+            if let Some(watchpoint) = watchpoint {
+                self.hit = Some(match access.kind {
+                    AccessKind::Read => format!(
+                        "Watchpoint {}: read ${:02X} from {:04X}",
+                        watchpoint.id, access.value, access.address
+                    ),
+                    AccessKind::Write => format!(
+                        "Watchpoint {}: wrote ${:02X} to {:04X}",
+                        watchpoint.id, access.value, access.address
+                    ),
+                });
+                return true;
+            }
+        }
+        let breakpoint = self.watchpoints.iter().find(|watchpoint| {
+            watchpoint.kind == WatchKind::Execute
+                && watchpoint.contains(cpu.reg_pc)
+                && watchpoint.condition_holds(cpu)
+        });
+        if let Some(breakpoint) = breakpoint {
+            self.hit = Some(format!(
+                "Breakpoint {} at {:04X}",
+                breakpoint.id, cpu.reg_pc
+            ));
+            return true;
+        }
+        false
+    }
+}
+
+struct WatchTracer {
+    watches: Rc<RefCell<Watches>>,
+}
+
+impl Tracer for WatchTracer {
+    fn instruction(&mut self, _cpu: &CPU, _bus: &mut Interconnect) {}
+
+    fn bus_access(&mut self, address: u16, value: u8, kind: AccessKind) {
+        let mut watches = self.watches.borrow_mut();
+        let watched = watches
+            .watchpoints
+            .iter()
+            .any(|watchpoint| watchpoint.kind.matches(kind) && watchpoint.contains(address));
+        if watched {
+            watches.accesses.push(Access {
+                address,
+                value,
+                kind,
+            });
+        }
+    }
+}
+
+pub struct Debugger {
+    nes: NES,
+    symbols: Option<Symbols>,
+    watches: Rc<RefCell<Watches>>,
+    next_id: usize,
+    last_command: String,
+}
+
+impl Debugger {
+    /// Takes over `nes`, attaching a tracer for the watchpoints
+    pub fn new(mut nes: NES, symbols: Option<Symbols>) -> Self {
+        let watches = Rc::new(RefCell::new(Watches::default()));
+        nes.set_tracer(Some(Box::new(WatchTracer {
+            watches: watches.clone(),
+        })));
+        Debugger {
+            nes,
+            symbols,
+            watches,
+            next_id: 1,
+            last_command: String::new(),
+        }
+    }
+
+    pub fn nes(&mut self) -> &mut NES {
+        &mut self.nes
+    }
+
+    /// Reads and runs commands until `quit` or the end of `input`
+    pub fn repl(&mut self, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        writeln!(output, "{}", self.nes.trace_line())?;
+        let mut lines = input.lines();
+        loop {
+            write!(output, "{}", PROMPT)?;
+            output.flush()?;
+            let Some(line) = lines.next().transpose()? else {
+                return Ok(());
+            };
+            let line = match line.trim() {
+                "" => self.last_command.clone(),
+                line => line.to_string(),
+            };
+            if matches!(line.as_str(), "q" | "quit") {
+                return Ok(());
+            }
+            match self.command(&line) {
+                Ok(text) => write!(output, "{}", text)?,
+                Err(message) => writeln!(output, "{}", message)?,
+            }
+            self.last_command = line;
+        }
+    }
+
+    /// Runs one command and returns what it printed, or why it couldn't run
+    pub fn command(&mut self, line: &str) -> Result<String, String> {
+        let (line, condition) = match line.split_once(" if ") {
+            Some((line, condition)) => (line, Some(condition)),
+            None => (line, None),
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, args)) = words.split_first() else {
+            return Ok(String::new());
+        };
+        if condition.is_some() && !matches!(command, "b" | "break" | "w" | "watch") {
+            return Err("Only breakpoints and watchpoints take a condition".to_string());
+        }
+        match (command, args) {
+            ("s" | "step", [] | [_]) => {
+                let count = match args.first() {
+                    Some(count) => count
+                        .parse()
+                        .map_err(|_| format!("Invalid count {}", count))?,
+                    None => 1,
+                };
+                self.step(count)
+            }
+            ("n" | "next", []) => self.next(),
+            ("f" | "finish", []) => self.finish(),
+            ("c" | "continue", []) => self.run(|_| false),
+            ("c" | "continue", [frames]) => {
+                let frames: u64 = frames
+                    .parse()
+                    .map_err(|_| format!("Invalid count {}", frames))?;
+                let end = self.nes.frame().saturating_add(frames);
+                self.run(|nes| nes.frame() >= end)
+            }
+            ("b" | "break", [address]) => {
+                let address = self.address(address)?;
+                self.add_watchpoint(WatchKind::Execute, address, address, condition)
+            }
+            ("w" | "watch", [kind, range]) => {
+                let kind = WatchKind::parse(kind)
+                    .ok_or_else(|| format!("Invalid kind {}, expected r, w, rw or x", kind))?;
+                let (start, end) = match range.split_once('-') {
+                    Some((start, end)) => (self.address(start)?, self.address(end)?),
+                    None => (self.address(range)?, self.address(range)?),
+                };
+                if end < start {
+                    return Err(format!("Range {} ends before it starts", range));
+                }
+                self.add_watchpoint(kind, start, end, condition)
+            }
+            ("bl" | "breaks", []) => Ok(self.list_watchpoints()),
+            ("d" | "delete", []) => {
+                self.watches.borrow_mut().watchpoints.clear();
+                Ok(String::new())
+            }
+            ("d" | "delete", [id]) => {
+                let id: usize = id.parse().map_err(|_| format!("Invalid id {}", id))?;
+                let watchpoints = &mut self.watches.borrow_mut().watchpoints;
+                let count = watchpoints.len();
+                watchpoints.retain(|watchpoint| watchpoint.id != id);
+                match watchpoints.len() < count {
+                    true => Ok(String::new()),
+                    false => Err(format!("No breakpoint or watchpoint {}", id)),
+                }
+            }
+            ("r" | "regs", []) => Ok(self.registers()),
+            ("set", [register, value]) => {
+                let value = self.address(value)?;
+                self.set_register(register, value)?;
+                Ok(self.registers())
+            }
+            ("m" | "mem", [address] | [address, _]) => {
+                let address = self.address(address)?;
+                let length = match args.get(1) {
+                    Some(length) => length
+                        .parse()
+                        .map_err(|_| format!("Invalid length {}", length))?,
+                    None => DEFAULT_DUMP_LENGTH,
+                };
+                Ok(self.dump(address, length))
+            }
+            ("poke", [address, values @ ..]) if !values.is_empty() => {
+                let address = self.address(address)?;
+                for (i, value) in values.iter().enumerate() {
+                    let value = self.byte(value)?;
+                    self.nes
+                        .poke(address.wrapping_add(i as u16), value)
+                        .map_err(|e| e.to_string())?;
+                }
+                Ok(String::new())
+            }
+            ("l" | "disasm", []) => {
+                let pc = self.nes.cpu().reg_pc;
+                let start = find_start_before(|a| self.nes.peek(a), pc, DISASSEMBLY_BEFORE);
+                Ok(self.disassembly(start, DISASSEMBLY_LINES))
+            }
+            ("l" | "disasm", [address] | [address, _]) => {
+                let address = self.address(address)?;
+                let count = match args.get(1) {
+                    Some(count) => count
+                        .parse()
+                        .map_err(|_| format!("Invalid count {}", count))?,
+                    None => DISASSEMBLY_LINES,
+                };
+                Ok(self.disassembly(address, count))
+            }
+            ("reset", []) => {
+                self.nes.reset();
+                // Through the reset sequence, to the first instruction
+                let stop = self.nes.step_instruction();
+                self.stopped(stop)
+            }
+            ("h" | "help", []) => Ok(HELP.to_string()),
+            _ => Err(format!(
+                "Invalid command \"{}\", \"help\" lists the commands",
+                line.trim()
+            )),
+        }
+    }
+
+    fn step(&mut self, count: usize) -> Result<String, String> {
+        let mut stop = Ok(StopReason::Stepped);
+        for _ in 0..count {
+            stop = self.nes.step_instruction();
+            if !matches!(stop, Ok(StopReason::Stepped)) {
+                break;
+            }
+        }
+        self.watches.borrow_mut().accesses.clear();
+        self.stopped(stop)
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        let instruction = Instruction::read(self.nes.cpu().reg_pc, |a| self.nes.peek(a));
+        if instruction.opcode().inst_type != InstructionType::JSR {
+            return self.step(1);
+        }
+        // Recursive calls return to the same address with less on the stack
+        let (return_address, stack) = (instruction.next_address(), self.nes.cpu().reg_s);
+        self.run(|nes| nes.cpu().reg_pc == return_address && nes.cpu().reg_s == stack)
+    }
+
+    fn finish(&mut self) -> Result<String, String> {
+        let stack = self.nes.cpu().reg_s;
+        self.run(|nes| {
+            let cpu = nes.cpu();
+            cpu.reg_s > stack
+                && matches!(
+                    cpu.current_opcode().inst_type,
+                    InstructionType::RTS | InstructionType::RTI
+                )
+        })
+    }
+
+    /// Runs until `done` returns true, or something else stops execution
+    fn run(&mut self, mut done: impl FnMut(&NES) -> bool) -> Result<String, String> {
+        let watches = self.watches.clone();
+        watches.borrow_mut().accesses.clear();
+        let stop = self
+            .nes
+            .run_until(|nes| watches.borrow_mut().check(nes.cpu()) || done(nes));
+        self.stopped(stop)
+    }
+
+    /// Says why execution stopped, and where
+    fn stopped(&mut self, stop: Result<StopReason, NesError>) -> Result<String, String> {
+        let mut text = String::new();
+        match stop {
+            Ok(StopReason::Jammed(jam)) => writeln!(
+                text,
+                "CPU jammed by opcode {:02X} at {:04X}",
+                jam.opcode, jam.pc
+            ),
+            Ok(StopReason::Breakpoint(address)) => writeln!(text, "Breakpoint at {:04X}", address),
+            Ok(_) => match self.watches.borrow_mut().hit.take() {
+                Some(hit) => writeln!(text, "{}", hit),
+                None => Ok(()),
+            },
+            Err(e) => writeln!(text, "Stopped by an error: {}", e),
+        }
+        .unwrap();
+        writeln!(text, "{}", self.nes.trace_line()).unwrap();
+        Ok(text)
+    }
+
+    fn add_watchpoint(
+        &mut self,
+        kind: WatchKind,
+        start: u16,
+        end: u16,
+        condition: Option<&str>,
+    ) -> Result<String, String> {
+        let condition = condition
+            .map(|condition| Condition::parse(condition, self.symbols.as_ref()))
+            .transpose()
+            .map_err(|e| format!("Invalid condition: {}", e))?;
+        let watchpoint = Watchpoint {
+            id: self.next_id,
+            kind,
+            start,
+            end,
+            condition,
+        };
+        self.next_id += 1;
+        let text = format!("{}  {}\n", watchpoint.id, watchpoint.describe());
+        self.watches.borrow_mut().watchpoints.push(watchpoint);
+        Ok(text)
+    }
+
+    fn list_watchpoints(&self) -> String {
+        let watches = self.watches.borrow();
+        if watches.watchpoints.is_empty() {
+            return "No breakpoints or watchpoints\n".to_string();
+        }
+        let mut text = String::new();
+        for watchpoint in &watches.watchpoints {
+            writeln!(text, "{}  {}", watchpoint.id, watchpoint.describe()).unwrap();
+        }
+        text
+    }
+
+    fn registers(&self) -> String {
+        let cpu = self.nes.cpu();
+        let p = cpu.status.into_bits();
+        let flags: String = "NV-BDIZC"
+            .chars()
+            .enumerate()
+            .map(|(i, name)| match p & (0x80 >> i) {
+                0 => name.to_ascii_lowercase(),
+                _ => name,
+            })
+            .collect();
+        format!(
+            "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} S:{:02X} P:{:02X} ({}) CYC:{} frame:{}\n",
+            cpu.reg_pc,
+            cpu.reg_a,
+            cpu.reg_x,
+            cpu.reg_y,
+            cpu.reg_s,
+            p,
+            flags,
+            cpu.cycle,
+            self.nes.frame()
+        )
+    }
+
+    fn set_register(&mut self, register: &str, value: u16) -> Result<(), String> {
+        let byte = u8::try_from(value);
+        let cpu = self.nes.cpu_mut();
+        match (register.to_ascii_uppercase().as_str(), byte) {
+            ("PC", _) => cpu.reg_pc = value,
+            ("A", Ok(byte)) => cpu.reg_a = byte,
+            ("X", Ok(byte)) => cpu.reg_x = byte,
+            ("Y", Ok(byte)) => cpu.reg_y = byte,
+            ("S" | "SP", Ok(byte)) => cpu.reg_s = byte,
+            ("P", Ok(byte)) => cpu.status = Status::from_bits(byte),
+            ("A" | "X" | "Y" | "S" | "SP" | "P", Err(_)) => {
+                return Err(format!("{:X} doesn't fit in {}", value, register))
+            }
+            _ => return Err(format!("Invalid register {}", register)),
+        }
+        Ok(())
+    }
+
+    fn dump(&mut self, address: u16, length: u16) -> String {
+        let mut text = String::new();
+        for row in (0..length).step_by(16) {
+            let row_address = address.wrapping_add(row);
+            write!(text, "{:04X} ", row_address).unwrap();
+            for i in 0..(length - row).min(16) {
+                write!(text, " {:02X}", self.nes.peek(row_address.wrapping_add(i))).unwrap();
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// `count` instructions from `address`, marking the one at PC
+    fn disassembly(&mut self, mut address: u16, count: usize) -> String {
+        let pc = self.nes.cpu().reg_pc;
+        let mut text = String::new();
+        for _ in 0..count {
+            if let Some(name) = self.symbols.as_ref().and_then(|s| s.name(address)) {
+                writeln!(text, "{}:", name).unwrap();
+            }
+            let instruction = Instruction::read(address, |a| self.nes.peek(a));
+            let marker = if address == pc { "=>" } else { "  " };
+            writeln!(
+                text,
+                "{} {}",
+                marker,
+                instruction.line(self.symbols.as_ref())
+            )
+            .unwrap();
+            address = instruction.next_address();
+        }
+        text
+    }
+
+    /// An address or value, given in hex or by the name of a symbol
+    fn address(&self, text: &str) -> Result<u16, String> {
+        self.symbols
+            .as_ref()
+            .and_then(|symbols| symbols.address(text))
+            .or_else(|| parse_address(text))
+            .ok_or_else(|| format!("Invalid address {}", text))
+    }
+
+    fn byte(&self, text: &str) -> Result<u8, String> {
+        parse_address(text)
+            .and_then(|value| u8::try_from(value).ok())
+            .ok_or_else(|| format!("Invalid value {}", text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom::TestRom;
+
+    /// Counts X up forever, storing it at $0200 and reading it back in a subroutine
+    const PROGRAM: &[(u16, &[u8])] = &[
+        (0x8000, &[0xA2, 0x00]),       // LDX #$00
+        (0x8002, &[0xE8]),             // loop: INX
+        (0x8003, &[0x8E, 0x00, 0x02]), // STX $0200
+        (0x8006, &[0x20, 0x10, 0x80]), // JSR load
+        (0x8009, &[0x4C, 0x02, 0x80]), // JMP loop
+        (0x8010, &[0xAD, 0x00, 0x02]), // load: LDA $0200
+        (0x8013, &[0x60]),             // RTS
+    ];
+
+    /// A debugger stopped at the first instruction
+    fn debugger(symbols: Option<Symbols>) -> Debugger {
+        let rom = PROGRAM
+            .iter()
+            .fold(TestRom::new(2), |rom, &(address, bytes)| {
+                rom.code(address, bytes)
+            });
+        let mut nes = NES::default();
+        nes.power_on();
+        nes.load_rom(rom.build()).unwrap();
+        nes.step_instruction().unwrap();
+        assert_eq!(nes.cpu().reg_pc, 0x8000);
+        Debugger::new(nes, symbols)
+    }
+
+    fn pc(debugger: &mut Debugger) -> u16 {
+        debugger.nes().cpu().reg_pc
+    }
+
+    #[test]
+    fn steps() {
+        let mut debugger = debugger(None);
+        let text = debugger.command("s 2").unwrap();
+        assert!(text.starts_with("8003  8E 00 02  STX $0200"), "{}", text);
+        // Over the subroutine, then back out of it
+        debugger.command("s").unwrap();
+        debugger.command("n").unwrap();
+        assert_eq!(pc(&mut debugger), 0x8009);
+        debugger.command("set pc 8006").unwrap();
+        debugger.command("s").unwrap();
+        assert_eq!(pc(&mut debugger), 0x8010);
+        debugger.command("finish").unwrap();
+        assert_eq!(pc(&mut debugger), 0x8009);
+    }
+
+    #[test]
+    fn stops_at_breakpoints() {
+        let mut debugger = debugger(None);
+        assert_eq!(debugger.command("b 8009").unwrap(), "1  break 8009\n");
+        for x in 1..=2 {
+            let text = debugger.command("c").unwrap();
+            assert!(text.starts_with("Breakpoint 1 at 8009\n"), "{}", text);
+            assert_eq!(debugger.nes().cpu().reg_x, x);
+        }
+    }
+
+    #[test]
+    fn continues_for_any_number_of_frames() {
+        let mut debugger = debugger(None);
+        debugger.command("c 1").unwrap();
+        debugger.command("b 8009").unwrap();
+        let text = debugger.command(&format!("c {}", u64::MAX)).unwrap();
+        assert!(text.starts_with("Breakpoint 1 at 8009\n"), "{}", text);
+    }
+
+    #[test]
+    fn checks_breakpoint_conditions() {
+        let mut symbols = Symbols::default();
+        symbols.insert(0x8003, "store");
+        let mut debugger = debugger(Some(symbols));
+        assert_eq!(
+            debugger.command("break store if X == 3").unwrap(),
+            "1  break 8003 if X == 3\n"
+        );
+        debugger.command("c").unwrap();
+        assert_eq!(pc(&mut debugger), 0x8003);
+        assert_eq!(debugger.nes().cpu().reg_x, 3);
+
+        assert_eq!(
+            debugger.command("b 8003 if X ==").unwrap_err(),
+            "Invalid condition: condition ends early"
+        );
+        assert_eq!(
+            debugger.command("s if X == 1").unwrap_err(),
+            "Only breakpoints and watchpoints take a condition"
+        );
+    }
+
+    #[test]
+    fn stops_after_watched_accesses() {
+        let mut debugger = debugger(None);
+        assert_eq!(
+            debugger.command("w w 0200-02FF").unwrap(),
+            "1  watch w 0200-02FF\n"
+        );
+        let text = debugger.command("c").unwrap();
+        assert!(
+            text.starts_with("Watchpoint 1: wrote $01 to 0200\n8006 "),
+            "{}",
+            text
+        );
+
+        debugger.command("d").unwrap();
+        // Checked after the instruction, once A holds what was read
+        debugger.command("w r 0200 if A == 2").unwrap();
+        let text = debugger.command("c").unwrap();
+        assert!(
+            text.starts_with("Watchpoint 2: read $02 from 0200\n8013 "),
+            "{}",
+            text
+        );
+
+        // Execute watchpoints cover a range of instructions
+        debugger.command("d").unwrap();
+        debugger.command("w x 8010-8013").unwrap();
+        debugger.command("c").unwrap();
+        assert_eq!(pc(&mut debugger), 0x8010);
+
+        assert!(debugger.command("w q 0200").is_err());
+        assert!(debugger.command("w r 0300-0200").is_err());
+    }
+
+    #[test]
+    fn lists_and_deletes_watchpoints() {
+        let mut debugger = debugger(None);
+        debugger.command("b 8002").unwrap();
+        debugger.command("w rw 0200 if X >= 2").unwrap();
+        assert_eq!(
+            debugger.command("bl").unwrap(),
+            "1  break 8002\n2  watch rw 0200 if X >= 2\n"
+        );
+        debugger.command("d 1").unwrap();
+        assert_eq!(
+            debugger.command("d 1").unwrap_err(),
+            "No breakpoint or watchpoint 1"
+        );
+        assert_eq!(
+            debugger.command("breaks").unwrap(),
+            "2  watch rw 0200 if X >= 2\n"
+        );
+        debugger.command("delete").unwrap();
+        assert_eq!(
+            debugger.command("bl").unwrap(),
+            "No breakpoints or watchpoints\n"
+        );
+        // Nothing stops a frame limited continue early now
+        debugger.command("c 1").unwrap();
+        assert_eq!(debugger.nes().frame(), 1);
+    }
+
+    #[test]
+    fn sets_registers() {
+        let mut debugger = debugger(None);
+        let text = debugger.command("set a 42").unwrap();
+        assert!(text.starts_with("PC:8000 A:42 "), "{}", text);
+        debugger.command("set P $C3").unwrap();
+        assert!(debugger.command("r").unwrap().contains("P:C3 (NV-bdiZC)"));
+        assert_eq!(
+            debugger.command("set x 100").unwrap_err(),
+            "100 doesn't fit in x"
+        );
+        assert_eq!(
+            debugger.command("set q 1").unwrap_err(),
+            "Invalid register q"
+        );
+        assert!(debugger.command("set a").is_err());
+    }
+
+    #[test]
+    fn shows_and_changes_memory() {
+        let mut debugger = debugger(None);
+        debugger.command("poke 0200 12 34").unwrap();
+        assert_eq!(debugger.command("m 0200 2").unwrap(), "0200  12 34\n");
+        assert_eq!(
+            debugger.command("l 8002 2").unwrap(),
+            "   8002  E8        INX\n   8003  8E 00 02  STX $0200\n"
+        );
+        // Starts a few instructions before PC
+        let text = debugger.command("disasm").unwrap();
+        assert!(text.contains("   7FFF  00        BRK\n=> 8000  A2 00     LDX #$00\n"));
+        assert!(debugger.command("poke 0200 100").is_err());
+        assert!(debugger.command("bogus").is_err());
+    }
+}
@@ -0,0 +1,364 @@
+//! Conditions for breakpoints, like `A == $10 && (C || X >= 80)`. Operands are registers (A, X,
+//! Y, S or SP, P, PC), flags (N, V, D, I, Z, C, worth 0 or 1), symbols and hexadecimal numbers.
+//! Numbers that look like a register or flag need a `$`, e.g. `$C`.
+
+use std::fmt;
+
+use crate::cpu::CPU;
+use crate::disasm::{parse_address, Symbols};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Register {
+    A,
+    X,
+    Y,
+    S,
+    P,
+    PC,
+    Negative,
+    Overflow,
+    Decimal,
+    InterruptDisable,
+    Zero,
+    Carry,
+}
+
+impl Register {
+    fn from_name(name: &str) -> Option<Register> {
+        let register = match name.to_ascii_uppercase().as_str() {
+            "A" => Register::A,
+            "X" => Register::X,
+            "Y" => Register::Y,
+            "S" | "SP" => Register::S,
+            "P" => Register::P,
+            "PC" => Register::PC,
+            "N" => Register::Negative,
+            "V" => Register::Overflow,
+            "D" => Register::Decimal,
+            "I" => Register::InterruptDisable,
+            "Z" => Register::Zero,
+            "C" => Register::Carry,
+            _ => return None,
+        };
+        Some(register)
+    }
+
+    fn value(self, cpu: &CPU) -> u16 {
+        match self {
+            Register::A => cpu.reg_a as u16,
+            Register::X => cpu.reg_x as u16,
+            Register::Y => cpu.reg_y as u16,
+            Register::S => cpu.reg_s as u16,
+            Register::P => cpu.status.into_bits() as u16,
+            Register::PC => cpu.reg_pc,
+            Register::Negative => cpu.status.negative() as u16,
+            Register::Overflow => cpu.status.overflow() as u16,
+            Register::Decimal => cpu.status.decimal() as u16,
+            Register::InterruptDisable => cpu.status.interrupt_disable() as u16,
+            Register::Zero => cpu.status.zero() as u16,
+            Register::Carry => cpu.status.carry() as u16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    And,
+    Or,
+}
+
+impl Operator {
+    fn apply(self, left: u16, right: u16) -> bool {
+        match self {
+            Operator::Equal => left == right,
+            Operator::NotEqual => left != right,
+            Operator::Less => left < right,
+            Operator::LessOrEqual => left <= right,
+            Operator::Greater => left > right,
+            Operator::GreaterOrEqual => left >= right,
+            Operator::And => left != 0 && right != 0,
+            Operator::Or => left != 0 || right != 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expression {
+    Value(u16),
+    Register(Register),
+    Binary(Box<Expression>, Operator, Box<Expression>),
+}
+
+impl Expression {
+    fn eval(&self, cpu: &CPU) -> u16 {
+        match self {
+            Expression::Value(value) => *value,
+            Expression::Register(register) => register.value(cpu),
+            Expression::Binary(left, operator, right) => {
+                operator.apply(left.eval(cpu), right.eval(cpu)) as u16
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Operator(Operator),
+    Open,
+    Close,
+}
+
+/// Longer operators first, so `<=` isn't read as `<`
+const OPERATORS: [(&str, Operator); 8] = [
+    ("==", Operator::Equal),
+    ("!=", Operator::NotEqual),
+    ("<=", Operator::LessOrEqual),
+    (">=", Operator::GreaterOrEqual),
+    ("&&", Operator::And),
+    ("||", Operator::Or),
+    ("<", Operator::Less),
+    (">", Operator::Greater),
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c == '(' || c == ')' {
+            tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            rest = &rest[1..];
+        } else if let Some(&(symbol, operator)) = OPERATORS
+            .iter()
+            .find(|(symbol, _)| rest.starts_with(symbol))
+        {
+            tokens.push(Token::Operator(operator));
+            rest = &rest[symbol.len()..];
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '$'))
+                .unwrap_or(rest.len());
+            if end == 0 {
+                return Err(format!("unexpected \"{}\"", c));
+            }
+            tokens.push(Token::Word(rest[..end].to_string()));
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    symbols: Option<&'a Symbols>,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek_operator(&self) -> Option<Operator> {
+        match self.tokens.get(self.position) {
+            Some(Token::Operator(operator)) => Some(*operator),
+            _ => None,
+        }
+    }
+
+    /// `||` binds loosest, then `&&`, then the comparisons
+    fn or(&mut self) -> Result<Expression, String> {
+        let mut left = self.and()?;
+        while self.peek_operator() == Some(Operator::Or) {
+            self.position += 1;
+            left = Expression::Binary(Box::new(left), Operator::Or, Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expression, String> {
+        let mut left = self.comparison()?;
+        while self.peek_operator() == Some(Operator::And) {
+            self.position += 1;
+            left = Expression::Binary(Box::new(left), Operator::And, Box::new(self.comparison()?));
+        }
+        Ok(left)
+    }
+
+    fn comparison(&mut self) -> Result<Expression, String> {
+        let left = self.operand()?;
+        match self.peek_operator() {
+            Some(operator) if !matches!(operator, Operator::And | Operator::Or) => {
+                self.position += 1;
+                Ok(Expression::Binary(
+                    Box::new(left),
+                    operator,
+                    Box::new(self.operand()?),
+                ))
+            }
+            _ => Ok(left),
+        }
+    }
+
+    fn operand(&mut self) -> Result<Expression, String> {
+        match self.next() {
+            Some(Token::Open) => {
+                let expression = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expression),
+                    _ => Err("missing \")\"".to_string()),
+                }
+            }
+            Some(Token::Word(word)) => {
+                if let Some(register) = Register::from_name(&word) {
+                    Ok(Expression::Register(register))
+                } else if let Some(address) = self.symbols.and_then(|s| s.address(&word)) {
+                    Ok(Expression::Value(address))
+                } else {
+                    parse_address(&word)
+                        .map(Expression::Value)
+                        .ok_or_else(|| format!("unknown name \"{}\"", word))
+                }
+            }
+            Some(Token::Close) => Err("unexpected \")\"".to_string()),
+            Some(Token::Operator(_)) => Err("missing an operand".to_string()),
+            None => Err("condition ends early".to_string()),
+        }
+    }
+}
+
+/// A condition that's checked against the CPU registers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    text: String,
+    expression: Expression,
+}
+
+impl Condition {
+    pub fn parse(text: &str, symbols: Option<&Symbols>) -> Result<Condition, String> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            position: 0,
+            symbols,
+        };
+        let expression = parser.or()?;
+        if parser.position < parser.tokens.len() {
+            return Err("unexpected text after the condition".to_string());
+        }
+        Ok(Condition {
+            text: text.trim().to_string(),
+            expression,
+        })
+    }
+
+    pub fn holds(&self, cpu: &CPU) -> bool {
+        self.expression.eval(cpu) != 0
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Status;
+
+    fn cpu(a: u8, x: u8, y: u8, p: u8) -> CPU {
+        let mut cpu = CPU::default();
+        (cpu.reg_a, cpu.reg_x, cpu.reg_y) = (a, x, y);
+        cpu.status = Status::from_bits(p);
+        cpu.reg_pc = 0xC000;
+        cpu
+    }
+
+    fn holds(text: &str, cpu: &CPU) -> bool {
+        Condition::parse(text, None).unwrap().holds(cpu)
+    }
+
+    #[test]
+    fn tokenizes() {
+        let word = |word: &str| Token::Word(word.to_string());
+        assert_eq!(
+            tokenize("A==$10&&(C||X>=80)").unwrap(),
+            [
+                word("A"),
+                Token::Operator(Operator::Equal),
+                word("$10"),
+                Token::Operator(Operator::And),
+                Token::Open,
+                word("C"),
+                Token::Operator(Operator::Or),
+                word("X"),
+                Token::Operator(Operator::GreaterOrEqual),
+                word("80"),
+                Token::Close,
+            ]
+        );
+        assert_eq!(
+            tokenize(" pc < reset_1 ").unwrap(),
+            [word("pc"), Token::Operator(Operator::Less), word("reset_1")]
+        );
+        assert_eq!(tokenize("A = 1"), Err("unexpected \"=\"".to_string()));
+    }
+
+    #[test]
+    fn compares_registers_and_flags() {
+        let cpu = cpu(0x10, 0x80, 0x00, 0x03);
+        assert!(holds("A == $10", &cpu));
+        assert!(holds("a != 11", &cpu));
+        assert!(holds("X > 7F && X >= 80 && X <= 80 && Y < 1", &cpu));
+        assert!(holds("PC == C000", &cpu));
+        assert!(holds("P == 3", &cpu));
+        // Flags are 0 or 1, and a plain operand is true when it isn't 0
+        assert!(holds("C == 1 && Z && N == 0", &cpu));
+        assert!(!holds("N", &cpu));
+        // $C is a number, C the carry flag
+        assert!(holds("A > $C && C < $C", &cpu));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let cpu = cpu(1, 0, 0, 0);
+        // A == 1 || (X == 2 && Y == 3)
+        assert!(holds("A == 1 || X == 2 && Y == 3", &cpu));
+        // (X == 2 && Y == 3) || A == 1
+        assert!(holds("X == 2 && Y == 3 || A == 1", &cpu));
+        assert!(!holds("(A == 1 || X == 2) && Y == 3", &cpu));
+    }
+
+    #[test]
+    fn looks_up_symbols() {
+        let mut symbols = Symbols::default();
+        symbols.insert(0xC000, "reset");
+        let condition = Condition::parse("PC == reset", Some(&symbols)).unwrap();
+        assert!(condition.holds(&cpu(0, 0, 0, 0)));
+        assert_eq!(condition.to_string(), "PC == reset");
+    }
+
+    #[test]
+    fn rejects_malformed_conditions() {
+        let error = |text| Condition::parse(text, None).unwrap_err();
+        assert_eq!(error("A =="), "condition ends early");
+        assert_eq!(error(""), "condition ends early");
+        assert_eq!(error("(A == 1"), "missing \")\"");
+        assert_eq!(error("A == 1)"), "unexpected text after the condition");
+        assert_eq!(error("A == 1 X"), "unexpected text after the condition");
+        assert_eq!(error(") == 1"), "unexpected \")\"");
+        assert_eq!(error("A == && 1"), "missing an operand");
+        assert_eq!(error("reset == 1"), "unknown name \"reset\"");
+        assert_eq!(error("A @ 1"), "unexpected \"@\"");
+    }
+}
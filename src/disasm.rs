@@ -0,0 +1,407 @@
+//! Turns machine code back into assembly, one line per instruction:
+//!
+//! `C5F5  A2 00     LDX #$00`
+//!
+//! Unofficial opcodes get a `*` before the mnemonic, like in nestest.log, and addresses that
+//! have a name in a symbol table are shown by that name.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::instructions::opcodes::{Opcode, OPCODES};
+use crate::instructions::AddressingMode;
+use crate::utils::build_u16;
+
+/// Names for addresses, e.g. from a label file written by the assembler
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Symbols {
+    names: HashMap<u16, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolParseError {
+    /// 1-based
+    pub line: usize,
+    pub text: String,
+}
+
+impl fmt::Display for SymbolParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: can't parse \"{}\"", self.line, self.text)
+    }
+}
+
+impl Error for SymbolParseError {}
+
+impl Symbols {
+    /// Parses a symbol file with one symbol per line, in any of these forms:
+    ///
+    /// - `al 00C000 .reset`, the VICE label files ld65 writes with `-Ln`
+    /// - `reset = $C000`
+    /// - `C000 reset`
+    ///
+    /// Empty lines and lines starting with `;` or `#` are skipped.
+    pub fn parse(text: &str) -> Result<Symbols, SymbolParseError> {
+        let mut symbols = Symbols::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let symbol = match words[..] {
+                ["al", address, name] => {
+                    parse_address(address).map(|address| (address, name.trim_start_matches('.')))
+                }
+                [name, "=", address] => parse_address(address).map(|address| (address, name)),
+                [address, name] => parse_address(address).map(|address| (address, name)),
+                _ => None,
+            };
+            let Some((address, name)) = symbol else {
+                return Err(SymbolParseError {
+                    line: i + 1,
+                    text: line.to_string(),
+                });
+            };
+            symbols.insert(address, name);
+        }
+        Ok(symbols)
+    }
+
+    pub fn insert(&mut self, address: u16, name: &str) {
+        self.names.insert(address, name.to_string());
+    }
+
+    pub fn name(&self, address: u16) -> Option<&str> {
+        self.names.get(&address).map(String::as_str)
+    }
+
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.names
+            .iter()
+            .find(|(_, symbol)| *symbol == name)
+            .map(|(&address, _)| address)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+/// Parses a hexadecimal address with an optional `$` or `0x` prefix. ld65 writes 24-bit
+/// addresses, so anything that fits after dropping leading zeros is accepted.
+pub fn parse_address(text: &str) -> Option<u16> {
+    let digits = text
+        .strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .unwrap_or(text);
+    u32::from_str_radix(digits, 16)
+        .ok()
+        .and_then(|address| u16::try_from(address).ok())
+}
+
+/// An instruction and its operand bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: u16,
+    /// The opcode and up to two operand bytes, only the first `size()` are used
+    bytes: [u8; 3],
+}
+
+impl Instruction {
+    /// Reads the instruction at `address` with `read`, e.g. `NES::peek`
+    pub fn read(address: u16, mut read: impl FnMut(u16) -> u8) -> Instruction {
+        let mut bytes = [read(address), 0, 0];
+        for i in 1..OPCODES[bytes[0] as usize].size() {
+            bytes[i] = read(address.wrapping_add(i as u16));
+        }
+        Instruction { address, bytes }
+    }
+
+    /// Decodes the instruction at the start of `bytes`, or `None` if `bytes` ends before it does
+    pub fn decode(address: u16, bytes: &[u8]) -> Option<Instruction> {
+        let size = OPCODES[*bytes.first()? as usize].size();
+        let bytes = bytes.get(..size)?;
+        Some(Instruction::read(address, |a| {
+            bytes[a.wrapping_sub(address) as usize]
+        }))
+    }
+
+    pub fn opcode(&self) -> &'static Opcode {
+        &OPCODES[self.bytes[0] as usize]
+    }
+
+    pub fn size(&self) -> usize {
+        self.opcode().size()
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.size()]
+    }
+
+    /// The operand as a number, zero for instructions without one
+    pub fn operand(&self) -> u16 {
+        match self.size() {
+            2 => self.bytes[1] as u16,
+            3 => build_u16(self.bytes[2], self.bytes[1]),
+            _ => 0,
+        }
+    }
+
+    /// Where the next instruction starts
+    pub fn next_address(&self) -> u16 {
+        self.address.wrapping_add(self.size() as u16)
+    }
+
+    /// Where a branch goes when it's taken
+    pub fn branch_target(&self) -> u16 {
+        self.next_address().wrapping_add(self.bytes[1] as i8 as u16)
+    }
+
+    /// E.g. "LDX"
+    pub fn mnemonic(&self) -> String {
+        format!("{:?}", self.opcode().inst_type)
+    }
+
+    /// The instruction bytes in hex, e.g. "A2 00"
+    pub fn hex_bytes(&self) -> String {
+        let bytes: Vec<String> = self.bytes().iter().map(|b| format!("{:02X}", b)).collect();
+        bytes.join(" ")
+    }
+
+    /// The operand in assembler syntax, e.g. "($12),Y", empty for implied instructions
+    pub fn operand_text(&self, symbols: Option<&Symbols>) -> String {
+        let zero_page = || address_text(self.operand(), 2, symbols);
+        let absolute = || address_text(self.operand(), 4, symbols);
+        match self.opcode().addr_mode {
+            AddressingMode::Implicit | AddressingMode::Illegal => String::new(),
+            AddressingMode::Accumulator => "A".to_string(),
+            AddressingMode::Immediate => format!("#${:02X}", self.operand()),
+            AddressingMode::ZeroPage => zero_page(),
+            AddressingMode::ZeroPageIndexedX => format!("{},X", zero_page()),
+            AddressingMode::ZeroPageIndexedY => format!("{},Y", zero_page()),
+            AddressingMode::Absolute => absolute(),
+            AddressingMode::AbsoluteIndexedX => format!("{},X", absolute()),
+            AddressingMode::AbsoluteIndexedY => format!("{},Y", absolute()),
+            AddressingMode::IndexedIndirect => format!("({},X)", zero_page()),
+            AddressingMode::IndirectIndexed => format!("({}),Y", zero_page()),
+            AddressingMode::Indirect => format!("({})", absolute()),
+            AddressingMode::Relative => address_text(self.branch_target(), 4, symbols),
+        }
+    }
+
+    /// The mnemonic and the operand, e.g. "LDX #$00"
+    pub fn text(&self, symbols: Option<&Symbols>) -> String {
+        match self.operand_text(symbols) {
+            operand if operand.is_empty() => self.mnemonic(),
+            operand => format!("{} {}", self.mnemonic(), operand),
+        }
+    }
+
+    /// The address, the bytes and the text, e.g. `C5F5  A2 00     LDX #$00`
+    pub fn line(&self, symbols: Option<&Symbols>) -> String {
+        format!(
+            "{:04X}  {:<8} {}{}",
+            self.address,
+            self.hex_bytes(),
+            if self.opcode().unofficial { '*' } else { ' ' },
+            self.text(symbols)
+        )
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.line(None))
+    }
+}
+
+fn address_text(address: u16, digits: usize, symbols: Option<&Symbols>) -> String {
+    match symbols.and_then(|symbols| symbols.name(address)) {
+        Some(name) => name.to_string(),
+        None => format!("${:0width$X}", address, width = digits),
+    }
+}
+
+/// Disassembles `bytes` as if they were loaded at `origin`. Bytes left over at the end that
+/// don't make up a whole instruction are listed as data.
+pub fn disassemble(bytes: &[u8], origin: u16, symbols: Option<&Symbols>) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let address = origin.wrapping_add(offset as u16);
+        push_label(&mut lines, address, symbols);
+        match Instruction::decode(address, &bytes[offset..]) {
+            Some(instruction) => {
+                lines.push(instruction.line(symbols));
+                offset += instruction.size();
+            }
+            None => {
+                lines.push(format!(
+                    "{:04X}  {:02X}        .byte ${:02X}",
+                    address, bytes[offset], bytes[offset]
+                ));
+                offset += 1;
+            }
+        }
+    }
+    lines
+}
+
+/// Disassembles the instructions that start between `start` and `end`, inclusive, reading
+/// memory with `read`, e.g. `NES::peek`
+pub fn disassemble_range(
+    mut read: impl FnMut(u16) -> u8,
+    start: u16,
+    end: u16,
+    symbols: Option<&Symbols>,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+    // Wider than the address space so a range ending at $FFFF stops
+    let mut address = start as u32;
+    while address <= end as u32 {
+        push_label(&mut lines, address as u16, symbols);
+        let instruction = Instruction::read(address as u16, &mut read);
+        lines.push(instruction.line(symbols));
+        address += instruction.size() as u32;
+    }
+    lines
+}
+
+/// Guesses where the instruction `count` instructions before `address` starts. Code can't be
+/// decoded backwards reliably, so this tries every start up to three bytes per instruction back
+/// and picks the one with the most instructions, up to `count`, that line up with `address`.
+pub fn find_start_before(mut read: impl FnMut(u16) -> u8, address: u16, count: usize) -> u16 {
+    let mut best = (0, address);
+    for back in (1..=count * 3).rev() {
+        let start = address.wrapping_sub(back as u16);
+        let (mut offset, mut instructions) = (0, 0);
+        while offset < back {
+            offset += Instruction::read(start.wrapping_add(offset as u16), &mut read).size();
+            instructions += 1;
+        }
+        if offset == back && instructions <= count && instructions > best.0 {
+            best = (instructions, start);
+        }
+    }
+    best.1
+}
+
+fn push_label(lines: &mut Vec<String>, address: u16, symbols: Option<&Symbols>) {
+    if let Some(name) = symbols.and_then(|symbols| symbols.name(address)) {
+        lines.push(format!("{}:", name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_symbol_files() {
+        let symbols = Symbols::parse(
+            "; comment\n\
+             al 00C000 .reset\n\
+             \n\
+             nmi = $C100\n\
+             # comment\n\
+             0xC200 irq\n",
+        )
+        .unwrap();
+        assert_eq!(symbols.name(0xC000), Some("reset"));
+        assert_eq!(symbols.address("nmi"), Some(0xC100));
+        assert_eq!(symbols.address("irq"), Some(0xC200));
+        assert_eq!(symbols.name(0xC001), None);
+    }
+
+    #[test]
+    fn rejects_bad_symbol_lines() {
+        let error = Symbols::parse("reset = $C000\nnmi = $10000\n").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert_eq!(error.text, "nmi = $10000");
+        assert!(Symbols::parse("reset").is_err());
+        assert!(Symbols::parse("C000 reset extra").is_err());
+    }
+
+    #[test]
+    fn parses_addresses() {
+        assert_eq!(parse_address("$C000"), Some(0xC000));
+        assert_eq!(parse_address("0x10"), Some(0x10));
+        assert_eq!(parse_address("00FFFF"), Some(0xFFFF));
+        assert_eq!(parse_address("10000"), None);
+        assert_eq!(parse_address("reset"), None);
+    }
+
+    #[test]
+    fn decodes_instructions() {
+        let lda = Instruction::decode(0xC5F5, &[0xA2, 0x00, 0xFF]).unwrap();
+        assert_eq!(lda.bytes(), [0xA2, 0x00]);
+        assert_eq!(lda.next_address(), 0xC5F7);
+        assert_eq!(lda.line(None), "C5F5  A2 00     LDX #$00");
+
+        let jmp = Instruction::decode(0xC000, &[0x6C, 0x34, 0x12]).unwrap();
+        assert_eq!(jmp.operand(), 0x1234);
+        assert_eq!(jmp.to_string(), "C000  6C 34 12  JMP ($1234)");
+
+        // Ends before the operand does
+        assert_eq!(Instruction::decode(0xC000, &[0x4C, 0x00]), None);
+        assert_eq!(Instruction::decode(0xC000, &[]), None);
+    }
+
+    #[test]
+    fn formats_operands() {
+        let line = |bytes: &[u8]| Instruction::decode(0x8000, bytes).unwrap().line(None);
+        assert_eq!(line(&[0x00]), "8000  00        BRK");
+        assert_eq!(line(&[0x0A]), "8000  0A        ASL A");
+        assert_eq!(line(&[0xB1, 0x12]), "8000  B1 12     LDA ($12),Y");
+        assert_eq!(line(&[0xA1, 0x12]), "8000  A1 12     LDA ($12,X)");
+        assert_eq!(line(&[0xB6, 0x12]), "8000  B6 12     LDX $12,Y");
+        assert_eq!(line(&[0x9D, 0x00, 0x02]), "8000  9D 00 02  STA $0200,X");
+        // Branch targets are relative to the next instruction
+        assert_eq!(line(&[0xD0, 0xFE]), "8000  D0 FE     BNE $8000");
+        assert_eq!(line(&[0xA7, 0x12]), "8000  A7 12    *LAX $12");
+    }
+
+    #[test]
+    fn shows_symbols() {
+        let mut symbols = Symbols::default();
+        symbols.insert(0x8000, "loop");
+        symbols.insert(0x0012, "pointer");
+        let lines = disassemble(&[0xB1, 0x12, 0xD0, 0xFC, 0x4C], 0x8000, Some(&symbols));
+        assert_eq!(
+            lines,
+            [
+                "loop:",
+                "8000  B1 12     LDA (pointer),Y",
+                "8002  D0 FC     BNE loop",
+                "8004  4C        .byte $4C",
+            ]
+        );
+    }
+
+    #[test]
+    fn disassembles_ranges_up_to_the_end_of_memory() {
+        let memory = |address: u16| match address {
+            0xFFFC => 0x20,
+            _ => 0xEA,
+        };
+        let lines = disassemble_range(memory, 0xFFFC, 0xFFFF, None);
+        assert_eq!(lines, ["FFFC  20 EA EA  JSR $EAEA", "FFFF  EA        NOP"]);
+    }
+
+    #[test]
+    fn finds_the_start_of_earlier_instructions() {
+        // LDA #$01, STA $0200, INX, JMP $8000
+        let code = [0xA9, 0x01, 0x8D, 0x00, 0x02, 0xE8, 0x4C, 0x00, 0x80];
+        let memory = |address: u16| {
+            *code
+                .get(address.wrapping_sub(0x8000) as usize)
+                .unwrap_or(&0xEA)
+        };
+        assert_eq!(find_start_before(memory, 0x8006, 1), 0x8005);
+        assert_eq!(find_start_before(memory, 0x8006, 2), 0x8002);
+        assert_eq!(find_start_before(memory, 0x8006, 3), 0x8000);
+        assert_eq!(find_start_before(memory, 0x8000, 0), 0x8000);
+    }
+}
@@ -0,0 +1,63 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::ines::RomHeaderError;
+
+/// Everything that can go wrong while loading or running a ROM
+#[derive(Debug)]
+pub enum NesError {
+    /// The file isn't a valid iNES or NES 2.0 file
+    RomHeader(RomHeaderError),
+    UnsupportedMapper(u16),
+    Io(io::Error),
+    /// In strict mode, a read of an address nothing responds to
+    UnmappedRead(u16),
+    /// In strict mode, a write to an address nothing responds to
+    UnmappedWrite {
+        address: u16,
+        value: u8,
+    },
+}
+
+impl fmt::Display for NesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NesError::RomHeader(e) => write!(f, "invalid ROM: {}", e),
+            NesError::UnsupportedMapper(mapper) => write!(f, "mapper {} is not supported", mapper),
+            NesError::Io(e) => write!(f, "{}", e),
+            NesError::UnmappedRead(address) => {
+                write!(f, "read from unmapped address ${:04X}", address)
+            }
+            NesError::UnmappedWrite { address, value } => {
+                write!(
+                    f,
+                    "write of ${:02X} to unmapped address ${:04X}",
+                    value, address
+                )
+            }
+        }
+    }
+}
+
+impl Error for NesError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            NesError::RomHeader(e) => Some(e),
+            NesError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<RomHeaderError> for NesError {
+    fn from(e: RomHeaderError) -> Self {
+        NesError::RomHeader(e)
+    }
+}
+
+impl From<io::Error> for NesError {
+    fn from(e: io::Error) -> Self {
+        NesError::Io(e)
+    }
+}
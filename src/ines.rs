@@ -1,5 +1,10 @@
+use std::error::Error;
+use std::fmt;
+
 use bitfield_struct::bitfield;
 
+use crate::mapper::Mirroring;
+
 #[derive(Debug, PartialEq, Eq)]
 #[repr(u8)]
 pub enum NametableArrangement {
@@ -33,3 +38,421 @@ pub struct Flags6 {
     #[bits(4)]
     pub mapper_number_lower_nibble: u8,
 }
+
+#[bitfield(u8)]
+pub struct Flags7 {
+    #[bits(2)]
+    pub console_type: u8,
+    /// 2 for NES 2.0 headers
+    #[bits(2)]
+    pub nes2_identifier: u8,
+    #[bits(4)]
+    pub mapper_number_upper_nibble: u8,
+}
+
+pub const HEADER_SIZE: usize = 16;
+pub const TRAINER_SIZE: usize = 512;
+/// Where the trainer gets loaded, in PRG-RAM
+pub const TRAINER_ADDR: u16 = 0x7000;
+const MAGIC: &[u8; 4] = b"NES\x1A";
+const PRG_ROM_UNIT: usize = 16 * 1024;
+const CHR_ROM_UNIT: usize = 8 * 1024;
+const PRG_RAM_UNIT: usize = 8 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderFormat {
+    /// Only bytes 4-6 are meaningful, the rest is often garbage like "DiskDude!"
+    ArchaicINes,
+    INes,
+    Nes20,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    MultipleRegion,
+    Dendy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    Playchoice10,
+    /// One of the NES 2.0 extended console types
+    Extended(u8),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RomHeaderError {
+    /// The file is shorter than a header
+    TooShort(usize),
+    /// The file doesn't start with "NES\x1A"
+    BadMagic,
+    /// The file ends before the trainer, PRG-ROM and CHR-ROM the header describes
+    Truncated { expected: usize, actual: usize },
+    /// The NES 2.0 sizes add up to more than fits in memory
+    SizeOverflow,
+    /// There's no PRG-ROM, so nothing for the CPU to run
+    NoPrgRom,
+}
+
+impl fmt::Display for RomHeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomHeaderError::TooShort(size) => {
+                write!(f, "file is {} bytes, shorter than an iNES header", size)
+            }
+            RomHeaderError::BadMagic => write!(f, "file doesn't start with NES<EOF>"),
+            RomHeaderError::Truncated { expected, actual } => write!(
+                f,
+                "header describes {} bytes of data, but the file is {} bytes",
+                expected, actual
+            ),
+            RomHeaderError::SizeOverflow => write!(f, "header describes an impossibly large ROM"),
+            RomHeaderError::NoPrgRom => write!(f, "header describes no PRG-ROM"),
+        }
+    }
+}
+
+impl Error for RomHeaderError {}
+
+/// Everything the 16 byte iNES or NES 2.0 header says about the cartridge. Sizes are in bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomHeader {
+    pub format: HeaderFormat,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,
+    /// Battery-backed PRG-RAM
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub trainer: bool,
+    pub timing: Timing,
+    pub console_type: ConsoleType,
+    pub default_expansion_device: u8,
+}
+
+impl RomHeader {
+    /// Parses the header of an iNES file and checks that the file holds all the data it describes
+    pub fn parse(ines: &[u8]) -> Result<RomHeader, RomHeaderError> {
+        if ines.len() < HEADER_SIZE {
+            return Err(RomHeaderError::TooShort(ines.len()));
+        }
+        if &ines[..4] != MAGIC {
+            return Err(RomHeaderError::BadMagic);
+        }
+
+        let header = &ines[..HEADER_SIZE];
+        let flags6 = Flags6::from_bits(header[6]);
+        let flags7 = Flags7::from_bits(header[7]);
+        let mut rom_header = match flags7.nes2_identifier() {
+            2 => Self::parse_nes2(header)?,
+            0 if header[12..].iter().all(|&byte| byte == 0) => Self::parse_ines(header),
+            _ => Self::parse_archaic(header),
+        };
+        // Some iNES headers wrongly have the NES 2.0 identifier, trust the file size instead
+        if rom_header.format == HeaderFormat::Nes20
+            && rom_header.data_size().is_some_and(|size| size > ines.len())
+        {
+            rom_header = Self::parse_ines(header);
+        }
+
+        rom_header.trainer = flags6.trainer();
+        rom_header.battery = flags6.battery_backed_prg_ram();
+        rom_header.mirroring = match (
+            flags6.alt_nametable_layout(),
+            flags6.nametable_arrangement(),
+        ) {
+            (true, _) => Mirroring::FourScreen,
+            (false, NametableArrangement::VERTICAL) => Mirroring::Horizontal,
+            (false, NametableArrangement::HORIZONTAL) => Mirroring::Vertical,
+        };
+
+        if rom_header.prg_rom_size == 0 {
+            return Err(RomHeaderError::NoPrgRom);
+        }
+        let expected = rom_header.data_size().ok_or(RomHeaderError::SizeOverflow)?;
+        if expected > ines.len() {
+            return Err(RomHeaderError::Truncated {
+                expected,
+                actual: ines.len(),
+            });
+        }
+        Ok(rom_header)
+    }
+
+    /// Offset of PRG-ROM in the file
+    pub fn prg_rom_offset(&self) -> usize {
+        HEADER_SIZE + if self.trainer { TRAINER_SIZE } else { 0 }
+    }
+
+    /// Offset of CHR-ROM in the file. Can't overflow for a header `parse` accepted.
+    pub fn chr_rom_offset(&self) -> usize {
+        self.prg_rom_offset().saturating_add(self.prg_rom_size)
+    }
+
+    /// Size of the header and everything after it that the header describes, `None` if it
+    /// doesn't fit in a `usize`
+    fn data_size(&self) -> Option<usize> {
+        self.prg_rom_offset()
+            .checked_add(self.prg_rom_size)?
+            .checked_add(self.chr_rom_size)
+    }
+
+    fn parse_archaic(header: &[u8]) -> RomHeader {
+        RomHeader {
+            format: HeaderFormat::ArchaicINes,
+            mapper: Flags6::from_bits(header[6]).mapper_number_lower_nibble() as u16,
+            submapper: 0,
+            prg_rom_size: header[4] as usize * PRG_ROM_UNIT,
+            chr_rom_size: header[5] as usize * CHR_ROM_UNIT,
+            prg_ram_size: PRG_RAM_UNIT,
+            prg_nvram_size: 0,
+            chr_ram_size: 0,
+            chr_nvram_size: 0,
+            mirroring: Mirroring::Horizontal,
+            battery: false,
+            trainer: false,
+            timing: Timing::Ntsc,
+            console_type: ConsoleType::Nes,
+            default_expansion_device: 0,
+        }
+    }
+
+    fn parse_ines(header: &[u8]) -> RomHeader {
+        let flags7 = Flags7::from_bits(header[7]);
+        let mut rom_header = Self::parse_archaic(header);
+        rom_header.format = HeaderFormat::INes;
+        rom_header.mapper |= (flags7.mapper_number_upper_nibble() as u16) << 4;
+        // 0 means 8K for compatibility
+        rom_header.prg_ram_size = header[8].max(1) as usize * PRG_RAM_UNIT;
+        rom_header.timing = match header[9] & 1 {
+            0 => Timing::Ntsc,
+            _ => Timing::Pal,
+        };
+        rom_header.console_type = match flags7.console_type() {
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::Playchoice10,
+            _ => ConsoleType::Nes,
+        };
+        rom_header
+    }
+
+    fn parse_nes2(header: &[u8]) -> Result<RomHeader, RomHeaderError> {
+        let flags6 = Flags6::from_bits(header[6]);
+        let flags7 = Flags7::from_bits(header[7]);
+        let prg_rom_size = nes2_rom_size(header[4], header[9] & 0x0F, PRG_ROM_UNIT);
+        let chr_rom_size = nes2_rom_size(header[5], header[9] >> 4, CHR_ROM_UNIT);
+        let (Some(prg_rom_size), Some(chr_rom_size)) = (prg_rom_size, chr_rom_size) else {
+            return Err(RomHeaderError::SizeOverflow);
+        };
+        Ok(RomHeader {
+            format: HeaderFormat::Nes20,
+            mapper: flags6.mapper_number_lower_nibble() as u16
+                | (flags7.mapper_number_upper_nibble() as u16) << 4
+                | ((header[8] & 0x0F) as u16) << 8,
+            submapper: header[8] >> 4,
+            prg_rom_size,
+            chr_rom_size,
+            prg_ram_size: nes2_ram_size(header[10] & 0x0F),
+            prg_nvram_size: nes2_ram_size(header[10] >> 4),
+            chr_ram_size: nes2_ram_size(header[11] & 0x0F),
+            chr_nvram_size: nes2_ram_size(header[11] >> 4),
+            mirroring: Mirroring::Horizontal,
+            battery: false,
+            trainer: false,
+            timing: match header[12] & 3 {
+                0 => Timing::Ntsc,
+                1 => Timing::Pal,
+                2 => Timing::MultipleRegion,
+                _ => Timing::Dendy,
+            },
+            console_type: match flags7.console_type() {
+                0 => ConsoleType::Nes,
+                1 => ConsoleType::VsSystem,
+                2 => ConsoleType::Playchoice10,
+                _ => ConsoleType::Extended(header[13] & 0x0F),
+            },
+            default_expansion_device: header[15] & 0x3F,
+        })
+    }
+}
+
+/// ROM sizes are a 12 bit count of units, or 2^E * (M * 2 + 1) bytes when the MSB nibble is $F.
+/// `None` if the size doesn't fit in a `usize`.
+fn nes2_rom_size(lsb: u8, msb: u8, unit: usize) -> Option<usize> {
+    match msb {
+        0x0F => {
+            let exponent = (lsb >> 2) as u32;
+            let multiplier = (lsb & 3) as usize * 2 + 1;
+            2usize.checked_pow(exponent)?.checked_mul(multiplier)
+        }
+        _ => ((msb as usize) << 8 | lsb as usize).checked_mul(unit),
+    }
+}
+
+/// RAM sizes are 64 << shift bytes, or none for 0
+fn nes2_ram_size(shift: u8) -> usize {
+    match shift {
+        0 => 0,
+        shift => 64 << shift,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A file with `header` followed by `data_size` bytes of data
+    fn file(header: [u8; HEADER_SIZE], data_size: usize) -> Vec<u8> {
+        let mut file = header.to_vec();
+        file.resize(HEADER_SIZE + data_size, 0);
+        file
+    }
+
+    fn header(bytes: &[u8]) -> [u8; HEADER_SIZE] {
+        let mut header = [0; HEADER_SIZE];
+        header[..4].copy_from_slice(MAGIC);
+        header[4..4 + bytes.len()].copy_from_slice(bytes);
+        header
+    }
+
+    #[test]
+    fn parses_ines() {
+        // Mapper $14, vertical mirroring, battery, trainer, 16K of PRG-RAM, PAL
+        let ines = file(
+            header(&[2, 1, 0x47, 0x10, 2, 1]),
+            TRAINER_SIZE + 0x8000 + 0x2000,
+        );
+        let header = RomHeader::parse(&ines).unwrap();
+        assert_eq!(header.format, HeaderFormat::INes);
+        assert_eq!(header.mapper, 0x14);
+        assert_eq!(header.prg_rom_size, 0x8000);
+        assert_eq!(header.chr_rom_size, 0x2000);
+        assert_eq!(header.prg_ram_size, 0x4000);
+        assert_eq!(header.mirroring, Mirroring::Vertical);
+        assert!(header.battery);
+        assert!(header.trainer);
+        assert_eq!(header.timing, Timing::Pal);
+        assert_eq!(header.prg_rom_offset(), HEADER_SIZE + TRAINER_SIZE);
+        assert_eq!(header.chr_rom_offset(), HEADER_SIZE + TRAINER_SIZE + 0x8000);
+    }
+
+    #[test]
+    fn ines_prg_ram_defaults_to_8k() {
+        let ines = file(header(&[1, 0, 0x08]), 0x4000);
+        let header = RomHeader::parse(&ines).unwrap();
+        assert_eq!(header.prg_ram_size, 0x2000);
+        assert_eq!(header.mirroring, Mirroring::FourScreen);
+    }
+
+    #[test]
+    fn parses_nes2() {
+        // Mapper $104 submapper 2, 8K of PRG-RAM, 8K of battery-backed PRG-RAM, 32K of
+        // CHR-RAM, multi-region, extended console type 3
+        let ines = file(
+            header(&[1, 0, 0x40, 0x0B, 0x21, 0, 0x77, 0x09, 2, 3, 0, 0x23]),
+            0x4000,
+        );
+        let header = RomHeader::parse(&ines).unwrap();
+        assert_eq!(header.format, HeaderFormat::Nes20);
+        assert_eq!(header.mapper, 0x104);
+        assert_eq!(header.submapper, 2);
+        assert_eq!(header.prg_ram_size, 0x2000);
+        assert_eq!(header.prg_nvram_size, 0x2000);
+        assert_eq!(header.chr_ram_size, 0x8000);
+        assert_eq!(header.chr_nvram_size, 0);
+        assert_eq!(header.timing, Timing::MultipleRegion);
+        assert_eq!(header.console_type, ConsoleType::Extended(3));
+        assert_eq!(header.default_expansion_device, 0x23);
+    }
+
+    #[test]
+    fn parses_nes2_msb_and_exponent_sizes() {
+        // $101 units of PRG-ROM, and 2^2 * 3 = 12 bytes of CHR-ROM
+        let ines = file(header(&[0x01, 0x09, 0, 0x08, 0, 0xF1]), 0x101 * 0x4000 + 12);
+        let header = RomHeader::parse(&ines).unwrap();
+        assert_eq!(header.prg_rom_size, 0x101 * 0x4000);
+        assert_eq!(header.chr_rom_size, 12);
+    }
+
+    #[test]
+    fn rejects_overflowing_exponent_sizes() {
+        // 2^63 * 7 bytes of PRG-ROM
+        let ines = file(header(&[0xFF, 0, 0, 0x08, 0, 0x0F]), 0);
+        assert_eq!(RomHeader::parse(&ines), Err(RomHeaderError::SizeOverflow));
+        // 2^63 bytes of PRG-ROM and 2^63 bytes of CHR-ROM fit alone, but not together
+        let ines = file(header(&[0xFC, 0xFC, 0, 0x08, 0, 0xFF]), 0);
+        assert_eq!(RomHeader::parse(&ines), Err(RomHeaderError::SizeOverflow));
+        // 2^63 bytes fit in a usize, but not in the file
+        let ines = file(header(&[0xFC, 0, 0, 0x08, 0, 0x0F]), 0);
+        assert!(matches!(
+            RomHeader::parse(&ines),
+            Err(RomHeaderError::Truncated { .. })
+        ));
+    }
+
+    #[test]
+    fn falls_back_to_ines_when_nes2_sizes_exceed_the_file() {
+        // Byte 9 would make this 0x201 units of PRG-ROM
+        let ines = file(header(&[1, 0, 0, 0x08, 0, 2]), 0x4000);
+        let header = RomHeader::parse(&ines).unwrap();
+        assert_eq!(header.format, HeaderFormat::INes);
+        assert_eq!(header.prg_rom_size, 0x4000);
+    }
+
+    #[test]
+    fn parses_archaic_ines() {
+        let mut bytes = header(&[1, 1, 0x31, 0x40]);
+        bytes[7..].copy_from_slice(b"DiskDude!");
+        let ines = file(bytes, 0x6000);
+        let header = RomHeader::parse(&ines).unwrap();
+        assert_eq!(header.format, HeaderFormat::ArchaicINes);
+        // The upper nibble in byte 7 is garbage
+        assert_eq!(header.mapper, 3);
+        assert_eq!(header.mirroring, Mirroring::Vertical);
+        assert_eq!(header.timing, Timing::Ntsc);
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let ines = file(header(&[2, 1]), 0x8000);
+        assert_eq!(
+            RomHeader::parse(&ines),
+            Err(RomHeaderError::Truncated {
+                expected: HEADER_SIZE + 0xA000,
+                actual: HEADER_SIZE + 0x8000
+            })
+        );
+        // The trainer counts too
+        let ines = file(header(&[1, 0, 0x04]), 0x4000);
+        assert!(matches!(
+            RomHeader::parse(&ines),
+            Err(RomHeaderError::Truncated { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_empty_prg_rom() {
+        let ines = file(header(&[0, 1]), 0x2000);
+        assert_eq!(RomHeader::parse(&ines), Err(RomHeaderError::NoPrgRom));
+    }
+
+    #[test]
+    fn rejects_short_files_and_bad_magic() {
+        assert_eq!(
+            RomHeader::parse(&MAGIC[..]),
+            Err(RomHeaderError::TooShort(4))
+        );
+        let mut ines = file(header(&[1]), 0x4000);
+        ines[3] = 0;
+        assert_eq!(RomHeader::parse(&ines), Err(RomHeaderError::BadMagic));
+    }
+}
@@ -1,7 +1,8 @@
 pub mod addressing;
 pub mod logic;
+pub mod opcodes;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum InstructionType {
     Illegal,
     ADC,
@@ -60,9 +61,29 @@ pub enum InstructionType {
     TXA,
     TXS,
     TYA,
+    // Unofficial opcodes
+    ALR,
+    ANC,
+    ARR,
+    AXS,
+    DCP,
+    ISB,
+    KIL,
+    LAS,
+    LAX,
+    RLA,
+    RRA,
+    SAX,
+    SHA,
+    SHX,
+    SHY,
+    SLO,
+    SRE,
+    TAS,
+    XAA,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AddressingMode {
     Illegal,
     ZeroPageIndexedX,
@@ -80,14 +101,7 @@ pub enum AddressingMode {
     Indirect,
 }
 
-#[derive(Debug)]
-pub struct Instruction {
-    pub inst_type: InstructionType,
-    pub addr_mode: AddressingMode,
-}
-
-pub fn get_inst_type(byte: u8) -> InstructionType {
-    // TODO handle illegal instructions
+pub const fn get_inst_type(byte: u8) -> InstructionType {
     match byte & 0b11 {
         0 => {
             // red
@@ -138,6 +152,9 @@ pub fn get_inst_type(byte: u8) -> InstructionType {
                 0xEC => InstructionType::CPX,
                 0xF0 => InstructionType::BEQ,
                 0xF8 => InstructionType::SED,
+                0x9C => InstructionType::SHY,
+                0x04 | 0x0C | 0x14 | 0x1C | 0x34 | 0x3C | 0x44 | 0x54 | 0x5C | 0x64 | 0x74
+                | 0x7C | 0x80 | 0xD4 | 0xDC | 0xF4 | 0xFC => InstructionType::NOP,
                 _ => InstructionType::Illegal,
             }
         }
@@ -148,7 +165,10 @@ pub fn get_inst_type(byte: u8) -> InstructionType {
                 0x20 => InstructionType::AND,
                 0x40 => InstructionType::EOR,
                 0x60 => InstructionType::ADC,
-                0x80 => InstructionType::STA,
+                0x80 => match (byte & 0b00011100) >> 2 {
+                    2 => InstructionType::NOP,
+                    _ => InstructionType::STA,
+                },
                 0xA0 => InstructionType::LDA,
                 0xC0 => InstructionType::CMP,
                 0xE0 => InstructionType::SBC,
@@ -157,37 +177,53 @@ pub fn get_inst_type(byte: u8) -> InstructionType {
         }
         2 => {
             // blue
-            match byte & 0b11100000 {
-                0x00 => InstructionType::ASL,
-                0x20 => InstructionType::ROL,
-                0x40 => InstructionType::LSR,
-                0x60 => InstructionType::ROR,
-                0x80 => match (byte & 0b00011100) >> 2 {
-                    0 => InstructionType::NOP,
-                    1 | 3 | 5 => InstructionType::STX,
-                    2 => InstructionType::TXA,
-                    6 => InstructionType::TXS,
-                    _ => InstructionType::Illegal,
-                },
-                0xA0 => match (byte & 0b00011100) >> 2 {
-                    2 => InstructionType::TAX,
-                    6 => InstructionType::TSX,
-                    _ => InstructionType::LDX,
-                },
-                0xC0 => match (byte & 0b00011100) >> 2 {
-                    2 => InstructionType::DEX,
-                    _ => InstructionType::DEC,
-                },
-                0xE0 => match (byte & 0b00011100) >> 2 {
-                    2 => InstructionType::NOP,
-                    _ => InstructionType::LDX,
-                },
+            match (byte & 0b11100000, (byte & 0b00011100) >> 2) {
+                (0x00..=0x60, 0 | 4) => InstructionType::KIL,
+                (0x00..=0x60, 6) => InstructionType::NOP,
+                (0x00, _) => InstructionType::ASL,
+                (0x20, _) => InstructionType::ROL,
+                (0x40, _) => InstructionType::LSR,
+                (0x60, _) => InstructionType::ROR,
+                (0x80, 0) => InstructionType::NOP,
+                (0x80, 2) => InstructionType::TXA,
+                (0x80, 4) => InstructionType::KIL,
+                (0x80, 6) => InstructionType::TXS,
+                (0x80, 7) => InstructionType::SHX,
+                (0x80, _) => InstructionType::STX,
+                (0xA0, 2) => InstructionType::TAX,
+                (0xA0, 4) => InstructionType::KIL,
+                (0xA0, 6) => InstructionType::TSX,
+                (0xA0, _) => InstructionType::LDX,
+                (0xC0, 2) => InstructionType::DEX,
+                (0xC0, 4) => InstructionType::KIL,
+                (0xC0, 0 | 6) => InstructionType::NOP,
+                (0xC0, _) => InstructionType::DEC,
+                (0xE0, 4) => InstructionType::KIL,
+                (0xE0, 0 | 2 | 6) => InstructionType::NOP,
+                (0xE0, _) => InstructionType::INC,
                 _ => InstructionType::Illegal,
             }
         }
         3 => {
-            // gray
-            match byte & 0b11100000 {
+            // gray, all of these are unofficial
+            match (byte & 0b11100000, (byte & 0b00011100) >> 2) {
+                (0x00 | 0x20, 2) => InstructionType::ANC,
+                (0x40, 2) => InstructionType::ALR,
+                (0x60, 2) => InstructionType::ARR,
+                (0x80, 2) => InstructionType::XAA,
+                (0xC0, 2) => InstructionType::AXS,
+                (0xE0, 2) => InstructionType::SBC,
+                (0x00, _) => InstructionType::SLO,
+                (0x20, _) => InstructionType::RLA,
+                (0x40, _) => InstructionType::SRE,
+                (0x60, _) => InstructionType::RRA,
+                (0x80, 4 | 7) => InstructionType::SHA,
+                (0x80, 6) => InstructionType::TAS,
+                (0x80, _) => InstructionType::SAX,
+                (0xA0, 6) => InstructionType::LAS,
+                (0xA0, _) => InstructionType::LAX,
+                (0xC0, _) => InstructionType::DCP,
+                (0xE0, _) => InstructionType::ISB,
                 _ => InstructionType::Illegal,
             }
         }
@@ -195,13 +231,14 @@ pub fn get_inst_type(byte: u8) -> InstructionType {
     }
 }
 
-pub fn get_addr_mode(byte: u8) -> AddressingMode {
+pub const fn get_addr_mode(byte: u8) -> AddressingMode {
     match byte & 0b11 {
         0 => {
             // red
             match (byte & 0b00011100) >> 2 {
+                // BRK skips the byte after it, but that's padding, not an operand
                 0 => match byte & 0b11100000 {
-                    0x10 | 0x40 | 0x60 => AddressingMode::Implicit,
+                    0x00 | 0x40 | 0x60 => AddressingMode::Implicit,
                     0x20 => AddressingMode::Absolute,
                     _ => AddressingMode::Immediate,
                 },
@@ -221,12 +258,12 @@ pub fn get_addr_mode(byte: u8) -> AddressingMode {
         1 => {
             // green
             match (byte & 0b00011100) >> 2 {
-                0 => AddressingMode::ZeroPageIndexedX,
+                0 => AddressingMode::IndexedIndirect,
                 1 => AddressingMode::ZeroPage,
                 2 => AddressingMode::Immediate,
                 3 => AddressingMode::Absolute,
                 4 => AddressingMode::IndirectIndexed,
-                5 => AddressingMode::IndexedIndirect,
+                5 => AddressingMode::ZeroPageIndexedX,
                 6 => AddressingMode::AbsoluteIndexedY,
                 7 => AddressingMode::AbsoluteIndexedX,
                 _ => AddressingMode::Illegal,
@@ -235,7 +272,10 @@ pub fn get_addr_mode(byte: u8) -> AddressingMode {
         2 => {
             // blue
             match (byte & 0b00011100) >> 2 {
-                0 => AddressingMode::Immediate,
+                0 => match byte & 0b11100000 {
+                    0x00..=0x60 => AddressingMode::Implicit,
+                    _ => AddressingMode::Immediate,
+                },
                 1 => AddressingMode::ZeroPage,
                 2 => match byte & 0b11100000 {
                     0x00..=0x60 => AddressingMode::Accumulator,
@@ -243,18 +283,35 @@ pub fn get_addr_mode(byte: u8) -> AddressingMode {
                 },
                 3 => AddressingMode::Absolute,
                 4 => AddressingMode::Implicit,
-                5 | 7 => match byte & 0b11100000 {
-                    0x00..=0x60 | 0xC0..=0xE0 => AddressingMode::ZeroPageIndexedX,
+                5 => match byte & 0b11100000 {
                     0x80 | 0xA0 => AddressingMode::ZeroPageIndexedY,
-                    _ => AddressingMode::Illegal,
+                    _ => AddressingMode::ZeroPageIndexedX,
                 },
                 6 => AddressingMode::Implicit,
+                7 => match byte & 0b11100000 {
+                    0x80 | 0xA0 => AddressingMode::AbsoluteIndexedY,
+                    _ => AddressingMode::AbsoluteIndexedX,
+                },
                 _ => AddressingMode::Illegal,
             }
         }
         3 => {
-            // gray
-            match byte & 0b11100000 {
+            // gray, same as green except that SAX/LAX and friends index with Y instead of X
+            match (byte & 0b00011100) >> 2 {
+                0 => AddressingMode::IndexedIndirect,
+                1 => AddressingMode::ZeroPage,
+                2 => AddressingMode::Immediate,
+                3 => AddressingMode::Absolute,
+                4 => AddressingMode::IndirectIndexed,
+                5 => match byte & 0b11100000 {
+                    0x80 | 0xA0 => AddressingMode::ZeroPageIndexedY,
+                    _ => AddressingMode::ZeroPageIndexedX,
+                },
+                6 => AddressingMode::AbsoluteIndexedY,
+                7 => match byte & 0b11100000 {
+                    0x80 | 0xA0 => AddressingMode::AbsoluteIndexedY,
+                    _ => AddressingMode::AbsoluteIndexedX,
+                },
                 _ => AddressingMode::Illegal,
             }
         }
@@ -262,7 +319,7 @@ pub fn get_addr_mode(byte: u8) -> AddressingMode {
     }
 }
 
-pub fn get_num_of_operands(addr_mode: &AddressingMode) -> usize {
+pub const fn get_num_of_operands(addr_mode: &AddressingMode) -> usize {
     match addr_mode {
         AddressingMode::Illegal => 0,
         AddressingMode::ZeroPageIndexedX => 1,
@@ -280,3 +337,40 @@ pub fn get_num_of_operands(addr_mode: &AddressingMode) -> usize {
         AddressingMode::Indirect => 2,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::opcodes::OPCODES;
+    use super::*;
+    use crate::nes::{Powerable, NES};
+    use crate::test_rom::TestRom;
+
+    #[test]
+    fn brk_rti_and_rts_are_implied() {
+        for byte in [0x00, 0x40, 0x60] {
+            assert_eq!(get_addr_mode(byte), AddressingMode::Implicit);
+            assert_eq!(OPCODES[byte as usize].size(), 1);
+        }
+        assert_eq!(get_addr_mode(0x20), AddressingMode::Absolute);
+        assert_eq!(get_addr_mode(0xA0), AddressingMode::Immediate);
+    }
+
+    #[test]
+    fn brk_still_skips_its_padding_byte() {
+        let ines = TestRom::new(2)
+            .code(0x8000, &[0x00])
+            .irq_vector(0x9000)
+            .build();
+        let mut nes = NES::default();
+        nes.power_on();
+        nes.load_rom(ines).unwrap();
+        while nes.cpu().reg_pc != 0x8000 {
+            nes.step_instruction().unwrap();
+        }
+        nes.step_instruction().unwrap();
+        assert_eq!(nes.cpu().reg_pc, 0x9000);
+        let s = nes.cpu().reg_s as u16;
+        let return_address = [2, 3].map(|i| nes.peek(0x0100 + s + i));
+        assert_eq!(return_address, [0x02, 0x80]);
+    }
+}
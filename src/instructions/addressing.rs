@@ -1,87 +1,291 @@
-use crate::{cpu::CPU, utils::build_u16};
+use crate::{
+    cpu::{MicroOp, CPU},
+    interconnect::Interconnect,
+    utils::{build_u16, get_lsb},
+};
 
-use super::{AddressingMode, InstructionType};
+use super::logic::{branch_1, branch_2};
+use super::AddressingMode;
 
+#[derive(Clone, Copy)]
 pub enum MemoryOp {
     Read,
     Write,
+    ReadModifyWrite,
 }
 
-pub fn read_mem(cpu: &mut CPU) {
-    cpu.value = cpu.ic.read_mem(cpu.addr);
-}
-
-pub fn write_mem(cpu: &mut CPU) {
-    cpu.ic.write_mem(cpu.addr, cpu.write);
-}
-
-pub fn queue_push_memory_op(cpu: &mut CPU, op: MemoryOp) {
-    let Some(inst) = cpu.curr_inst.as_mut() else {
-        panic!("No instruction");
-    };
-    // TODO make cycle accurate   
-    match inst.addr_mode {
-        AddressingMode::Illegal => panic!("Illegal addressing mode"),
-        AddressingMode::ZeroPageIndexedX => {
-            cpu.addr = cpu.operands[0].wrapping_add(cpu.reg_x) as u16;
-        }
-        AddressingMode::ZeroPageIndexedY => {
-            cpu.addr = cpu.operands[0].wrapping_add(cpu.reg_y) as u16;
-        }
-        AddressingMode::AbsoluteIndexedX => {
-            cpu.addr = build_u16(cpu.operands[1], cpu.operands[0] + cpu.reg_x);
-        }
-        AddressingMode::AbsoluteIndexedY => {
-            cpu.addr = build_u16(cpu.operands[1], cpu.operands[0] + cpu.reg_y);
-        }
-        AddressingMode::IndexedIndirect => {
-            cpu.addr = build_u16(
-                cpu.ic
-                    .read_mem(cpu.operands[0].wrapping_add(cpu.reg_x).wrapping_add(1) as u16),
-                cpu.ic
-                    .read_mem(cpu.operands[0].wrapping_add(cpu.reg_x) as u16),
-            )
-        }
-        AddressingMode::IndirectIndexed => {
-            cpu.addr = cpu.ic.read_mem(cpu.operands[0] as u16) as u16
-                + cpu.ic.read_mem(cpu.operands[0].wrapping_add(1) as u16) as u16 * 256
-                + cpu.reg_y as u16
-        }
-        AddressingMode::Implicit => {}
-        AddressingMode::Accumulator => {
-            cpu.value = cpu.reg_a;
-        }
-        AddressingMode::Immediate => {
-            cpu.value = cpu.operands[0];
-        }
-        AddressingMode::ZeroPage => {
-            cpu.addr = cpu.operands[0] as u16;
-        }
-        AddressingMode::Absolute => {
-            cpu.addr = build_u16(cpu.operands[1], cpu.operands[0]);
-        }
-        AddressingMode::Relative => {
-            cpu.addr = (cpu.reg_pc as i32 + cpu.operands[0] as i8 as i32) as u16;
-        }
-        AddressingMode::Indirect => {
-            cpu.addr = cpu
-                .ic
-                .read_mem_word(build_u16(cpu.operands[1], cpu.operands[0]));
-        }
-    };
-    if matches!(op, MemoryOp::Read) {
-        match inst.addr_mode {
-            AddressingMode::Immediate
-            | AddressingMode::Accumulator
-            | AddressingMode::Implicit
-            | AddressingMode::Relative => {}
-            _ => {
-                if !matches!(inst.inst_type, InstructionType::JMP) {
-                    cpu.inst_queue.push_back((read_mem, 1));
-                }
-            }
-        };
+pub fn dummy_read_pc(cpu: &mut CPU, bus: &mut Interconnect) {
+    bus.read_mem(cpu.reg_pc);
+}
+
+pub fn dummy_read_stack(cpu: &mut CPU, bus: &mut Interconnect) {
+    bus.read_mem(0x100 + cpu.reg_s as u16);
+}
+
+/// Fetches a zero page address, a pointer or the low byte of an absolute address
+pub fn fetch_operand(cpu: &mut CPU, bus: &mut Interconnect) {
+    cpu.addr = bus.read_mem(cpu.reg_pc) as u16;
+    cpu.reg_pc = cpu.reg_pc.wrapping_add(1);
+}
+
+pub fn fetch_operand_msb(cpu: &mut CPU, bus: &mut Interconnect) {
+    let msb = bus.read_mem(cpu.reg_pc);
+    cpu.reg_pc = cpu.reg_pc.wrapping_add(1);
+    cpu.addr = build_u16(msb, get_lsb(cpu.addr));
+}
+
+/// The index is added to the low byte only, the carry into the high byte takes another cycle
+fn add_index_to_lsb(cpu: &mut CPU, msb: u8, index: u8) {
+    let (lsb, page_crossed) = get_lsb(cpu.addr).overflowing_add(index);
+    cpu.addr = build_u16(msb, lsb);
+    cpu.page_crossed = page_crossed;
+}
+
+pub fn fetch_operand_msb_x(cpu: &mut CPU, bus: &mut Interconnect) {
+    let msb = bus.read_mem(cpu.reg_pc);
+    cpu.reg_pc = cpu.reg_pc.wrapping_add(1);
+    add_index_to_lsb(cpu, msb, cpu.reg_x);
+}
+
+pub fn fetch_operand_msb_y(cpu: &mut CPU, bus: &mut Interconnect) {
+    let msb = bus.read_mem(cpu.reg_pc);
+    cpu.reg_pc = cpu.reg_pc.wrapping_add(1);
+    add_index_to_lsb(cpu, msb, cpu.reg_y);
+}
+
+pub fn index_zero_page_x(cpu: &mut CPU, bus: &mut Interconnect) {
+    // Dummy read of the unindexed address
+    bus.read_mem(cpu.addr);
+    cpu.addr = get_lsb(cpu.addr).wrapping_add(cpu.reg_x) as u16;
+}
+
+pub fn index_zero_page_y(cpu: &mut CPU, bus: &mut Interconnect) {
+    // Dummy read of the unindexed address
+    bus.read_mem(cpu.addr);
+    cpu.addr = get_lsb(cpu.addr).wrapping_add(cpu.reg_y) as u16;
+}
+
+pub fn fetch_pointer_lsb(cpu: &mut CPU, bus: &mut Interconnect) {
+    cpu.pointer = get_lsb(cpu.addr);
+    cpu.addr = bus.read_mem(cpu.pointer as u16) as u16;
+}
+
+/// The pointer wraps around within the zero page
+pub fn fetch_pointer_msb(cpu: &mut CPU, bus: &mut Interconnect) {
+    let msb = bus.read_mem(cpu.pointer.wrapping_add(1) as u16);
+    cpu.addr = build_u16(msb, get_lsb(cpu.addr));
+}
+
+pub fn fetch_pointer_msb_y(cpu: &mut CPU, bus: &mut Interconnect) {
+    let msb = bus.read_mem(cpu.pointer.wrapping_add(1) as u16);
+    add_index_to_lsb(cpu, msb, cpu.reg_y);
+}
+
+/// Reads from the address with the unfixed high byte, writes and read-modify-writes always
+/// take this extra cycle
+pub fn fix_page(cpu: &mut CPU, bus: &mut Interconnect) {
+    bus.read_mem(cpu.addr);
+    if cpu.page_crossed {
+        cpu.addr = cpu.addr.wrapping_add(0x100);
+    }
+}
+
+/// Reads skip the extra cycle if the indexing didn't cross a page
+pub fn read_fix_page_execute(cpu: &mut CPU, bus: &mut Interconnect) {
+    cpu.value = bus.read_mem(cpu.addr);
+    if cpu.page_crossed {
+        cpu.addr = cpu.addr.wrapping_add(0x100);
     } else {
-        cpu.inst_queue.push_back((write_mem, 1));
+        cpu.execute();
+        cpu.end_instruction();
+    }
+}
+
+pub fn read_execute(cpu: &mut CPU, bus: &mut Interconnect) {
+    cpu.value = bus.read_mem(cpu.addr);
+    cpu.execute();
+}
+
+pub fn read_immediate_execute(cpu: &mut CPU, bus: &mut Interconnect) {
+    cpu.value = bus.read_mem(cpu.reg_pc);
+    cpu.reg_pc = cpu.reg_pc.wrapping_add(1);
+    cpu.execute();
+}
+
+pub fn implied_execute(cpu: &mut CPU, bus: &mut Interconnect) {
+    dummy_read_pc(cpu, bus);
+    cpu.execute();
+}
+
+pub fn accumulator_execute(cpu: &mut CPU, bus: &mut Interconnect) {
+    dummy_read_pc(cpu, bus);
+    cpu.value = cpu.reg_a;
+    cpu.execute();
+    cpu.reg_a = cpu.value;
+}
+
+pub fn read_value(cpu: &mut CPU, bus: &mut Interconnect) {
+    cpu.value = bus.read_mem(cpu.addr);
+}
+
+/// Read-modify-write instructions write back the unmodified value while they compute the
+/// result
+pub fn dummy_write_value(cpu: &mut CPU, bus: &mut Interconnect) {
+    bus.write_mem(cpu.addr, cpu.value);
+}
+
+pub fn execute_write(cpu: &mut CPU, bus: &mut Interconnect) {
+    cpu.execute();
+    bus.write_mem(cpu.addr, cpu.value);
+}
+
+// Micro-op programs of the regular instructions, everything after the opcode fetch
+const IMPLIED: &[MicroOp] = &[implied_execute];
+const ACCUMULATOR: &[MicroOp] = &[accumulator_execute];
+const IMMEDIATE: &[MicroOp] = &[read_immediate_execute];
+const RELATIVE: &[MicroOp] = &[read_immediate_execute, branch_1, branch_2];
+
+const READ_ZERO_PAGE: &[MicroOp] = &[fetch_operand, read_execute];
+const READ_ZERO_PAGE_X: &[MicroOp] = &[fetch_operand, index_zero_page_x, read_execute];
+const READ_ZERO_PAGE_Y: &[MicroOp] = &[fetch_operand, index_zero_page_y, read_execute];
+const READ_ABSOLUTE: &[MicroOp] = &[fetch_operand, fetch_operand_msb, read_execute];
+const READ_ABSOLUTE_X: &[MicroOp] = &[
+    fetch_operand,
+    fetch_operand_msb_x,
+    read_fix_page_execute,
+    read_execute,
+];
+const READ_ABSOLUTE_Y: &[MicroOp] = &[
+    fetch_operand,
+    fetch_operand_msb_y,
+    read_fix_page_execute,
+    read_execute,
+];
+const READ_INDEXED_INDIRECT: &[MicroOp] = &[
+    fetch_operand,
+    index_zero_page_x,
+    fetch_pointer_lsb,
+    fetch_pointer_msb,
+    read_execute,
+];
+const READ_INDIRECT_INDEXED: &[MicroOp] = &[
+    fetch_operand,
+    fetch_pointer_lsb,
+    fetch_pointer_msb_y,
+    read_fix_page_execute,
+    read_execute,
+];
+
+const WRITE_ZERO_PAGE: &[MicroOp] = &[fetch_operand, execute_write];
+const WRITE_ZERO_PAGE_X: &[MicroOp] = &[fetch_operand, index_zero_page_x, execute_write];
+const WRITE_ZERO_PAGE_Y: &[MicroOp] = &[fetch_operand, index_zero_page_y, execute_write];
+const WRITE_ABSOLUTE: &[MicroOp] = &[fetch_operand, fetch_operand_msb, execute_write];
+const WRITE_ABSOLUTE_X: &[MicroOp] = &[fetch_operand, fetch_operand_msb_x, fix_page, execute_write];
+const WRITE_ABSOLUTE_Y: &[MicroOp] = &[fetch_operand, fetch_operand_msb_y, fix_page, execute_write];
+const WRITE_INDEXED_INDIRECT: &[MicroOp] = &[
+    fetch_operand,
+    index_zero_page_x,
+    fetch_pointer_lsb,
+    fetch_pointer_msb,
+    execute_write,
+];
+const WRITE_INDIRECT_INDEXED: &[MicroOp] = &[
+    fetch_operand,
+    fetch_pointer_lsb,
+    fetch_pointer_msb_y,
+    fix_page,
+    execute_write,
+];
+
+const RMW_ZERO_PAGE: &[MicroOp] = &[fetch_operand, read_value, dummy_write_value, execute_write];
+const RMW_ZERO_PAGE_X: &[MicroOp] = &[
+    fetch_operand,
+    index_zero_page_x,
+    read_value,
+    dummy_write_value,
+    execute_write,
+];
+const RMW_ZERO_PAGE_Y: &[MicroOp] = &[
+    fetch_operand,
+    index_zero_page_y,
+    read_value,
+    dummy_write_value,
+    execute_write,
+];
+const RMW_ABSOLUTE: &[MicroOp] = &[
+    fetch_operand,
+    fetch_operand_msb,
+    read_value,
+    dummy_write_value,
+    execute_write,
+];
+const RMW_ABSOLUTE_X: &[MicroOp] = &[
+    fetch_operand,
+    fetch_operand_msb_x,
+    fix_page,
+    read_value,
+    dummy_write_value,
+    execute_write,
+];
+const RMW_ABSOLUTE_Y: &[MicroOp] = &[
+    fetch_operand,
+    fetch_operand_msb_y,
+    fix_page,
+    read_value,
+    dummy_write_value,
+    execute_write,
+];
+const RMW_INDEXED_INDIRECT: &[MicroOp] = &[
+    fetch_operand,
+    index_zero_page_x,
+    fetch_pointer_lsb,
+    fetch_pointer_msb,
+    read_value,
+    dummy_write_value,
+    execute_write,
+];
+const RMW_INDIRECT_INDEXED: &[MicroOp] = &[
+    fetch_operand,
+    fetch_pointer_lsb,
+    fetch_pointer_msb_y,
+    fix_page,
+    read_value,
+    dummy_write_value,
+    execute_write,
+];
+
+/// Returns the cycles an instruction spends on addressing and accessing memory
+pub const fn program(addr_mode: AddressingMode, op: MemoryOp) -> &'static [MicroOp] {
+    match (addr_mode, op) {
+        (AddressingMode::Implicit, _) => IMPLIED,
+        (AddressingMode::Accumulator, _) => ACCUMULATOR,
+        (AddressingMode::Immediate, _) => IMMEDIATE,
+        (AddressingMode::Relative, _) => RELATIVE,
+        (AddressingMode::ZeroPage, MemoryOp::Read) => READ_ZERO_PAGE,
+        (AddressingMode::ZeroPageIndexedX, MemoryOp::Read) => READ_ZERO_PAGE_X,
+        (AddressingMode::ZeroPageIndexedY, MemoryOp::Read) => READ_ZERO_PAGE_Y,
+        (AddressingMode::Absolute, MemoryOp::Read) => READ_ABSOLUTE,
+        (AddressingMode::AbsoluteIndexedX, MemoryOp::Read) => READ_ABSOLUTE_X,
+        (AddressingMode::AbsoluteIndexedY, MemoryOp::Read) => READ_ABSOLUTE_Y,
+        (AddressingMode::IndexedIndirect, MemoryOp::Read) => READ_INDEXED_INDIRECT,
+        (AddressingMode::IndirectIndexed, MemoryOp::Read) => READ_INDIRECT_INDEXED,
+        (AddressingMode::ZeroPage, MemoryOp::Write) => WRITE_ZERO_PAGE,
+        (AddressingMode::ZeroPageIndexedX, MemoryOp::Write) => WRITE_ZERO_PAGE_X,
+        (AddressingMode::ZeroPageIndexedY, MemoryOp::Write) => WRITE_ZERO_PAGE_Y,
+        (AddressingMode::Absolute, MemoryOp::Write) => WRITE_ABSOLUTE,
+        (AddressingMode::AbsoluteIndexedX, MemoryOp::Write) => WRITE_ABSOLUTE_X,
+        (AddressingMode::AbsoluteIndexedY, MemoryOp::Write) => WRITE_ABSOLUTE_Y,
+        (AddressingMode::IndexedIndirect, MemoryOp::Write) => WRITE_INDEXED_INDIRECT,
+        (AddressingMode::IndirectIndexed, MemoryOp::Write) => WRITE_INDIRECT_INDEXED,
+        (AddressingMode::ZeroPage, MemoryOp::ReadModifyWrite) => RMW_ZERO_PAGE,
+        (AddressingMode::ZeroPageIndexedX, MemoryOp::ReadModifyWrite) => RMW_ZERO_PAGE_X,
+        (AddressingMode::ZeroPageIndexedY, MemoryOp::ReadModifyWrite) => RMW_ZERO_PAGE_Y,
+        (AddressingMode::Absolute, MemoryOp::ReadModifyWrite) => RMW_ABSOLUTE,
+        (AddressingMode::AbsoluteIndexedX, MemoryOp::ReadModifyWrite) => RMW_ABSOLUTE_X,
+        (AddressingMode::AbsoluteIndexedY, MemoryOp::ReadModifyWrite) => RMW_ABSOLUTE_Y,
+        (AddressingMode::IndexedIndirect, MemoryOp::ReadModifyWrite) => RMW_INDEXED_INDIRECT,
+        (AddressingMode::IndirectIndexed, MemoryOp::ReadModifyWrite) => RMW_INDIRECT_INDEXED,
+        (AddressingMode::Indirect, _) => panic!("Only JMP uses indirect addressing"),
+        (AddressingMode::Illegal, _) => panic!("Illegal addressing mode"),
     }
 }
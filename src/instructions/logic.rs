@@ -1,27 +1,69 @@
 use crate::{
-    cpu::{Status, CPU, IRQ_VECTOR_ADDR},
-    utils::{get_lsb, get_msb},
+    cpu::{JamPolicy, Status, CPU},
+    interconnect::Interconnect,
+    utils::{build_u16, get_lsb, get_msb},
 };
 
-use super::AddressingMode;
+use super::addressing::dummy_read_pc;
+
+// Unstable opcodes OR the accumulator with a chip dependent value before using it
+const UNSTABLE_MAGIC_XAA: u8 = 0xEE;
+const UNSTABLE_MAGIC_LAX: u8 = 0xFF;
 
 fn set_register_with_flags(reg: &mut u8, status: &mut Status, value: u8) {
-    // TODO maybe extract the first line so that this function becomes more universal
     *reg = value;
-    status.set_zero(*reg == 0);
-    status.set_negative(*reg & 0b10000000 != 0);
+    set_flags(status, value);
+}
+
+fn set_flags(status: &mut Status, value: u8) {
+    status.set_zero(value == 0);
+    status.set_negative(value & 0b10000000 != 0);
 }
 
-fn handle_successful_branching(cpu: &mut CPU) {
-    cpu.inst_queue.push_back((nop, 1));
-    if cpu.reg_pc & 0xFF00 != cpu.addr & 0xFF00 {
-        // new page
-        cpu.inst_queue.push_back((nop, 1));
+fn compare(status: &mut Status, reg: u8, value: u8) {
+    let res = reg.wrapping_sub(value);
+    status.set_carry(reg >= value);
+    status.set_zero(res == 0);
+    status.set_negative(res & 0b10000000 != 0);
+}
+
+/// The SHA/SHX/SHY/TAS family stores `value & (H + 1)`, H being the high byte of the address
+/// before indexing. If indexing crossed a page the result also replaces the high byte of the
+/// address that gets written to.
+fn and_high_byte(cpu: &mut CPU, value: u8, index: u8) {
+    let base = cpu.addr.wrapping_sub(index as u16);
+    cpu.value = value & get_msb(base).wrapping_add(1);
+    if get_msb(base) != get_msb(cpu.addr) {
+        cpu.addr = build_u16(cpu.value, get_lsb(cpu.addr));
     }
+}
+
+fn branch_if(cpu: &mut CPU, condition: bool) {
+    if !condition {
+        cpu.end_instruction();
+    }
+}
+
+/// Taken branches add the offset to the low byte of PC
+pub fn branch_1(cpu: &mut CPU, bus: &mut Interconnect) {
+    bus.read_mem(cpu.reg_pc);
+    let target = cpu.reg_pc.wrapping_add(cpu.value as i8 as u16);
+    cpu.reg_pc = build_u16(get_msb(cpu.reg_pc), get_lsb(target));
+    if cpu.reg_pc != target {
+        cpu.addr = target;
+    } else {
+        cpu.ignore_new_irq();
+        cpu.end_instruction();
+    }
+}
+
+/// The high byte is fixed up in an extra cycle if a page was crossed
+pub fn branch_2(cpu: &mut CPU, bus: &mut Interconnect) {
+    bus.read_mem(cpu.reg_pc);
     cpu.reg_pc = cpu.addr;
 }
 
-pub fn adc_1(cpu: &mut CPU) {
+pub fn adc(cpu: &mut CPU) {
     let carry_6 =
         (((cpu.reg_a & 0b01111111) + (cpu.value & 0b01111111) + cpu.status.carry() as u8)
             & 0b10000000)
@@ -34,331 +76,276 @@ pub fn adc_1(cpu: &mut CPU) {
     cpu.status.set_zero(sum == 0);
     cpu.status.set_overflow(carry_6 ^ overflow);
     cpu.status.set_negative(sum & 0b10000000 != 0);
-    cpu.reg_a = sum as u8;
+    cpu.reg_a = sum;
 }
 
-pub fn and_1(cpu: &mut CPU) {
+pub fn and(cpu: &mut CPU) {
     let anded = cpu.reg_a & cpu.value;
     set_register_with_flags(&mut cpu.reg_a, &mut cpu.status, anded);
 }
 
-pub fn asl_1(cpu: &mut CPU) {
-    match cpu.curr_inst.as_mut().expect("No instruction").addr_mode {
-        AddressingMode::Accumulator => {
-            let old_bit_7 = cpu.reg_a & 0b10000000 != 0;
-            cpu.reg_a = cpu.reg_a.wrapping_shl(1);
-            cpu.status.set_carry(old_bit_7);
-            cpu.status.set_zero(cpu.reg_a == 0);
-            cpu.status.set_negative(cpu.reg_a & 0b10000000 != 0);
-        }
-        _ => {
-            let old_bit_7 = cpu.value & 0b10000000 != 0;
-            let rot = cpu.value.wrapping_shl(1);
-            // TODO fix for cycle accuracy
-            cpu.ic.write_mem(cpu.addr, rot);
-            cpu.status.set_carry(old_bit_7);
-            cpu.status.set_zero(cpu.reg_a == 0); // TODO is this right?
-            cpu.status.set_negative(rot & 0b10000000 != 0);
-        }
-    }
+pub fn asl(cpu: &mut CPU) {
+    cpu.status.set_carry(cpu.value & 0b10000000 != 0);
+    cpu.value = cpu.value.wrapping_shl(1);
+    set_flags(&mut cpu.status, cpu.value);
 }
 
-pub fn bcc_1(cpu: &mut CPU) {
-    if !cpu.status.carry() {
-        handle_successful_branching(cpu);
-    }
+pub fn bcc(cpu: &mut CPU) {
+    branch_if(cpu, !cpu.status.carry());
 }
 
-pub fn bcs_1(cpu: &mut CPU) {
-    if cpu.status.carry() {
-        handle_successful_branching(cpu);
-    }
+pub fn bcs(cpu: &mut CPU) {
+    branch_if(cpu, cpu.status.carry());
 }
 
-pub fn beq_1(cpu: &mut CPU) {
-    if cpu.status.zero() {
-        handle_successful_branching(cpu);
-    }
+pub fn beq(cpu: &mut CPU) {
+    branch_if(cpu, cpu.status.zero());
 }
 
-pub fn bit_1(cpu: &mut CPU) {
+pub fn bit(cpu: &mut CPU) {
     cpu.status.set_zero(cpu.reg_a & cpu.value == 0);
     cpu.status.set_overflow(cpu.value & 0b01000000 != 0);
     cpu.status.set_negative(cpu.value & 0b10000000 != 0);
 }
 
-pub fn bmi_1(cpu: &mut CPU) {
-    if cpu.status.negative() {
-        handle_successful_branching(cpu);
-    }
+pub fn bmi(cpu: &mut CPU) {
+    branch_if(cpu, cpu.status.negative());
 }
 
-pub fn bne_1(cpu: &mut CPU) {
-    if !cpu.status.zero() {
-        handle_successful_branching(cpu);
-    }
+pub fn bne(cpu: &mut CPU) {
+    branch_if(cpu, !cpu.status.zero());
 }
 
-pub fn bpl_1(cpu: &mut CPU) {
-    if !cpu.status.negative() {
-        handle_successful_branching(cpu);
-    }
+pub fn bpl(cpu: &mut CPU) {
+    branch_if(cpu, !cpu.status.negative());
 }
 
-pub fn brk_1(cpu: &mut CPU) {
-    cpu.status.set_b(true);
-    cpu.push_to_stack(get_msb(cpu.reg_pc));
+pub fn brk_1(cpu: &mut CPU, bus: &mut Interconnect) {
+    cpu.push_to_stack(bus, get_msb(cpu.reg_pc));
 }
 
-pub fn brk_2(cpu: &mut CPU) {
-    cpu.push_to_stack(get_lsb(cpu.reg_pc));
+pub fn brk_2(cpu: &mut CPU, bus: &mut Interconnect) {
+    cpu.push_to_stack(bus, get_lsb(cpu.reg_pc));
 }
 
-pub fn brk_3(cpu: &mut CPU) {
-    cpu.push_to_stack(cpu.status.into_bits());
+pub fn brk_3(cpu: &mut CPU, bus: &mut Interconnect) {
+    // B only exists in the copy of P that gets pushed
+    cpu.push_to_stack(bus, cpu.status.with_b(true).with_one(true).into_bits());
+    cpu.select_interrupt_vector();
 }
 
-pub fn brk_4(cpu: &mut CPU) {
-    cpu.reg_pc = cpu.ic.read_mem_word(IRQ_VECTOR_ADDR);
+pub fn brk_4(cpu: &mut CPU, bus: &mut Interconnect) {
+    let lsb = bus.read_mem(cpu.interrupt_vector);
+    cpu.reg_pc = (cpu.reg_pc & 0xFF00) | lsb as u16;
+    cpu.status.set_interrupt_disable(true);
 }
 
-pub fn bvc_1(cpu: &mut CPU) {
-    if !cpu.status.overflow() {
-        handle_successful_branching(cpu);
-    }
+pub fn brk_5(cpu: &mut CPU, bus: &mut Interconnect) {
+    let msb = bus.read_mem(cpu.interrupt_vector + 1);
+    cpu.reg_pc = build_u16(msb, get_lsb(cpu.reg_pc));
 }
 
-pub fn bvs_1(cpu: &mut CPU) {
-    if cpu.status.overflow() {
-        handle_successful_branching(cpu);
-    }
+/// NMI and IRQ push P with B cleared, the rest of the sequence is shared with BRK
+pub fn interrupt_3(cpu: &mut CPU, bus: &mut Interconnect) {
+    cpu.push_to_stack(bus, cpu.status.with_b(false).with_one(true).into_bits());
+    cpu.select_interrupt_vector();
 }
 
-pub fn clc_1(cpu: &mut CPU) {
-    cpu.status.set_carry(false);
+pub fn bvc(cpu: &mut CPU) {
+    branch_if(cpu, !cpu.status.overflow());
 }
 
-pub fn cld_1(cpu: &mut CPU) {
-    cpu.status.set_decimal(false);
+pub fn bvs(cpu: &mut CPU) {
+    branch_if(cpu, cpu.status.overflow());
+}
+
+pub fn clc(cpu: &mut CPU) {
+    cpu.status.set_carry(false);
 }
 
-pub fn cli_1(cpu: &mut CPU) {
+pub fn cld(cpu: &mut CPU) {
     cpu.status.set_decimal(false);
 }
 
-pub fn clv_1(cpu: &mut CPU) {
-    cpu.status.set_overflow(false);
+pub fn cli(cpu: &mut CPU) {
+    cpu.status.set_interrupt_disable(false);
 }
 
-pub fn cmp_1(cpu: &mut CPU) {
-    let res = cpu.reg_a.wrapping_sub(cpu.value);
-    cpu.status.set_carry(cpu.reg_a >= cpu.value);
-    cpu.status.set_zero(res == 0);
-    cpu.status.set_negative(res & 0b10000000 != 0);
+pub fn clv(cpu: &mut CPU) {
+    cpu.status.set_overflow(false);
 }
 
-pub fn cpx_1(cpu: &mut CPU) {
-    let res = cpu.reg_x.wrapping_sub(cpu.value);
-    cpu.status.set_carry(cpu.reg_x >= cpu.value);
-    cpu.status.set_zero(res == 0);
-    cpu.status.set_negative(res & 0b10000000 != 0);
+pub fn cmp(cpu: &mut CPU) {
+    compare(&mut cpu.status, cpu.reg_a, cpu.value);
 }
 
-pub fn cpy_1(cpu: &mut CPU) {
-    let res = cpu.reg_y.wrapping_sub(cpu.value);
-    cpu.status.set_carry(cpu.reg_y >= cpu.value);
-    cpu.status.set_zero(res == 0);
-    cpu.status.set_negative(res & 0b10000000 != 0);
+pub fn cpx(cpu: &mut CPU) {
+    compare(&mut cpu.status, cpu.reg_x, cpu.value);
 }
 
-pub fn dec_1(cpu: &mut CPU) {
-    // Dummy write
-    cpu.ic.write_mem(cpu.addr, cpu.value);
+pub fn cpy(cpu: &mut CPU) {
+    compare(&mut cpu.status, cpu.reg_y, cpu.value);
 }
 
-pub fn dec_2(cpu: &mut CPU) {
-    let sub = cpu.value.wrapping_sub(1);
-    cpu.ic.write_mem(cpu.addr, sub);
-    cpu.status.set_zero(sub == 0);
-    cpu.status.set_negative(sub & 0b10000000 != 0);
+pub fn dec(cpu: &mut CPU) {
+    cpu.value = cpu.value.wrapping_sub(1);
+    set_flags(&mut cpu.status, cpu.value);
 }
 
-pub fn dex_1(cpu: &mut CPU) {
+pub fn dex(cpu: &mut CPU) {
     let sub = cpu.reg_x.wrapping_sub(1);
     set_register_with_flags(&mut cpu.reg_x, &mut cpu.status, sub);
 }
 
-pub fn dey_1(cpu: &mut CPU) {
+pub fn dey(cpu: &mut CPU) {
     let sub = cpu.reg_y.wrapping_sub(1);
     set_register_with_flags(&mut cpu.reg_y, &mut cpu.status, sub);
 }
 
-pub fn eor_1(cpu: &mut CPU) {
+pub fn eor(cpu: &mut CPU) {
     let eorred = cpu.reg_a ^ cpu.value;
     set_register_with_flags(&mut cpu.reg_a, &mut cpu.status, eorred);
 }
 
-pub fn inc_1(cpu: &mut CPU) {
-    // Dummy write
-    cpu.ic.write_mem(cpu.addr, cpu.value);
+pub fn inc(cpu: &mut CPU) {
+    cpu.value = cpu.value.wrapping_add(1);
+    set_flags(&mut cpu.status, cpu.value);
 }
 
-pub fn inc_2(cpu: &mut CPU) {
-    let inc = cpu.value.wrapping_add(1);
-    cpu.ic.write_mem(cpu.addr, inc);
-    cpu.status.set_zero(inc == 0);
-    cpu.status.set_negative(inc & 0b10000000 != 0);
-}
-
-pub fn inx_1(cpu: &mut CPU) {
+pub fn inx(cpu: &mut CPU) {
     let inc = cpu.reg_x.wrapping_add(1);
     set_register_with_flags(&mut cpu.reg_x, &mut cpu.status, inc);
 }
 
-pub fn iny_1(cpu: &mut CPU) {
+pub fn iny(cpu: &mut CPU) {
     let inc = cpu.reg_y.wrapping_add(1);
     set_register_with_flags(&mut cpu.reg_y, &mut cpu.status, inc);
 }
 
-pub fn jmp_1(cpu: &mut CPU) {
-    cpu.reg_pc = cpu.addr;
-    // println!("Jumped to {:#02X}", cpu.addr);
+pub fn jmp_1(cpu: &mut CPU, bus: &mut Interconnect) {
+    let msb = bus.read_mem(cpu.reg_pc);
+    cpu.reg_pc = build_u16(msb, get_lsb(cpu.addr));
 }
 
-pub fn jsr_1(cpu: &mut CPU) {
-    cpu.push_to_stack(get_msb(cpu.reg_pc - 1));
+pub fn jmp_indirect_1(cpu: &mut CPU, bus: &mut Interconnect) {
+    cpu.value = bus.read_mem(cpu.addr);
 }
 
-pub fn jsr_2(cpu: &mut CPU) {
-    cpu.push_to_stack(get_lsb(cpu.reg_pc - 1));
-    cpu.reg_pc = cpu.addr;
-    // println!("Jumped to {:#02X}", cpu.addr);
+pub fn jmp_indirect_2(cpu: &mut CPU, bus: &mut Interconnect) {
+    // The high byte of the pointer isn't incremented, so $xxFF wraps around within the page
+    let msb_addr = build_u16(get_msb(cpu.addr), get_lsb(cpu.addr).wrapping_add(1));
+    let msb = bus.read_mem(msb_addr);
+    cpu.reg_pc = build_u16(msb, cpu.value);
+}
+
+pub fn jsr_1(cpu: &mut CPU, bus: &mut Interconnect) {
+    cpu.push_to_stack(bus, get_msb(cpu.reg_pc));
+}
+
+pub fn jsr_2(cpu: &mut CPU, bus: &mut Interconnect) {
+    cpu.push_to_stack(bus, get_lsb(cpu.reg_pc));
+}
+
+pub fn jsr_3(cpu: &mut CPU, bus: &mut Interconnect) {
+    let msb = bus.read_mem(cpu.reg_pc);
+    cpu.reg_pc = build_u16(msb, get_lsb(cpu.addr));
 }
 
-pub fn lda_1(cpu: &mut CPU) {
+pub fn lda(cpu: &mut CPU) {
     set_register_with_flags(&mut cpu.reg_a, &mut cpu.status, cpu.value);
 }
 
-pub fn ldx_1(cpu: &mut CPU) {
+pub fn ldx(cpu: &mut CPU) {
     set_register_with_flags(&mut cpu.reg_x, &mut cpu.status, cpu.value);
 }
 
-pub fn ldy_1(cpu: &mut CPU) {
+pub fn ldy(cpu: &mut CPU) {
     set_register_with_flags(&mut cpu.reg_y, &mut cpu.status, cpu.value);
 }
 
-pub fn lsr_1(cpu: &mut CPU) {
-    // TODO
-    match cpu.curr_inst.as_mut().expect("No instruction").addr_mode {
-        AddressingMode::Accumulator => {
-            let old_bit_0 = cpu.reg_a & 1 != 0;
-            cpu.reg_a = cpu.reg_a.wrapping_shr(1);
-            cpu.status.set_carry(old_bit_0);
-            cpu.status.set_zero(cpu.reg_a == 0);
-            cpu.status.set_negative(cpu.reg_a & 0b10000000 != 0);
-        }
-        _ => {
-            let old_bit_0 = cpu.value & 1 != 0;
-            let rot = cpu.value.wrapping_shr(1);
-            cpu.ic.write_mem(cpu.addr, rot); // TODO
-            cpu.status.set_carry(old_bit_0);
-            cpu.status.set_zero(rot == 0); // TODO is this right?
-            cpu.status.set_negative(rot & 0b10000000 != 0);
-        }
-    }
+pub fn lsr(cpu: &mut CPU) {
+    cpu.status.set_carry(cpu.value & 1 != 0);
+    cpu.value = cpu.value.wrapping_shr(1);
+    set_flags(&mut cpu.status, cpu.value);
 }
 
-pub fn nop(cpu: &mut CPU) {}
+pub fn nop(_cpu: &mut CPU) {}
+
+pub fn kil(cpu: &mut CPU, bus: &mut Interconnect) {
+    match cpu.jam_policy() {
+        JamPolicy::Halt => cpu.halt(),
+        JamPolicy::Skip => dummy_read_pc(cpu, bus),
+    }
+}
 
-pub fn ora_1(cpu: &mut CPU) {
+pub fn ora(cpu: &mut CPU) {
     let orred = cpu.reg_a | cpu.value;
     set_register_with_flags(&mut cpu.reg_a, &mut cpu.status, orred);
 }
 
-pub fn pha_1(cpu: &mut CPU) {
-    cpu.push_to_stack(cpu.reg_a);
+pub fn pha_1(cpu: &mut CPU, bus: &mut Interconnect) {
+    cpu.push_to_stack(bus, cpu.reg_a);
 }
 
-pub fn php_1(cpu: &mut CPU) {
-    cpu.push_to_stack(cpu.status.with_b(true).into_bits());
+pub fn php_1(cpu: &mut CPU, bus: &mut Interconnect) {
+    cpu.push_to_stack(bus, cpu.status.with_b(true).with_one(true).into_bits());
 }
 
-pub fn pla_1(cpu: &mut CPU) {
-    let s = cpu.pull_from_stack();
+pub fn pla_1(cpu: &mut CPU, bus: &mut Interconnect) {
+    let s = cpu.pull_from_stack(bus);
     set_register_with_flags(&mut cpu.reg_a, &mut cpu.status, s);
 }
 
-pub fn plp_1(cpu: &mut CPU) {
-    let s = Status::from_bits(cpu.pull_from_stack())
+pub fn plp_1(cpu: &mut CPU, bus: &mut Interconnect) {
+    cpu.status = Status::from_bits(cpu.pull_from_stack(bus))
         .with_b(false)
         .with_one(true);
-    cpu.status = Status::from_bits(s.into_bits());
-}
-
-pub fn rol_1(cpu: &mut CPU) {
-    match cpu.curr_inst.as_mut().expect("No instruction").addr_mode {
-        AddressingMode::Accumulator => {
-            let old_bit_7 = cpu.reg_a & 0b10000000 != 0;
-            cpu.reg_a = cpu.reg_a.wrapping_shl(1) | cpu.status.carry() as u8;
-            cpu.status.set_carry(old_bit_7);
-            cpu.status.set_zero(cpu.reg_a == 0);
-            cpu.status.set_negative(cpu.reg_a & 0b10000000 != 0);
-        }
-        _ => {
-            let old_bit_7 = cpu.value & 0b10000000 != 0;
-            let rot = cpu.value.wrapping_shl(1) | cpu.status.carry() as u8;
-            cpu.ic.write_mem(cpu.addr, rot); // TODO
-            cpu.status.set_carry(old_bit_7);
-            cpu.status.set_zero(cpu.reg_a == 0); // TODO is this right?
-            cpu.status.set_negative(rot & 0b10000000 != 0);
-        }
-    }
 }
 
-pub fn ror_1(cpu: &mut CPU) {
-    match cpu.curr_inst.as_mut().expect("No instruction").addr_mode {
-        AddressingMode::Accumulator => {
-            let old_bit_0 = cpu.reg_a & 1 != 0;
-            cpu.reg_a = cpu.reg_a.wrapping_shl(1) | (cpu.status.carry() as u8).wrapping_shl(7);
-            cpu.status.set_carry(old_bit_0);
-            cpu.status.set_zero(cpu.reg_a == 0);
-            cpu.status.set_negative(cpu.reg_a & 0b10000000 != 0);
-        }
-        _ => {
-            let old_bit_0 = cpu.value & 1 != 0;
-            let rot = cpu.value.wrapping_shr(1) | (cpu.status.carry() as u8).wrapping_shl(7);
-            cpu.ic.write_mem(cpu.addr, rot); // TODO
-            cpu.status.set_carry(old_bit_0);
-            cpu.status.set_zero(cpu.reg_a == 0); // TODO is this right?
-            cpu.status.set_negative(rot & 0b10000000 != 0);
-        }
-    }
+pub fn rol(cpu: &mut CPU) {
+    let old_bit_7 = cpu.value & 0b10000000 != 0;
+    cpu.value = cpu.value.wrapping_shl(1) | cpu.status.carry() as u8;
+    cpu.status.set_carry(old_bit_7);
+    set_flags(&mut cpu.status, cpu.value);
+}
+
+pub fn ror(cpu: &mut CPU) {
+    let old_bit_0 = cpu.value & 1 != 0;
+    cpu.value = cpu.value.wrapping_shr(1) | (cpu.status.carry() as u8).wrapping_shl(7);
+    cpu.status.set_carry(old_bit_0);
+    set_flags(&mut cpu.status, cpu.value);
+}
+
+pub fn rti_1(cpu: &mut CPU, bus: &mut Interconnect) {
+    // Unlike PLP, the restored flags are already in effect for the interrupt polling of RTI
+    cpu.status = Status::from_bits(cpu.pull_from_stack(bus))
+        .with_b(false)
+        .with_one(true);
 }
 
-pub fn rti_1(cpu: &mut CPU) {
-    cpu.status = Status::from_bits(cpu.pull_from_stack());
+pub fn rti_2(cpu: &mut CPU, bus: &mut Interconnect) {
+    cpu.reg_pc = cpu.pull_from_stack(bus) as u16;
 }
 
-pub fn rti_2(cpu: &mut CPU) {
-    cpu.reg_pc = cpu.pull_from_stack() as u16;
+pub fn rti_3(cpu: &mut CPU, bus: &mut Interconnect) {
+    cpu.reg_pc |= (cpu.pull_from_stack(bus) as u16) << 8;
 }
 
-pub fn rti_3(cpu: &mut CPU) {
-    cpu.reg_pc = (cpu.pull_from_stack() as u16) << 8;
+pub fn rts_1(cpu: &mut CPU, bus: &mut Interconnect) {
+    cpu.reg_pc = cpu.pull_from_stack(bus) as u16;
 }
 
-pub fn rts_1(cpu: &mut CPU) {
-    let lsb = cpu.pull_from_stack();
-    cpu.reg_pc = lsb as u16;
+pub fn rts_2(cpu: &mut CPU, bus: &mut Interconnect) {
+    cpu.reg_pc |= (cpu.pull_from_stack(bus) as u16) << 8;
 }
 
-pub fn rts_2(cpu: &mut CPU) {
-    let msb = cpu.pull_from_stack();
-    cpu.reg_pc += ((msb as u16) << 8) + 1;
-    // println!("Jumped to {:#02X}", cpu.reg_pc);
+pub fn rts_3(cpu: &mut CPU, bus: &mut Interconnect) {
+    // JSR pushes the address of its last byte
+    bus.read_mem(cpu.reg_pc);
+    cpu.reg_pc = cpu.reg_pc.wrapping_add(1);
 }
 
-pub fn sbc_1(cpu: &mut CPU) {
+pub fn sbc(cpu: &mut CPU) {
     let carry_6 = ((cpu.reg_a & 0b01111111)
         .wrapping_sub(cpu.value & 0b01111111)
         .wrapping_sub(1 - cpu.status.carry() as u8)
@@ -372,41 +359,155 @@ pub fn sbc_1(cpu: &mut CPU) {
     cpu.status.set_zero(sub == 0);
     cpu.status.set_overflow(carry_6 ^ overflow);
     cpu.status.set_negative(sub & 0b10000000 != 0);
-    cpu.reg_a = sub as u8;
+    cpu.reg_a = sub;
 }
 
-pub fn sec_1(cpu: &mut CPU) {
+pub fn sec(cpu: &mut CPU) {
     cpu.status.set_carry(true);
 }
 
-pub fn sed_1(cpu: &mut CPU) {
+pub fn sed(cpu: &mut CPU) {
     cpu.status.set_decimal(true);
 }
 
-pub fn sei_1(cpu: &mut CPU) {
+pub fn sei(cpu: &mut CPU) {
     cpu.status.set_interrupt_disable(true);
 }
 
-pub fn tax_1(cpu: &mut CPU) {
+pub fn sta(cpu: &mut CPU) {
+    cpu.value = cpu.reg_a;
+}
+
+pub fn stx(cpu: &mut CPU) {
+    cpu.value = cpu.reg_x;
+}
+
+pub fn sty(cpu: &mut CPU) {
+    cpu.value = cpu.reg_y;
+}
+
+pub fn tax(cpu: &mut CPU) {
     set_register_with_flags(&mut cpu.reg_x, &mut cpu.status, cpu.reg_a);
 }
 
-pub fn tay_1(cpu: &mut CPU) {
+pub fn tay(cpu: &mut CPU) {
     set_register_with_flags(&mut cpu.reg_y, &mut cpu.status, cpu.reg_a);
 }
 
-pub fn tsx_1(cpu: &mut CPU) {
+pub fn tsx(cpu: &mut CPU) {
     set_register_with_flags(&mut cpu.reg_x, &mut cpu.status, cpu.reg_s);
 }
 
-pub fn txa_1(cpu: &mut CPU) {
+pub fn txa(cpu: &mut CPU) {
     set_register_with_flags(&mut cpu.reg_a, &mut cpu.status, cpu.reg_x);
 }
 
-pub fn txs_1(cpu: &mut CPU) {
-    set_register_with_flags(&mut cpu.reg_s, &mut cpu.status, cpu.reg_x);
+pub fn txs(cpu: &mut CPU) {
+    // The only transfer that doesn't touch the flags
+    cpu.reg_s = cpu.reg_x;
 }
 
-pub fn tya_1(cpu: &mut CPU) {
+pub fn tya(cpu: &mut CPU) {
     set_register_with_flags(&mut cpu.reg_a, &mut cpu.status, cpu.reg_y);
 }
+
+pub fn alr(cpu: &mut CPU) {
+    let anded = cpu.reg_a & cpu.value;
+    cpu.status.set_carry(anded & 1 != 0);
+    set_register_with_flags(&mut cpu.reg_a, &mut cpu.status, anded >> 1);
+}
+
+pub fn anc(cpu: &mut CPU) {
+    let anded = cpu.reg_a & cpu.value;
+    set_register_with_flags(&mut cpu.reg_a, &mut cpu.status, anded);
+    cpu.status.set_carry(anded & 0b10000000 != 0);
+}
+
+pub fn arr(cpu: &mut CPU) {
+    let anded = cpu.reg_a & cpu.value;
+    let rot = (anded >> 1) | (cpu.status.carry() as u8) << 7;
+    set_register_with_flags(&mut cpu.reg_a, &mut cpu.status, rot);
+    cpu.status.set_carry(rot & 0b01000000 != 0);
+    cpu.status.set_overflow(((rot >> 6) ^ (rot >> 5)) & 1 != 0);
+}
+
+pub fn axs(cpu: &mut CPU) {
+    let anded = cpu.reg_a & cpu.reg_x;
+    compare(&mut cpu.status, anded, cpu.value);
+    cpu.reg_x = anded.wrapping_sub(cpu.value);
+}
+
+pub fn dcp(cpu: &mut CPU) {
+    cpu.value = cpu.value.wrapping_sub(1);
+    compare(&mut cpu.status, cpu.reg_a, cpu.value);
+}
+
+pub fn isb(cpu: &mut CPU) {
+    cpu.value = cpu.value.wrapping_add(1);
+    sbc(cpu);
+}
+
+pub fn las(cpu: &mut CPU) {
+    let anded = cpu.value & cpu.reg_s;
+    cpu.reg_s = anded;
+    cpu.reg_x = anded;
+    set_register_with_flags(&mut cpu.reg_a, &mut cpu.status, anded);
+}
+
+pub fn lax(cpu: &mut CPU) {
+    cpu.reg_x = cpu.value;
+    set_register_with_flags(&mut cpu.reg_a, &mut cpu.status, cpu.value);
+}
+
+/// The immediate variant of LAX
+pub fn lxa(cpu: &mut CPU) {
+    let value = (cpu.reg_a | UNSTABLE_MAGIC_LAX) & cpu.value;
+    cpu.reg_x = value;
+    set_register_with_flags(&mut cpu.reg_a, &mut cpu.status, value);
+}
+
+pub fn rla(cpu: &mut CPU) {
+    rol(cpu);
+    and(cpu);
+}
+
+pub fn rra(cpu: &mut CPU) {
+    ror(cpu);
+    adc(cpu);
+}
+
+pub fn sax(cpu: &mut CPU) {
+    cpu.value = cpu.reg_a & cpu.reg_x;
+}
+
+pub fn sha(cpu: &mut CPU) {
+    and_high_byte(cpu, cpu.reg_a & cpu.reg_x, cpu.reg_y);
+}
+
+pub fn shx(cpu: &mut CPU) {
+    and_high_byte(cpu, cpu.reg_x, cpu.reg_y);
+}
+
+pub fn shy(cpu: &mut CPU) {
+    and_high_byte(cpu, cpu.reg_y, cpu.reg_x);
+}
+
+pub fn slo(cpu: &mut CPU) {
+    asl(cpu);
+    ora(cpu);
+}
+
+pub fn sre(cpu: &mut CPU) {
+    lsr(cpu);
+    eor(cpu);
+}
+
+pub fn tas(cpu: &mut CPU) {
+    cpu.reg_s = cpu.reg_a & cpu.reg_x;
+    and_high_byte(cpu, cpu.reg_s, cpu.reg_y);
+}
+
+pub fn xaa(cpu: &mut CPU) {
+    let value = (cpu.reg_a | UNSTABLE_MAGIC_XAA) & cpu.reg_x & cpu.value;
+    set_register_with_flags(&mut cpu.reg_a, &mut cpu.status, value);
+}
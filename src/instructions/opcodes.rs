@@ -0,0 +1,262 @@
+use crate::cpu::{MicroOp, Operation};
+
+use super::addressing::{self, dummy_read_pc, dummy_read_stack, fetch_operand, MemoryOp};
+use super::{get_addr_mode, get_inst_type, get_num_of_operands, logic};
+use super::{AddressingMode, InstructionType};
+
+/// Everything the CPU and the disassembler need to know about an opcode
+#[derive(Clone, Copy)]
+pub struct Opcode {
+    pub inst_type: InstructionType,
+    pub addr_mode: AddressingMode,
+    /// Cycles taken without any penalties, including the opcode fetch
+    pub cycles: u8,
+    /// Whether crossing a page with indexing costs another cycle. Branches also take one
+    /// more cycle when they are taken.
+    pub page_cross_penalty: bool,
+    pub unofficial: bool,
+    /// The part of the instruction that's independent of the addressing mode
+    pub operation: Operation,
+    /// One micro-op for every cycle after the opcode fetch
+    pub program: &'static [MicroOp],
+}
+
+impl Opcode {
+    /// Size of the instruction in bytes, including the opcode
+    pub const fn size(&self) -> usize {
+        1 + get_num_of_operands(&self.addr_mode)
+    }
+}
+
+pub static OPCODES: [Opcode; 256] = build_table();
+
+const BRK: &[MicroOp] = &[
+    // The byte after BRK is skipped
+    fetch_operand,
+    logic::brk_1,
+    logic::brk_2,
+    logic::brk_3,
+    logic::brk_4,
+    logic::brk_5,
+];
+const JMP_ABSOLUTE: &[MicroOp] = &[fetch_operand, logic::jmp_1];
+const JMP_INDIRECT: &[MicroOp] = &[
+    fetch_operand,
+    addressing::fetch_operand_msb,
+    logic::jmp_indirect_1,
+    logic::jmp_indirect_2,
+];
+const JSR: &[MicroOp] = &[
+    fetch_operand,
+    dummy_read_stack,
+    logic::jsr_1,
+    logic::jsr_2,
+    logic::jsr_3,
+];
+const PHA: &[MicroOp] = &[dummy_read_pc, logic::pha_1];
+const PHP: &[MicroOp] = &[dummy_read_pc, logic::php_1];
+const PLA: &[MicroOp] = &[dummy_read_pc, dummy_read_stack, logic::pla_1];
+// P is only updated in the last cycle, after interrupts were polled
+const PLP: &[MicroOp] = &[dummy_read_pc, dummy_read_stack, logic::plp_1];
+const RTI: &[MicroOp] = &[
+    dummy_read_pc,
+    dummy_read_stack,
+    logic::rti_1,
+    logic::rti_2,
+    logic::rti_3,
+];
+const RTS: &[MicroOp] = &[
+    dummy_read_pc,
+    dummy_read_stack,
+    logic::rts_1,
+    logic::rts_2,
+    logic::rts_3,
+];
+const KIL: &[MicroOp] = &[logic::kil];
+
+const fn build_table() -> [Opcode; 256] {
+    let mut table = [build_opcode(0); 256];
+    let mut byte = 0;
+    while byte < 256 {
+        table[byte] = build_opcode(byte as u8);
+        byte += 1;
+    }
+    table
+}
+
+const fn build_opcode(byte: u8) -> Opcode {
+    let inst_type = get_inst_type(byte);
+    let addr_mode = get_addr_mode(byte);
+    let program = program(inst_type, addr_mode);
+    let memory_op = memory_op(inst_type);
+    let page_cross_penalty = matches!(addr_mode, AddressingMode::Relative)
+        || matches!(memory_op, MemoryOp::Read)
+            && matches!(
+                addr_mode,
+                AddressingMode::AbsoluteIndexedX
+                    | AddressingMode::AbsoluteIndexedY
+                    | AddressingMode::IndirectIndexed
+            );
+    // Programs contain the cycles that might get skipped
+    let optional_cycles = match (addr_mode, page_cross_penalty) {
+        (AddressingMode::Relative, _) => 2,
+        (_, true) => 1,
+        (_, false) => 0,
+    };
+    Opcode {
+        inst_type,
+        addr_mode,
+        cycles: 1 + program.len() as u8 - optional_cycles,
+        page_cross_penalty,
+        unofficial: is_unofficial(byte, inst_type),
+        operation: operation(inst_type, addr_mode),
+        program,
+    }
+}
+
+const fn is_unofficial(byte: u8, inst_type: InstructionType) -> bool {
+    match inst_type {
+        InstructionType::NOP => byte != 0xEA,
+        InstructionType::SBC => byte == 0xEB,
+        InstructionType::ALR
+        | InstructionType::ANC
+        | InstructionType::ARR
+        | InstructionType::AXS
+        | InstructionType::DCP
+        | InstructionType::ISB
+        | InstructionType::KIL
+        | InstructionType::LAS
+        | InstructionType::LAX
+        | InstructionType::RLA
+        | InstructionType::RRA
+        | InstructionType::SAX
+        | InstructionType::SHA
+        | InstructionType::SHX
+        | InstructionType::SHY
+        | InstructionType::SLO
+        | InstructionType::SRE
+        | InstructionType::TAS
+        | InstructionType::XAA => true,
+        _ => false,
+    }
+}
+
+const fn memory_op(inst_type: InstructionType) -> MemoryOp {
+    match inst_type {
+        InstructionType::ASL
+        | InstructionType::DEC
+        | InstructionType::INC
+        | InstructionType::LSR
+        | InstructionType::ROL
+        | InstructionType::ROR
+        | InstructionType::DCP
+        | InstructionType::ISB
+        | InstructionType::RLA
+        | InstructionType::RRA
+        | InstructionType::SLO
+        | InstructionType::SRE => MemoryOp::ReadModifyWrite,
+        InstructionType::STA
+        | InstructionType::STX
+        | InstructionType::STY
+        | InstructionType::SAX
+        | InstructionType::SHA
+        | InstructionType::SHX
+        | InstructionType::SHY
+        | InstructionType::TAS => MemoryOp::Write,
+        _ => MemoryOp::Read,
+    }
+}
+
+const fn program(inst_type: InstructionType, addr_mode: AddressingMode) -> &'static [MicroOp] {
+    // These don't fit any addressing mode pattern
+    match inst_type {
+        InstructionType::BRK => BRK,
+        InstructionType::JMP => match addr_mode {
+            AddressingMode::Indirect => JMP_INDIRECT,
+            _ => JMP_ABSOLUTE,
+        },
+        InstructionType::JSR => JSR,
+        InstructionType::PHA => PHA,
+        InstructionType::PHP => PHP,
+        InstructionType::PLA => PLA,
+        InstructionType::PLP => PLP,
+        InstructionType::RTI => RTI,
+        InstructionType::RTS => RTS,
+        InstructionType::KIL => KIL,
+        _ => addressing::program(addr_mode, memory_op(inst_type)),
+    }
+}
+
+const fn operation(inst_type: InstructionType, addr_mode: AddressingMode) -> Operation {
+    match inst_type {
+        InstructionType::ADC => logic::adc,
+        InstructionType::AND => logic::and,
+        InstructionType::ASL => logic::asl,
+        InstructionType::BCC => logic::bcc,
+        InstructionType::BCS => logic::bcs,
+        InstructionType::BEQ => logic::beq,
+        InstructionType::BIT => logic::bit,
+        InstructionType::BMI => logic::bmi,
+        InstructionType::BNE => logic::bne,
+        InstructionType::BPL => logic::bpl,
+        InstructionType::BVC => logic::bvc,
+        InstructionType::BVS => logic::bvs,
+        InstructionType::CLC => logic::clc,
+        InstructionType::CLD => logic::cld,
+        InstructionType::CLI => logic::cli,
+        InstructionType::CLV => logic::clv,
+        InstructionType::CMP => logic::cmp,
+        InstructionType::CPX => logic::cpx,
+        InstructionType::CPY => logic::cpy,
+        InstructionType::DEC => logic::dec,
+        InstructionType::DEX => logic::dex,
+        InstructionType::DEY => logic::dey,
+        InstructionType::EOR => logic::eor,
+        InstructionType::INC => logic::inc,
+        InstructionType::INX => logic::inx,
+        InstructionType::INY => logic::iny,
+        InstructionType::LDA => logic::lda,
+        InstructionType::LDX => logic::ldx,
+        InstructionType::LDY => logic::ldy,
+        InstructionType::LSR => logic::lsr,
+        InstructionType::ORA => logic::ora,
+        InstructionType::ROL => logic::rol,
+        InstructionType::ROR => logic::ror,
+        InstructionType::SBC => logic::sbc,
+        InstructionType::SEC => logic::sec,
+        InstructionType::SED => logic::sed,
+        InstructionType::SEI => logic::sei,
+        InstructionType::STA => logic::sta,
+        InstructionType::STX => logic::stx,
+        InstructionType::STY => logic::sty,
+        InstructionType::TAX => logic::tax,
+        InstructionType::TAY => logic::tay,
+        InstructionType::TSX => logic::tsx,
+        InstructionType::TXA => logic::txa,
+        InstructionType::TXS => logic::txs,
+        InstructionType::TYA => logic::tya,
+        InstructionType::ALR => logic::alr,
+        InstructionType::ANC => logic::anc,
+        InstructionType::ARR => logic::arr,
+        InstructionType::AXS => logic::axs,
+        InstructionType::DCP => logic::dcp,
+        InstructionType::ISB => logic::isb,
+        InstructionType::LAS => logic::las,
+        InstructionType::LAX => match addr_mode {
+            AddressingMode::Immediate => logic::lxa,
+            _ => logic::lax,
+        },
+        InstructionType::RLA => logic::rla,
+        InstructionType::RRA => logic::rra,
+        InstructionType::SAX => logic::sax,
+        InstructionType::SHA => logic::sha,
+        InstructionType::SHX => logic::shx,
+        InstructionType::SHY => logic::shy,
+        InstructionType::SLO => logic::slo,
+        InstructionType::SRE => logic::sre,
+        InstructionType::TAS => logic::tas,
+        InstructionType::XAA => logic::xaa,
+        // The control flow instructions are entirely made up of their programs
+        _ => logic::nop,
+    }
+}
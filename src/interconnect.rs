@@ -1,9 +1,16 @@
 use crate::apu::APU;
 use crate::cartridge::Cartridge;
-use crate::ines::{Flags6, NametableArrangement};
+use crate::clock::MasterClock;
+use crate::controller::{ControllerPort, Port};
+use crate::cpu::{IrqSources, CPU};
+use crate::error::NesError;
+use crate::ines::{RomHeader, HEADER_SIZE, TRAINER_ADDR, TRAINER_SIZE};
+use crate::mapper;
 use crate::nes::Powerable;
 use crate::ppu::PPU;
 use crate::ram::RAM;
+use crate::region::Region;
+use crate::trace::{AccessKind, Tracer};
 use crate::utils::build_u16;
 
 #[derive(Default)]
@@ -12,51 +19,315 @@ pub struct Interconnect {
     ram: RAM,
     ppu: PPU,
     apu: APU,
+    ports: [ControllerPort; 2],
+
+    region: Region,
+    clock: MasterClock,
+    cycle: u64,
+    /// Last value on the CPU data bus
+    open_bus: u8,
+    /// Whether unmapped accesses get reported
+    strict: bool,
+    /// The first unmapped access since the last `take_error`
+    error: Option<NesError>,
+
+    oam_dma_page: Option<u8>,
+    dmc_dma_address: Option<u16>,
+    /// DMA can only halt the CPU on a read cycle, so a request waits for the next read
+    dma_need_halt: bool,
+    dma_need_dummy_read: bool,
+    dma_cycles: u64,
+
+    tracer: Option<Box<dyn Tracer>>,
 }
 
 impl Interconnect {
-    pub fn load_rom(&mut self, ines: Vec<u8>) {
-        let header = &ines[..16];
-        let prg_rom_size = header[4] as usize * 16 * 1024;
-        let chr_rom_size = header[5] as usize * 8 * 1024;
-        let flags = Flags6::from_bits(header[6]);
-
-        let mut prg_rom = vec![];
-        let prg_slice = match flags.trainer() {
-            true => &ines[16..16 + 512 + prg_rom_size],
-            false => &ines[16..16 + prg_rom_size],
-        };
-        prg_rom.extend_from_slice(prg_slice);
-        // TODO implement mapper logic, only direct mapping now
-        self.cartridge.load_at(0xC000 - 0x4020, prg_rom);
-        // TODO do the rest of the flags
+    /// Inserts the cartridge in the iNES file and returns its header
+    pub fn load_rom(&mut self, ines: Vec<u8>) -> Result<RomHeader, NesError> {
+        let header = RomHeader::parse(&ines)?;
+        let prg_start = header.prg_rom_offset();
+        let prg_rom = ines[prg_start..prg_start + header.prg_rom_size].to_vec();
+        let chr_start = header.chr_rom_offset();
+        let chr_rom = ines[chr_start..chr_start + header.chr_rom_size].to_vec();
+
+        let mapper = mapper::create(&header, prg_rom, chr_rom)
+            .ok_or(NesError::UnsupportedMapper(header.mapper))?;
+        self.cartridge.insert(mapper);
+
+        if header.trainer {
+            let trainer = &ines[HEADER_SIZE..HEADER_SIZE + TRAINER_SIZE];
+            for (address, &value) in (TRAINER_ADDR..).zip(trainer) {
+                self.cartridge.write_mem(address, value);
+            }
+        }
+        Ok(header)
     }
 
-    pub fn read_mem(&self, address: u16) -> u8 {
-        let val = match address {
-            0x0000..=0x1FFF => self.ram.read_mem(address & 0x7FF), // remove mirroring
-            0x2000..=0x3FFF => self.ppu.read_reg((address & 7) as u8),
-            0x4014 => panic!("Read of OAMDMA not implemented"), // TODO
-            0x4020..=0xFFFF => self.cartridge.read_mem(address - 0x4020),
-            _ => panic!("Address {} not implemented for read", address),
-        };
-        // println!("Read {:#02x} from address {:#02x}", val, address);
-        val
+    /// Ends the current CPU cycle by clocking the APU and the cartridge. The PPU gets run up to
+    /// the same point right after, when the CPU polls `nmi_line` and `irq_sources`.
+    pub fn tick(&mut self) {
+        self.clock.advance_cpu();
+        self.cartridge.cpu_tick();
+        self.apu.tick();
+        if let Some(address) = self.apu.take_dmc_dma_request() {
+            self.dmc_dma_address = Some(address);
+            self.dma_need_halt = true;
+            self.dma_need_dummy_read = true;
+        }
+        self.cycle += 1;
+    }
+
+    /// Runs the PPU up to the current master clock cycle. The CPU polls the interrupt lines at the
+    /// end of every cycle, so this usually runs the dots of a single CPU cycle. Cycles stolen by
+    /// DMA aren't polled, their dots get run at the next OAM write or at the end of the CPU cycle.
+    fn catch_up_ppu(&mut self) {
+        for _ in 0..self.clock.take_ppu_dots() {
+            self.ppu.tick(&mut self.cartridge);
+        }
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.clock.set_dividers(region.clock_dividers());
+        self.ppu.set_region(region);
+        self.apu.set_region(region);
+    }
+
+    /// Master clock cycles the PPU runs ahead of the CPU, see `MasterClock::set_ppu_phase`
+    pub fn set_ppu_phase(&mut self, ppu_phase: u64) {
+        self.clock.set_ppu_phase(ppu_phase);
+    }
+
+    pub fn clock(&self) -> &MasterClock {
+        &self.clock
+    }
+
+    /// In strict mode, reads and writes of unmapped addresses are reported by `take_error`.
+    /// Otherwise reads return the open bus value and writes are ignored, like on the console.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    pub fn take_error(&mut self) -> Option<NesError> {
+        self.error.take()
+    }
+
+    fn report(&mut self, error: NesError) {
+        if self.strict && self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+
+    /// Attaches a tracer, or detaches it with `None`, returning the previous one
+    pub fn set_tracer(&mut self, tracer: Option<Box<dyn Tracer>>) -> Option<Box<dyn Tracer>> {
+        std::mem::replace(&mut self.tracer, tracer)
+    }
+
+    /// Hands the instruction `cpu` is about to fetch to the tracer, if there is one
+    pub fn trace_instruction(&mut self, cpu: &CPU) {
+        if let Some(mut tracer) = self.tracer.take() {
+            tracer.instruction(cpu, self);
+            self.tracer = Some(tracer);
+        }
+    }
+
+    /// Reads memory without side effects, for debugging. The PPU, APU and I/O registers aren't
+    /// read and return $FF, like Nintendulator shows them.
+    pub fn peek(&mut self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1FFF => self.ram.read_mem(address),
+            0x4020..=0xFFFF => self.cartridge.read_mem(address).unwrap_or(self.open_bus),
+            _ => 0xFF,
+        }
+    }
+
+    /// Number of cycles the CPU was halted for since the last call
+    pub fn take_dma_cycles(&mut self) -> u64 {
+        std::mem::take(&mut self.dma_cycles)
+    }
+
+    pub fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
     }
 
-    pub fn read_mem_word(&self, address: u16) -> u16 {
+    pub fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    pub fn apu_mut(&mut self) -> &mut APU {
+        &mut self.apu
+    }
+
+    pub fn port_mut(&mut self, port: Port) -> &mut ControllerPort {
+        &mut self.ports[port as usize]
+    }
+
+    pub fn ppu(&self) -> &PPU {
+        &self.ppu
+    }
+
+    /// Level of the PPU's /NMI output, the CPU does the edge detection
+    pub fn nmi_line(&mut self) -> bool {
+        self.catch_up_ppu();
+        self.ppu.nmi_output()
+    }
+
+    pub fn irq_sources(&mut self) -> IrqSources {
+        // Mappers like the MMC3 count scanlines from the PPU's fetches
+        self.catch_up_ppu();
+        IrqSources::new()
+            .with_frame_counter(self.apu.frame_irq())
+            .with_dmc(self.apu.dmc_irq())
+            .with_mapper(self.cartridge.irq())
+    }
+
+    pub fn read_mem(&mut self, address: u16) -> u8 {
+        if self.dma_need_halt {
+            self.run_dma(address);
+        }
+        self.read_bus(address)
+    }
+
+    pub fn read_mem_word(&mut self, address: u16) -> u16 {
         build_u16(self.read_mem(address + 1), self.read_mem(address))
     }
 
     pub fn write_mem(&mut self, address: u16, value: u8) {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.bus_access(address, value, AccessKind::Write);
+        }
+        self.open_bus = value;
         match address {
-            0x0000..=0x1FFF => self.ram.write_mem(address & 0x7FF, value), // remove mirroring
-            0x2000..=0x3FFF => self.ppu.write_reg((address & 7) as u8, value),
-            0x4014 => panic!("Read of OAMDMA not implemented"), // TODO
-            0x4020..=0xFFFF => self.cartridge.write_mem(address - 0x4020, value),
-            _ => panic!("Address {} not implemented for read", address),
+            0x0000..=0x1FFF => self.ram.write_mem(address, value),
+            0x2000..=0x3FFF => {
+                self.catch_up_ppu();
+                self.ppu
+                    .write_reg((address & 7) as u8, value, &mut self.cartridge)
+            }
+            0x4000..=0x4013 | 0x4017 => self.apu.write_reg(address, value),
+            0x4015 => {
+                self.apu.write_reg(address, value);
+                if !self.apu.dmc_dma_pending() {
+                    self.cancel_dmc_dma();
+                }
+            }
+            0x4016 => {
+                for port in self.ports.iter_mut() {
+                    port.write_strobe(value & 1 != 0);
+                }
+            }
+            0x4014 => {
+                self.oam_dma_page = Some(value);
+                self.dma_need_halt = true;
+            }
+            0x4020..=0xFFFF => {
+                if !self.cartridge.write_mem(address, value) {
+                    self.report(NesError::UnmappedWrite { address, value });
+                }
+            }
+            // The APU and I/O test registers, disabled on retail consoles
+            0x4018..=0x401F => self.report(NesError::UnmappedWrite { address, value }),
+        }
+    }
+
+    fn read_bus(&mut self, address: u16) -> u8 {
+        let val = match address {
+            0x0000..=0x1FFF => self.ram.read_mem(address),
+            0x2000..=0x3FFF => {
+                self.catch_up_ppu();
+                self.ppu.read_reg((address & 7) as u8, &mut self.cartridge)
+            }
+            0x4000..=0x4014 => self.open_bus, // Write-only
+            0x4015 => self.apu.read_status(self.open_bus),
+            0x4016 => self.ports[0].read(self.open_bus),
+            0x4017 => self.ports[1].read(self.open_bus),
+            0x4020..=0xFFFF => match self.cartridge.read_mem(address) {
+                Some(value) => value,
+                None => self.unmapped_read(address),
+            },
+            0x4018..=0x401F => self.unmapped_read(address),
+        };
+        if let Some(tracer) = &mut self.tracer {
+            tracer.bus_access(address, val, AccessKind::Read);
+        }
+        self.open_bus = val;
+        val
+    }
+
+    fn unmapped_read(&mut self, address: u16) -> u8 {
+        self.report(NesError::UnmappedRead(address));
+        self.open_bus
+    }
+
+    /// Runs the pending OAM and DMC DMAs while the CPU is halted on its read of `address`.
+    ///
+    /// DMA alternates between get (read) and put (write) cycles. OAM DMA takes 513 cycles, plus
+    /// one when it has to wait for a get cycle. DMC DMA needs a halt and a dummy cycle before its
+    /// get, but when it lands in the middle of an OAM DMA those are absorbed by the OAM cycles.
+    fn run_dma(&mut self, address: u16) {
+        // The halted CPU keeps repeating its read, which matters for registers with read side
+        // effects, except for the controller ports which only see the first one
+        let dummy_reads = !matches!(address, 0x4016 | 0x4017);
+        self.read_bus(address);
+        self.dma_cycle();
+        self.dma_need_halt = false;
+
+        let mut oam_count: u16 = 0;
+        let mut oam_value = 0;
+        while self.dmc_dma_address.is_some() || self.oam_dma_page.is_some() {
+            let get_cycle = self.cycle.is_multiple_of(2);
+            let dmc_ready = !self.dma_need_halt && !self.dma_need_dummy_read;
+            // Any cycle spent here counts as the DMC's halt and dummy cycles
+            if self.dma_need_halt {
+                self.dma_need_halt = false;
+            } else if self.dma_need_dummy_read {
+                self.dma_need_dummy_read = false;
+            }
+
+            match (get_cycle, self.dmc_dma_address, self.oam_dma_page) {
+                (true, Some(dmc_address), _) if dmc_ready => {
+                    let value = self.read_bus(dmc_address);
+                    self.apu.load_dmc_sample(value);
+                    self.dmc_dma_address = None;
+                }
+                (true, _, Some(page)) => {
+                    oam_value = self.read_bus(build_u16(page, (oam_count / 2) as u8));
+                    oam_count += 1;
+                }
+                (false, _, Some(_)) if oam_count % 2 == 1 => {
+                    self.catch_up_ppu();
+                    self.ppu.write_reg(4, oam_value, &mut self.cartridge);
+                    oam_count += 1;
+                    if oam_count == 512 {
+                        self.oam_dma_page = None;
+                    }
+                }
+                // Waiting for the right kind of cycle
+                _ => {
+                    if dummy_reads {
+                        self.read_bus(address);
+                    }
+                }
+            }
+            self.dma_cycle();
+        }
+    }
+
+    /// Drops a DMC fetch that's waiting for the CPU to halt
+    fn cancel_dmc_dma(&mut self) {
+        if self.dmc_dma_address.take().is_some() {
+            self.dma_need_dummy_read = false;
+            self.dma_need_halt = self.oam_dma_page.is_some();
         }
-        // println!("Wrote {:#02x} to address {:#02x}", value, address);
+    }
+
+    fn dma_cycle(&mut self) {
+        self.tick();
+        self.dma_cycles += 1;
     }
 }
 
@@ -66,11 +337,201 @@ impl Powerable for Interconnect {
         self.ram.power_on();
         self.ppu.power_on();
         self.apu.power_on();
+
+        self.clock.restart();
+        self.cycle = 0;
+        self.open_bus = 0;
+        self.error = None;
+        self.oam_dma_page = None;
+        self.dmc_dma_address = None;
+        self.dma_need_halt = false;
+        self.dma_need_dummy_read = false;
+        self.dma_cycles = 0;
     }
     fn reset(&mut self) {
         self.cartridge.reset();
         self.ram.reset();
         self.ppu.reset();
         self.apu.reset();
+
+        self.oam_dma_page = None;
+        self.dmc_dma_address = None;
+        self.dma_need_halt = false;
+        self.dma_need_dummy_read = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::CPU;
+    use crate::test_rom::TestRom;
+
+    #[test]
+    fn disabling_the_dmc_cancels_its_pending_dma() {
+        let mut bus = Interconnect::default();
+        bus.power_on();
+        // A 17 byte sample, its first byte gets requested right away
+        bus.write_mem(0x4013, 1);
+        bus.write_mem(0x4015, 0x10);
+        bus.tick();
+        assert_eq!(bus.dmc_dma_address, Some(0xC000));
+
+        // Disabled before the next read cycle could halt the CPU for the fetch
+        bus.write_mem(0x4015, 0);
+        bus.read_mem(0x0000);
+        assert_eq!(bus.dmc_dma_address, None);
+        assert_eq!(bus.take_dma_cycles(), 0);
+        assert_eq!(bus.apu.read_status(0) & 0x10, 0);
+    }
+
+    /// Runs the LDA and STA $4014 in `program` and the NOP after them. Returns the cycle of the
+    /// write, the cycles the CPU was halted for and the OAM filled from page $02.
+    fn oam_dma(program: &[u8]) -> (u64, u64, Vec<u8>) {
+        let mut bus = Interconnect::default();
+        bus.load_rom(TestRom::new(2).code(0x8000, program).build())
+            .unwrap();
+        bus.power_on();
+        for i in 0..=0xFF {
+            bus.write_mem(0x0200 + i, (i as u8).wrapping_mul(7));
+        }
+        bus.write_mem(0x0000, 0x02);
+
+        let mut cpu = CPU::default();
+        cpu.power_on();
+        let mut instruction = || {
+            cpu.do_cycle(&mut bus);
+            while !cpu.instruction_done() {
+                cpu.do_cycle(&mut bus);
+            }
+            cpu.cycle
+        };
+        // Reset and the LDA
+        instruction();
+        let start = instruction();
+        // The DMA halts the CPU on the NOP's opcode fetch
+        instruction();
+        let cycles = instruction() - start - 6;
+
+        let oam = (0..=0xFF)
+            .map(|i| {
+                bus.write_mem(0x2003, i);
+                bus.read_mem(0x2004)
+            })
+            .collect();
+        // STA abs writes on its fourth cycle
+        (start + 3, cycles, oam)
+    }
+
+    #[test]
+    fn oam_dma_takes_513_or_514_cycles() {
+        // LDA #$02 and LDA $00 put the STA $4014 on cycles of different parity
+        let (even_write, even_cycles, even_oam) = oam_dma(&[0xA9, 0x02, 0x8D, 0x14, 0x40]);
+        let (odd_write, odd_cycles, odd_oam) = oam_dma(&[0xA5, 0x00, 0x8D, 0x14, 0x40]);
+        assert_eq!((even_write % 2, even_cycles), (0, 513));
+        assert_eq!((odd_write % 2, odd_cycles), (1, 514));
+        for oam in [even_oam, odd_oam] {
+            for (i, &value) in oam.iter().enumerate() {
+                // The unimplemented bits of the attribute bytes read back as 0
+                let mask = if i % 4 == 2 { 0xE3 } else { 0xFF };
+                assert_eq!(value, (i as u8).wrapping_mul(7) & mask);
+            }
+        }
+    }
+
+    /// A bus whose DMC just requested the first byte of a sample, `cycles` cycles after power on
+    fn dmc_bus(cycles: u64) -> Interconnect {
+        let mut bus = Interconnect::default();
+        bus.load_rom(TestRom::new(2).build()).unwrap();
+        bus.power_on();
+        for _ in 0..cycles {
+            bus.tick();
+        }
+        // The fastest rate and a 17 byte sample
+        bus.write_mem(0x4010, 0x0F);
+        bus.write_mem(0x4013, 1);
+        bus.write_mem(0x4015, 0x10);
+        bus.tick();
+        bus
+    }
+
+    #[test]
+    fn dmc_fetches_halt_the_cpu() {
+        // Halt, dummy and get cycles, plus one to wait for a get cycle
+        for (cycles, halted) in [(1, 3), (2, 4)] {
+            let mut bus = dmc_bus(cycles);
+            bus.read_mem(0x0000);
+            assert_eq!(bus.take_dma_cycles(), halted);
+            assert_eq!(bus.dmc_dma_address, None);
+        }
+    }
+
+    #[test]
+    fn dmc_fetches_during_oam_dma_take_two_cycles() {
+        // 513 or 514 cycles for the OAM DMA, the DMC's halt and dummy cycles overlap with it
+        for (wait, halted) in [(300, 516), (301, 515)] {
+            let mut bus = dmc_bus(1);
+            bus.read_mem(0x0000);
+            bus.take_dma_cycles();
+            // The next fetch comes while the OAM DMA runs
+            for _ in 0..wait {
+                bus.tick();
+            }
+            bus.write_mem(0x4014, 0x02);
+            bus.tick();
+            bus.read_mem(0x0000);
+            assert_eq!(bus.take_dma_cycles(), halted);
+            assert_eq!(bus.dmc_dma_address, None);
+        }
+    }
+
+    /// A strict bus with a 32 KiB PRG-ROM, CHR-RAM cartridge for `mapper`
+    fn strict_bus(mapper: u8) -> Interconnect {
+        let mut bus = Interconnect::default();
+        bus.load_rom(TestRom::new(2).mapper(mapper).build())
+            .unwrap();
+        bus.power_on();
+        bus.set_strict(true);
+        bus
+    }
+
+    fn unmapped_write(bus: &mut Interconnect, address: u16) -> bool {
+        bus.write_mem(address, 0x55);
+        match bus.take_error() {
+            Some(NesError::UnmappedWrite { address: a, value }) => {
+                assert_eq!((a, value), (address, 0x55));
+                true
+            }
+            None => false,
+            Some(error) => panic!("unexpected error {:?}", error),
+        }
+    }
+
+    #[test]
+    fn strict_mode_reports_unmapped_cartridge_writes() {
+        // NROM has PRG-RAM, UxROM, CNROM and AxROM don't
+        let mut bus = strict_bus(0);
+        assert!(unmapped_write(&mut bus, 0x4020));
+        assert!(unmapped_write(&mut bus, 0x5FFF));
+        assert!(!unmapped_write(&mut bus, 0x6000));
+        assert!(!unmapped_write(&mut bus, 0x8000));
+        for mapper in [2, 3, 7] {
+            let mut bus = strict_bus(mapper);
+            assert!(unmapped_write(&mut bus, 0x5000));
+            assert!(unmapped_write(&mut bus, 0x6000));
+            assert!(unmapped_write(&mut bus, 0x7FFF));
+            assert!(!unmapped_write(&mut bus, 0x8000));
+        }
+    }
+
+    #[test]
+    fn strict_mode_reports_writes_to_disabled_prg_ram() {
+        let mut bus = strict_bus(4);
+        assert!(!unmapped_write(&mut bus, 0x6000));
+        // Write protected RAM still responds, it just ignores the write
+        bus.write_mem(0xA001, 0xC0);
+        assert!(!unmapped_write(&mut bus, 0x6000));
+        bus.write_mem(0xA001, 0x00);
+        assert!(unmapped_write(&mut bus, 0x6000));
     }
 }
@@ -1,10 +1,22 @@
 pub mod apu;
+pub mod blargg;
 pub mod cartridge;
+pub mod clock;
+pub mod controller;
 pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod error;
 pub mod ines;
 pub mod instructions;
 pub mod interconnect;
+pub mod mapper;
 pub mod nes;
 pub mod ppu;
 pub mod ram;
+pub mod region;
+pub mod save;
+#[cfg(test)]
+mod test_rom;
+pub mod trace;
 pub mod utils;
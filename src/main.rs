@@ -1,22 +1,175 @@
-use nesty::nes::{Powerable, NES};
+use nesty::debugger::Debugger;
+use nesty::disasm::{disassemble_range, parse_address, Symbols};
+use nesty::error::NesError;
+use nesty::nes::{Powerable, StopReason, NES};
+use nesty::save::SaveFile;
 
 use std::env;
-use std::fs::File;
-use std::io::Read;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
-fn main() {
+const USAGE: &str = "Usage: nesty <rom.nes>
+       nesty disasm <rom.nes> [start [end]] [--symbols <file>]
+       nesty debug <rom.nes> [--symbols <file>]";
+
+fn main() -> ExitCode {
+    let args: Vec<OsString> = env::args_os().skip(1).collect();
+    match args.first().and_then(|arg| arg.to_str()) {
+        None => {
+            eprintln!("{}", USAGE);
+            ExitCode::FAILURE
+        }
+        Some("disasm") => disasm_command(&args[1..]),
+        Some("debug") => debug_command(&args[1..]),
+        Some(_) => {
+            let rom_path = PathBuf::from(&args[0]);
+            match run(&rom_path) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("{}: {}", rom_path.display(), e);
+                    ExitCode::FAILURE
+                }
+            }
+        }
+    }
+}
+
+fn run(rom_path: &Path) -> Result<(), NesError> {
+    let ines = fs::read(rom_path)?;
     let mut nes = NES::default();
-    let ines = read_ines_file();
     nes.power_on();
-    nes.load_rom(ines);
-    nes.run();
+    nes.load_rom(ines)?;
+    nes.attach_save_file(SaveFile::for_rom(rom_path))?;
+    let result = loop {
+        let stop = nes.run_frame();
+        if let Some(e) = nes.take_save_error() {
+            report_save_error(&e);
+        }
+        match stop {
+            Ok(StopReason::FrameDone) => {}
+            Ok(StopReason::Jammed(jam)) => {
+                eprintln!("CPU jammed by opcode {:02X} at {:04X}", jam.opcode, jam.pc);
+                break Ok(());
+            }
+            Ok(_) => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
+    finish_saving(&mut nes);
+    result
+}
+
+/// Writes the save file one last time, and says if that or an earlier periodic save failed
+fn finish_saving(nes: &mut NES) {
+    let earlier = nes.take_save_error();
+    for e in earlier.into_iter().chain(nes.save().err()) {
+        report_save_error(&e);
+    }
+}
+
+fn report_save_error(e: &NesError) {
+    eprintln!("Failed to write save file: {}", e);
 }
 
-fn read_ines_file() -> Vec<u8> {
-    let rom_file_name = env::args().nth(1).unwrap(); // TODO error handling
-    let mut ines_file = File::open(rom_file_name).unwrap();
+/// Disassembles the CPU address space with the banks the mapper has at power on, $8000-$FFFF
+/// unless a range is given
+fn disasm_command(args: &[OsString]) -> ExitCode {
+    let mut rom_path = None;
+    let mut symbols_path = None;
+    let mut range = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--symbols" {
+            symbols_path = args.next().map(PathBuf::from);
+            if symbols_path.is_none() {
+                eprintln!("{}", USAGE);
+                return ExitCode::FAILURE;
+            }
+        } else if rom_path.is_none() {
+            rom_path = Some(PathBuf::from(arg));
+        } else if let Some(address) = arg.to_str().and_then(parse_address) {
+            range.push(address);
+        } else {
+            eprintln!("Invalid address {}", arg.to_string_lossy());
+            return ExitCode::FAILURE;
+        }
+    }
+    let (Some(rom_path), true) = (rom_path, range.len() <= 2) else {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    };
+    let start = range.first().copied().unwrap_or(0x8000);
+    let end = range.get(1).copied().unwrap_or(0xFFFF);
+
+    let symbols = match symbols_path.map(|path| load_symbols(&path)).transpose() {
+        Ok(symbols) => symbols,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut nes = NES::default();
+    nes.power_on();
+    if let Err(e) = fs::read(&rom_path)
+        .map_err(NesError::from)
+        .and_then(|ines| nes.load_rom(ines))
+    {
+        eprintln!("{}: {}", rom_path.display(), e);
+        return ExitCode::FAILURE;
+    }
+    for line in disassemble_range(|address| nes.peek(address), start, end, symbols.as_ref()) {
+        println!("{}", line);
+    }
+    ExitCode::SUCCESS
+}
+
+/// Runs the ROM under the interactive debugger, stopped at the first instruction
+fn debug_command(args: &[OsString]) -> ExitCode {
+    let (rom_path, symbols_path) = match args {
+        [rom_path] => (PathBuf::from(rom_path), None),
+        [rom_path, option, symbols_path] if option == "--symbols" => {
+            (PathBuf::from(rom_path), Some(PathBuf::from(symbols_path)))
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    let symbols = match symbols_path.map(|path| load_symbols(&path)).transpose() {
+        Ok(symbols) => symbols,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut nes = NES::default();
+    nes.power_on();
+    let loaded = fs::read(&rom_path)
+        .map_err(NesError::from)
+        .and_then(|ines| nes.load_rom(ines))
+        .and_then(|()| nes.attach_save_file(SaveFile::for_rom(&rom_path)))
+        // Through the reset sequence, to the first instruction
+        .and_then(|()| nes.step_instruction());
+    if let Err(e) = loaded {
+        eprintln!("{}: {}", rom_path.display(), e);
+        return ExitCode::FAILURE;
+    }
+    let mut debugger = Debugger::new(nes, symbols);
+    let result = debugger.repl(io::stdin().lock(), io::stdout());
+    finish_saving(debugger.nes());
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
 
-    let mut ines = Vec::new();
-    ines_file.read_to_end(&mut ines).unwrap();
-    ines
+fn load_symbols(path: &Path) -> Result<Symbols, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Symbols::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
 }
@@ -0,0 +1,171 @@
+pub mod axrom;
+pub mod cnrom;
+pub mod mmc1;
+pub mod mmc3;
+pub mod nrom;
+pub mod uxrom;
+
+use crate::ines::RomHeader;
+
+use axrom::AxROM;
+use cnrom::CNROM;
+use mmc1::MMC1;
+use mmc3::{Mmc3Revision, MMC3};
+use nrom::NROM;
+use uxrom::UxROM;
+
+const CHR_RAM_SIZE: usize = 8 * 1024;
+const PRG_RAM_SIZE: usize = 8 * 1024;
+
+/// How the four logical nametables map onto the nametable memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    SingleScreenA,
+    SingleScreenB,
+    FourScreen,
+}
+
+/// The board inside a cartridge, which decides what the CPU and the PPU see of its memory
+pub trait Mapper {
+    /// Reads from $4020-$FFFF, `None` when nothing drives the data bus
+    fn cpu_read(&mut self, address: u16) -> Option<u8>;
+    /// Writes to $4020-$FFFF, false when nothing on the board responds to the address. Writes
+    /// to ROM count as responded to.
+    fn cpu_write(&mut self, address: u16, value: u8) -> bool;
+
+    /// Reads from the pattern tables at $0000-$1FFF
+    fn ppu_read(&mut self, address: u16) -> u8;
+    fn ppu_write(&mut self, address: u16, value: u8);
+
+    fn mirroring(&self) -> Mirroring;
+
+    /// Level of the IRQ output
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Called for every address the PPU puts on its bus, for mappers that watch it
+    /// (e.g. the MMC3 counts scanlines from the rising edges of A12)
+    fn notify_ppu_address(&mut self, _address: u16) {}
+
+    /// Called once every CPU cycle, for mappers that count M2 cycles
+    fn cpu_tick(&mut self) {}
+
+    /// Battery-backed PRG-RAM that should persist between sessions
+    fn save_ram(&self) -> Option<&[u8]> {
+        None
+    }
+
+    fn save_ram_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+}
+
+/// Creates the mapper the header asks for
+pub fn create(header: &RomHeader, prg_rom: Vec<u8>, chr_rom: Vec<u8>) -> Option<Box<dyn Mapper>> {
+    let mirroring = header.mirroring;
+    let battery = header.battery;
+    let chr = ChrMemory::new(chr_rom, header.chr_ram_size + header.chr_nvram_size);
+    let prg_ram_size = match header.prg_ram_size + header.prg_nvram_size {
+        0 => PRG_RAM_SIZE,
+        size => size,
+    };
+    let mapper: Box<dyn Mapper> = match header.mapper {
+        0 => Box::new(NROM::new(prg_rom, chr, mirroring, battery)),
+        1 => Box::new(MMC1::new(prg_rom, chr, prg_ram_size, battery)),
+        2 => Box::new(UxROM::new(prg_rom, chr, mirroring)),
+        3 => Box::new(CNROM::new(prg_rom, chr, mirroring)),
+        4 => {
+            let revision = match header.submapper {
+                4 => Mmc3Revision::A,
+                _ => Mmc3Revision::B,
+            };
+            Box::new(MMC3::new(prg_rom, chr, mirroring, battery, revision))
+        }
+        7 => Box::new(AxROM::new(prg_rom, chr)),
+        _ => return None,
+    };
+    Some(mapper)
+}
+
+/// The pattern tables on the cartridge, CHR-RAM when it has no CHR-ROM
+pub struct ChrMemory {
+    data: Vec<u8>,
+    writable: bool,
+}
+
+impl ChrMemory {
+    /// `ram_size` is the CHR-RAM size from a NES 2.0 header, 0 if unknown
+    pub fn new(chr_rom: Vec<u8>, ram_size: usize) -> Self {
+        match chr_rom.is_empty() {
+            true => ChrMemory {
+                data: vec![0; if ram_size > 0 { ram_size } else { CHR_RAM_SIZE }],
+                writable: true,
+            },
+            false => ChrMemory {
+                data: chr_rom,
+                writable: false,
+            },
+        }
+    }
+
+    fn read(&self, bank: usize, bank_size: usize, address: u16) -> u8 {
+        read_banked(&self.data, bank, bank_size, address)
+    }
+
+    fn write(&mut self, bank: usize, bank_size: usize, address: u16, value: u8) {
+        if self.writable {
+            write_banked(&mut self.data, bank, bank_size, address, value);
+        }
+    }
+}
+
+/// Reads `address` from a `bank_size` window switched to `bank`. Bank numbers wrap around the
+/// size of the memory, like the unconnected high bank bits do.
+fn read_banked(memory: &[u8], bank: usize, bank_size: usize, address: u16) -> u8 {
+    match memory.len() {
+        0 => 0,
+        len => memory[(bank * bank_size + address as usize % bank_size) % len],
+    }
+}
+
+fn write_banked(memory: &mut [u8], bank: usize, bank_size: usize, address: u16, value: u8) {
+    let len = memory.len();
+    if len > 0 {
+        memory[(bank * bank_size + address as usize % bank_size) % len] = value;
+    }
+}
+
+/// Number of `bank_size` banks in the memory
+fn bank_count(memory: &[u8], bank_size: usize) -> usize {
+    (memory.len() / bank_size).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom::TestRom;
+
+    /// The battery-backed PRG-RAM the mapper of `rom` gets
+    fn save_ram_size(rom: TestRom) -> usize {
+        let ines = rom.build();
+        let header = RomHeader::parse(&ines).unwrap();
+        let mapper = create(&header, ines[16..].to_vec(), Vec::new()).unwrap();
+        mapper.save_ram().unwrap().len()
+    }
+
+    #[test]
+    fn sizes_mmc1_prg_ram_from_the_header() {
+        let mmc1 = || TestRom::new(2).mapper(1).battery();
+        // iNES byte 8 of 0 means 8K
+        assert_eq!(save_ram_size(mmc1()), 0x2000);
+        assert_eq!(save_ram_size(mmc1().header(8, 2)), 0x4000);
+        // NES 2.0 SXROM, 32K of battery-backed PRG-RAM
+        assert_eq!(
+            save_ram_size(mmc1().header(7, 0x08).header(10, 0x90)),
+            0x8000
+        );
+    }
+}
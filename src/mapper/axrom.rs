@@ -0,0 +1,78 @@
+use super::{read_banked, ChrMemory, Mapper, Mirroring};
+
+const PRG_BANK_SIZE: usize = 32 * 1024;
+const CHR_BANK_SIZE: usize = 8 * 1024;
+
+/// Mapper 7, a switchable 32K PRG bank and single-screen mirroring selected by software
+pub struct AxROM {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_bank: usize,
+    mirroring: Mirroring,
+}
+
+impl AxROM {
+    pub fn new(prg_rom: Vec<u8>, chr: ChrMemory) -> Self {
+        AxROM {
+            prg_rom,
+            chr,
+            prg_bank: 0,
+            mirroring: Mirroring::SingleScreenA,
+        }
+    }
+}
+
+impl Mapper for AxROM {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x8000..=0xFFFF => Some(read_banked(
+                &self.prg_rom,
+                self.prg_bank,
+                PRG_BANK_SIZE,
+                address,
+            )),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) -> bool {
+        if address < 0x8000 {
+            return false;
+        }
+        self.prg_bank = (value & 0x07) as usize;
+        self.mirroring = match value & 0x10 {
+            0 => Mirroring::SingleScreenA,
+            _ => Mirroring::SingleScreenB,
+        };
+        true
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(0, CHR_BANK_SIZE, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(0, CHR_BANK_SIZE, address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switches_the_prg_bank_and_the_nametable() {
+        let prg_rom = (0..8).flat_map(|bank| [bank; PRG_BANK_SIZE]).collect();
+        let mut axrom = AxROM::new(prg_rom, ChrMemory::new(Vec::new(), 0));
+        assert_eq!(axrom.cpu_read(0xFFFF), Some(0));
+        assert_eq!(axrom.mirroring(), Mirroring::SingleScreenA);
+        axrom.cpu_write(0x8000, 0x13);
+        assert_eq!(axrom.cpu_read(0x8000), Some(3));
+        assert_eq!(axrom.cpu_read(0xFFFF), Some(3));
+        assert_eq!(axrom.mirroring(), Mirroring::SingleScreenB);
+    }
+}
@@ -0,0 +1,71 @@
+use super::{read_banked, ChrMemory, Mapper, Mirroring};
+
+const PRG_BANK_SIZE: usize = 32 * 1024;
+const CHR_BANK_SIZE: usize = 8 * 1024;
+
+/// Mapper 3, fixed PRG like NROM and a switchable 8K CHR bank
+pub struct CNROM {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    mirroring: Mirroring,
+    chr_bank: usize,
+}
+
+impl CNROM {
+    pub fn new(prg_rom: Vec<u8>, chr: ChrMemory, mirroring: Mirroring) -> Self {
+        CNROM {
+            prg_rom,
+            chr,
+            mirroring,
+            chr_bank: 0,
+        }
+    }
+}
+
+impl Mapper for CNROM {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            // 16K of PRG-ROM wraps around into $C000
+            0x8000..=0xFFFF => Some(read_banked(&self.prg_rom, 0, PRG_BANK_SIZE, address)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) -> bool {
+        if address < 0x8000 {
+            return false;
+        }
+        self.chr_bank = value as usize;
+        true
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(self.chr_bank, CHR_BANK_SIZE, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(self.chr_bank, CHR_BANK_SIZE, address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switches_the_chr_bank() {
+        let chr_rom = (0..4).flat_map(|bank| [bank; CHR_BANK_SIZE]).collect();
+        let chr = ChrMemory::new(chr_rom, 0);
+        let mut cnrom = CNROM::new(vec![0xEA; 0x4000], chr, Mirroring::Horizontal);
+        assert_eq!(cnrom.ppu_read(0x1FFF), 0);
+        cnrom.cpu_write(0x8000, 2);
+        assert_eq!(cnrom.ppu_read(0x0000), 2);
+        assert_eq!(cnrom.ppu_read(0x1FFF), 2);
+        // 16K of PRG-ROM is mirrored at $C000
+        assert_eq!(cnrom.cpu_read(0xC000), Some(0xEA));
+    }
+}
@@ -0,0 +1,309 @@
+use bitfield_struct::bitfield;
+
+use super::{bank_count, read_banked, write_banked, ChrMemory, Mapper, Mirroring};
+
+const PRG_BANK_SIZE: usize = 16 * 1024;
+const CHR_BANK_SIZE: usize = 4 * 1024;
+const PRG_RAM_BANK_SIZE: usize = 8 * 1024;
+/// SUROM and SXROM select the 256K half of their PRG-ROM with a CHR bank bit
+const PRG_OUTER_BANK_SIZE: usize = 256 * 1024;
+/// The shift register is full once this bit gets shifted down to bit 0
+const SHIFT_RESET: u8 = 0x10;
+
+#[bitfield(u8)]
+struct Control {
+    #[bits(2)]
+    mirroring: u8,
+    #[bits(2)]
+    prg_mode: u8,
+    #[bits(1)]
+    chr_4k_mode: bool,
+    #[bits(3)]
+    __: u8,
+}
+
+/// Mapper 1, registers are written one bit at a time through a serial shift register
+pub struct MMC1 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: ChrMemory,
+    battery: bool,
+
+    shift: u8,
+    control: Control,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,
+
+    cycle: u64,
+    last_write_cycle: Option<u64>,
+}
+
+impl MMC1 {
+    /// `prg_ram_size` is 8K for most boards, 16K for SOROM and 32K for SXROM
+    pub fn new(prg_rom: Vec<u8>, chr: ChrMemory, prg_ram_size: usize, battery: bool) -> Self {
+        MMC1 {
+            prg_rom,
+            prg_ram: vec![0; prg_ram_size],
+            chr,
+            battery,
+            shift: SHIFT_RESET,
+            // Starts with the last bank fixed at $C000
+            control: Control::from_bits(0x0C),
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+            cycle: 0,
+            last_write_cycle: None,
+        }
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x8000..=0x9FFF => self.control = Control::from_bits(value),
+            0xA000..=0xBFFF => self.chr_bank_0 = value,
+            0xC000..=0xDFFF => self.chr_bank_1 = value,
+            _ => self.prg_bank = value,
+        }
+    }
+
+    fn prg_bank(&self, address: u16) -> usize {
+        let bank = (self.prg_bank & 0x0F) as usize;
+        let last = (bank_count(&self.prg_rom, PRG_BANK_SIZE) - 1) & 0x0F;
+        let bank = match (self.control.prg_mode(), address) {
+            (0 | 1, 0x8000..=0xBFFF) => bank & !1,
+            (0 | 1, _) => bank | 1,
+            (2, 0x8000..=0xBFFF) => 0,
+            (2, _) => bank,
+            (_, 0x8000..=0xBFFF) => bank,
+            (_, _) => last,
+        };
+        let outer = match self.prg_rom.len() > PRG_OUTER_BANK_SIZE {
+            true => (self.chr_bank_0 & 0x10) as usize,
+            false => 0,
+        };
+        outer | bank
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_bank & 0x10 == 0
+    }
+
+    fn prg_ram_bank(&self) -> usize {
+        // SOROM and SXROM use CHR bank bits to select the PRG-RAM bank, on 8K boards those bits
+        // go to the CHR-ROM or aren't connected
+        match self.prg_ram.len() / PRG_RAM_BANK_SIZE {
+            0 | 1 => 0,
+            2 => ((self.chr_bank_0 >> 3) & 1) as usize,
+            _ => ((self.chr_bank_0 >> 2) & 3) as usize,
+        }
+    }
+
+    fn chr_bank(&self, address: u16) -> usize {
+        match (self.control.chr_4k_mode(), address) {
+            (false, 0x0000..=0x0FFF) => (self.chr_bank_0 & !1) as usize,
+            (false, _) => (self.chr_bank_0 | 1) as usize,
+            (true, 0x0000..=0x0FFF) => self.chr_bank_0 as usize,
+            (true, _) => self.chr_bank_1 as usize,
+        }
+    }
+}
+
+impl Mapper for MMC1 {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => Some(read_banked(
+                &self.prg_ram,
+                self.prg_ram_bank(),
+                PRG_RAM_BANK_SIZE,
+                address,
+            )),
+            0x8000..=0xFFFF => Some(read_banked(
+                &self.prg_rom,
+                self.prg_bank(address),
+                PRG_BANK_SIZE,
+                address,
+            )),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) -> bool {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                let bank = self.prg_ram_bank();
+                write_banked(&mut self.prg_ram, bank, PRG_RAM_BANK_SIZE, address, value);
+            }
+            0x8000..=0xFFFF => {
+                // Writes on consecutive cycles (from read-modify-write instructions) only
+                // see the first one
+                let consecutive = self
+                    .last_write_cycle
+                    .is_some_and(|last| self.cycle == last + 1);
+                self.last_write_cycle = Some(self.cycle);
+                if consecutive {
+                    return true;
+                }
+
+                if value & 0x80 != 0 {
+                    self.shift = SHIFT_RESET;
+                    self.control = Control::from_bits(self.control.into_bits() | 0x0C);
+                    return true;
+                }
+                let full = self.shift & 1 != 0;
+                self.shift = (self.shift >> 1) | (value & 1) << 4;
+                if full {
+                    self.write_register(address, self.shift);
+                    self.shift = SHIFT_RESET;
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr
+            .read(self.chr_bank(address), CHR_BANK_SIZE, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        let bank = self.chr_bank(address);
+        self.chr.write(bank, CHR_BANK_SIZE, address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control.mirroring() {
+            0 => Mirroring::SingleScreenA,
+            1 => Mirroring::SingleScreenB,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn cpu_tick(&mut self) {
+        self.cycle += 1;
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        self.battery.then_some(&self.prg_ram)
+    }
+
+    fn save_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.battery.then_some(&mut self.prg_ram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 128K of PRG-ROM where every byte holds the number of its 16K bank
+    fn mmc1(chr_rom: Vec<u8>, prg_ram_size: usize) -> MMC1 {
+        let prg_rom = (0..8).flat_map(|bank| [bank; PRG_BANK_SIZE]).collect();
+        MMC1::new(prg_rom, ChrMemory::new(chr_rom, 0), prg_ram_size, false)
+    }
+
+    /// Writes a register through the shift register, one bit per write
+    fn write_register(mmc1: &mut MMC1, address: u16, value: u8) {
+        for bit in 0..5 {
+            mmc1.cpu_write(address, value >> bit & 1);
+            mmc1.cpu_tick();
+            mmc1.cpu_tick();
+        }
+    }
+
+    #[test]
+    fn switches_prg_banks() {
+        let mut mmc1 = mmc1(Vec::new(), PRG_RAM_BANK_SIZE);
+        // Starts in mode 3, the last bank is fixed at $C000
+        write_register(&mut mmc1, 0xE000, 5);
+        assert_eq!(mmc1.cpu_read(0x8000), Some(5));
+        assert_eq!(mmc1.cpu_read(0xC000), Some(7));
+        // Mode 2 fixes the first bank at $8000 instead
+        write_register(&mut mmc1, 0x8000, 0x08);
+        assert_eq!(mmc1.cpu_read(0x8000), Some(0));
+        assert_eq!(mmc1.cpu_read(0xC000), Some(5));
+        // Mode 0 switches 32K at a time and ignores the low bit
+        write_register(&mut mmc1, 0x8000, 0x00);
+        assert_eq!(mmc1.cpu_read(0x8000), Some(4));
+        assert_eq!(mmc1.cpu_read(0xC000), Some(5));
+    }
+
+    #[test]
+    fn ignores_writes_on_consecutive_cycles() {
+        let mut mmc1 = mmc1(Vec::new(), PRG_RAM_BANK_SIZE);
+        mmc1.cpu_tick();
+        for bit in [1, 1, 0, 0, 0] {
+            // The dummy write of a read-modify-write instruction, then the real one
+            mmc1.cpu_write(0xE000, 0);
+            mmc1.cpu_tick();
+            mmc1.cpu_write(0xE000, bit);
+            mmc1.cpu_tick();
+            mmc1.cpu_tick();
+        }
+        // Only the dummy writes were seen
+        assert_eq!(mmc1.cpu_read(0x8000), Some(0));
+    }
+
+    #[test]
+    fn takes_a_first_write_on_cycle_1() {
+        let mut mmc1 = mmc1(Vec::new(), PRG_RAM_BANK_SIZE);
+        mmc1.cpu_tick();
+        write_register(&mut mmc1, 0xE000, 3);
+        assert_eq!(mmc1.cpu_read(0x8000), Some(3));
+    }
+
+    #[test]
+    fn switches_chr_banks() {
+        let chr_rom = (0..4).flat_map(|bank| [bank; CHR_BANK_SIZE]).collect();
+        let mut mmc1 = mmc1(chr_rom, PRG_RAM_BANK_SIZE);
+        // 8K mode ignores the low bit
+        write_register(&mut mmc1, 0xA000, 3);
+        assert_eq!(mmc1.ppu_read(0x0000), 2);
+        assert_eq!(mmc1.ppu_read(0x1000), 3);
+        // 4K mode
+        write_register(&mut mmc1, 0x8000, 0x1C);
+        write_register(&mut mmc1, 0xC000, 1);
+        assert_eq!(mmc1.ppu_read(0x0000), 3);
+        assert_eq!(mmc1.ppu_read(0x1000), 1);
+    }
+
+    /// What $6000 reads with each value of the CHR bank 0 register, after writing them
+    fn prg_ram_banks(prg_ram_size: usize) -> Vec<u8> {
+        let mut mmc1 = mmc1(Vec::new(), prg_ram_size);
+        let values = [0x00, 0x04, 0x08, 0x0C];
+        for value in values {
+            write_register(&mut mmc1, 0xA000, value);
+            mmc1.cpu_write(0x6000, value | 1);
+        }
+        values
+            .into_iter()
+            .map(|value| {
+                write_register(&mut mmc1, 0xA000, value);
+                mmc1.cpu_read(0x6000).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn banks_prg_ram_only_when_larger_than_8k() {
+        // SNROM has CHR-RAM too, but the CHR bank bits don't reach its 8K of PRG-RAM
+        assert_eq!(prg_ram_banks(8 * 1024), [0x0D; 4]);
+        // SOROM selects its 8K bank with bit 3
+        assert_eq!(prg_ram_banks(16 * 1024), [0x05, 0x05, 0x0D, 0x0D]);
+        // SXROM with bits 2 and 3
+        assert_eq!(prg_ram_banks(32 * 1024), [0x01, 0x05, 0x09, 0x0D]);
+    }
+
+    #[test]
+    fn disables_prg_ram() {
+        let mut mmc1 = mmc1(Vec::new(), PRG_RAM_BANK_SIZE);
+        assert!(mmc1.cpu_write(0x6000, 0x42));
+        write_register(&mut mmc1, 0xE000, 0x10);
+        assert_eq!(mmc1.cpu_read(0x6000), None);
+        assert!(!mmc1.cpu_write(0x6000, 0));
+        write_register(&mut mmc1, 0xE000, 0x00);
+        assert_eq!(mmc1.cpu_read(0x6000), Some(0x42));
+    }
+}
@@ -0,0 +1,352 @@
+use bitfield_struct::bitfield;
+
+use super::{bank_count, read_banked, ChrMemory, Mapper, Mirroring};
+
+const PRG_BANK_SIZE: usize = 8 * 1024;
+const CHR_BANK_SIZE: usize = 1024;
+const PRG_RAM_SIZE: usize = 8 * 1024;
+/// A12 has to stay low for this many CPU cycles before a rising edge clocks the IRQ counter,
+/// which filters out the short drops between the sprite pattern fetches
+const A12_LOW_CYCLES: u64 = 3;
+
+/// The two MMC3 revisions differ in when a counter reaching 0 triggers an IRQ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mmc3Revision {
+    /// Only triggers when the counter gets decremented or reloaded to 0 (NEC chips)
+    A,
+    /// Triggers every time the counter is 0 after being clocked (Sharp chips)
+    B,
+}
+
+#[bitfield(u8)]
+struct BankSelect {
+    #[bits(3)]
+    register: u8,
+    #[bits(3)]
+    __: u8,
+    #[bits(1)]
+    prg_mode: bool,
+    #[bits(1)]
+    chr_inversion: bool,
+}
+
+/// Mapper 4, 8K PRG and 1K/2K CHR banks with a scanline counter clocked by PPU A12
+pub struct MMC3 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: ChrMemory,
+    battery: bool,
+    revision: Mmc3Revision,
+    four_screen: bool,
+
+    bank_select: BankSelect,
+    registers: [u8; 8],
+    mirroring: Mirroring,
+    prg_ram_enabled: bool,
+    prg_ram_write_protected: bool,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq: bool,
+
+    cycle: u64,
+    a12: bool,
+    a12_low_since: u64,
+}
+
+impl MMC3 {
+    pub fn new(
+        prg_rom: Vec<u8>,
+        chr: ChrMemory,
+        mirroring: Mirroring,
+        battery: bool,
+        revision: Mmc3Revision,
+    ) -> Self {
+        MMC3 {
+            prg_rom,
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            chr,
+            battery,
+            revision,
+            four_screen: mirroring == Mirroring::FourScreen,
+            bank_select: BankSelect::new(),
+            registers: [0; 8],
+            mirroring,
+            prg_ram_enabled: true,
+            prg_ram_write_protected: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq: false,
+            cycle: 0,
+            a12: false,
+            a12_low_since: 0,
+        }
+    }
+
+    fn prg_bank(&self, address: u16) -> usize {
+        let second_last = bank_count(&self.prg_rom, PRG_BANK_SIZE).saturating_sub(2);
+        let r6 = self.registers[6] as usize & 0x3F;
+        let r7 = self.registers[7] as usize & 0x3F;
+        match (self.bank_select.prg_mode(), address) {
+            (false, 0x8000..=0x9FFF) => r6,
+            (true, 0x8000..=0x9FFF) => second_last,
+            (_, 0xA000..=0xBFFF) => r7,
+            (false, 0xC000..=0xDFFF) => second_last,
+            (true, 0xC000..=0xDFFF) => r6,
+            (_, _) => second_last + 1,
+        }
+    }
+
+    fn chr_bank(&self, address: u16) -> usize {
+        // The inversion swaps the 2K banks and the 1K banks
+        let address = match self.bank_select.chr_inversion() {
+            true => address ^ 0x1000,
+            false => address,
+        };
+        let r = &self.registers;
+        match address {
+            0x0000..=0x07FF => (r[0] & !1) as usize | (address >> 10 & 1) as usize,
+            0x0800..=0x0FFF => (r[1] & !1) as usize | (address >> 10 & 1) as usize,
+            _ => r[2 + (address as usize - 0x1000) / CHR_BANK_SIZE] as usize,
+        }
+    }
+
+    fn clock_irq_counter(&mut self) {
+        let previous = self.irq_counter;
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+        } else {
+            self.irq_counter -= 1;
+        }
+        let triggers = match self.revision {
+            Mmc3Revision::A => (previous > 0 || self.irq_reload) && self.irq_counter == 0,
+            Mmc3Revision::B => self.irq_counter == 0,
+        };
+        if triggers && self.irq_enabled {
+            self.irq = true;
+        }
+        self.irq_reload = false;
+    }
+}
+
+impl Mapper for MMC3 {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled => {
+                Some(self.prg_ram[(address & 0x1FFF) as usize])
+            }
+            0x8000..=0xFFFF => Some(read_banked(
+                &self.prg_rom,
+                self.prg_bank(address),
+                PRG_BANK_SIZE,
+                address,
+            )),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) -> bool {
+        let even = address & 1 == 0;
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled => {
+                if !self.prg_ram_write_protected {
+                    self.prg_ram[(address & 0x1FFF) as usize] = value;
+                }
+            }
+            0x8000..=0x9FFF if even => self.bank_select = BankSelect::from_bits(value),
+            0x8000..=0x9FFF => self.registers[self.bank_select.register() as usize] = value,
+            // Boards with four-screen VRAM ignore it
+            0xA000..=0xBFFF if even && self.four_screen => {}
+            0xA000..=0xBFFF if even => {
+                self.mirroring = match value & 1 {
+                    0 => Mirroring::Vertical,
+                    _ => Mirroring::Horizontal,
+                };
+            }
+            0xA000..=0xBFFF if !even => {
+                self.prg_ram_enabled = value & 0x80 != 0;
+                self.prg_ram_write_protected = value & 0x40 != 0;
+            }
+            0xC000..=0xDFFF if even => self.irq_latch = value,
+            0xC000..=0xDFFF => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            0xE000..=0xFFFF if even => {
+                self.irq_enabled = false;
+                self.irq = false;
+            }
+            0xE000..=0xFFFF => self.irq_enabled = true,
+            _ => return false,
+        }
+        true
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr
+            .read(self.chr_bank(address), CHR_BANK_SIZE, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        let bank = self.chr_bank(address);
+        self.chr.write(bank, CHR_BANK_SIZE, address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq(&self) -> bool {
+        self.irq
+    }
+
+    fn notify_ppu_address(&mut self, address: u16) {
+        let a12 = address & 0x1000 != 0;
+        if a12 && !self.a12 && self.cycle - self.a12_low_since >= A12_LOW_CYCLES {
+            self.clock_irq_counter();
+        }
+        if !a12 && self.a12 {
+            self.a12_low_since = self.cycle;
+        }
+        self.a12 = a12;
+    }
+
+    fn cpu_tick(&mut self) {
+        self.cycle += 1;
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        self.battery.then_some(&self.prg_ram)
+    }
+
+    fn save_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.battery.then_some(&mut self.prg_ram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 64K of PRG-ROM and 8K of CHR-ROM where every byte holds the number of its bank
+    fn mmc3(revision: Mmc3Revision) -> MMC3 {
+        let prg_rom = (0..8).flat_map(|bank| [bank; PRG_BANK_SIZE]).collect();
+        let chr_rom = (0..8).flat_map(|bank| [bank; CHR_BANK_SIZE]).collect();
+        let chr = ChrMemory::new(chr_rom, 0);
+        MMC3::new(prg_rom, chr, Mirroring::Vertical, false, revision)
+    }
+
+    fn select_banks(mmc3: &mut MMC3, mode: u8, banks: [u8; 8]) {
+        for (register, bank) in banks.into_iter().enumerate() {
+            mmc3.cpu_write(0x8000, mode | register as u8);
+            mmc3.cpu_write(0x8001, bank);
+        }
+    }
+
+    fn prg_banks(mmc3: &mut MMC3) -> [u8; 4] {
+        [0x8000, 0xA000, 0xC000, 0xE000].map(|address| mmc3.cpu_read(address).unwrap())
+    }
+
+    fn chr_banks(mmc3: &mut MMC3) -> Vec<u8> {
+        (0..8).map(|i| mmc3.ppu_read(i * 0x400)).collect()
+    }
+
+    #[test]
+    fn switches_prg_banks() {
+        let mut mmc3 = mmc3(Mmc3Revision::B);
+        select_banks(&mut mmc3, 0x00, [0, 0, 0, 0, 0, 0, 2, 3]);
+        assert_eq!(prg_banks(&mut mmc3), [2, 3, 6, 7]);
+        // Mode 1 swaps $8000 and $C000
+        select_banks(&mut mmc3, 0x40, [0, 0, 0, 0, 0, 0, 2, 3]);
+        assert_eq!(prg_banks(&mut mmc3), [6, 3, 2, 7]);
+    }
+
+    #[test]
+    fn switches_chr_banks() {
+        let mut mmc3 = mmc3(Mmc3Revision::B);
+        // The 2K banks ignore the low bit
+        select_banks(&mut mmc3, 0x00, [3, 4, 1, 2, 3, 7, 0, 0]);
+        assert_eq!(chr_banks(&mut mmc3), [2, 3, 4, 5, 1, 2, 3, 7]);
+        // Inversion puts the 1K banks at $0000
+        select_banks(&mut mmc3, 0x80, [3, 4, 1, 2, 3, 7, 0, 0]);
+        assert_eq!(chr_banks(&mut mmc3), [1, 2, 3, 7, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn switches_mirroring() {
+        let mut mmc3 = mmc3(Mmc3Revision::B);
+        mmc3.cpu_write(0xA000, 1);
+        assert_eq!(mmc3.mirroring(), Mirroring::Horizontal);
+        mmc3.cpu_write(0xA000, 0);
+        assert_eq!(mmc3.mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn four_screen_boards_ignore_mirroring_writes() {
+        let chr = ChrMemory::new(Vec::new(), CHR_BANK_SIZE * 8);
+        let prg_rom = vec![0; PRG_BANK_SIZE * 4];
+        let mut mmc3 = MMC3::new(prg_rom, chr, Mirroring::FourScreen, false, Mmc3Revision::B);
+        assert!(mmc3.cpu_write(0xA000, 0));
+        assert_eq!(mmc3.mirroring(), Mirroring::FourScreen);
+    }
+
+    /// Raises A12 once after it was low long enough, like at the start of a scanline's sprite
+    /// fetches
+    fn clock_scanline(mmc3: &mut MMC3) {
+        mmc3.notify_ppu_address(0x0000);
+        for _ in 0..A12_LOW_CYCLES {
+            mmc3.cpu_tick();
+        }
+        mmc3.notify_ppu_address(0x1000);
+    }
+
+    #[test]
+    fn counts_scanlines_and_raises_irq() {
+        let mut mmc3 = mmc3(Mmc3Revision::B);
+        mmc3.cpu_write(0xC000, 2);
+        mmc3.cpu_write(0xC001, 0);
+        mmc3.cpu_write(0xE001, 0);
+        // Reloads to 2, then 1, then 0
+        for _ in 0..2 {
+            clock_scanline(&mut mmc3);
+            assert!(!mmc3.irq());
+        }
+        clock_scanline(&mut mmc3);
+        assert!(mmc3.irq());
+        // Acknowledged by $E000
+        mmc3.cpu_write(0xE000, 0);
+        assert!(!mmc3.irq());
+    }
+
+    #[test]
+    fn ignores_short_a12_drops() {
+        let mut mmc3 = mmc3(Mmc3Revision::B);
+        mmc3.cpu_write(0xC000, 0);
+        mmc3.cpu_write(0xE001, 0);
+        clock_scanline(&mut mmc3);
+        assert!(mmc3.irq());
+        mmc3.cpu_write(0xE000, 0);
+        mmc3.cpu_write(0xE001, 0);
+        mmc3.notify_ppu_address(0x0000);
+        mmc3.cpu_tick();
+        mmc3.notify_ppu_address(0x1000);
+        assert!(!mmc3.irq());
+    }
+
+    #[test]
+    fn revision_a_only_triggers_when_the_counter_reaches_0() {
+        for (revision, triggers) in [(Mmc3Revision::A, false), (Mmc3Revision::B, true)] {
+            let mut mmc3 = mmc3(revision);
+            // A latch of 0 keeps the counter at 0
+            mmc3.cpu_write(0xC000, 0);
+            clock_scanline(&mut mmc3);
+            mmc3.cpu_write(0xE001, 0);
+            clock_scanline(&mut mmc3);
+            assert_eq!(mmc3.irq(), triggers, "{:?}", revision);
+        }
+    }
+}
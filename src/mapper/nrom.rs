@@ -0,0 +1,64 @@
+use super::{ChrMemory, Mapper, Mirroring};
+
+const PRG_RAM_SIZE: usize = 8 * 1024;
+const CHR_BANK_SIZE: usize = 8 * 1024;
+
+/// Mapper 0, no bank switching. 16K of PRG-ROM is mirrored at $C000.
+pub struct NROM {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: ChrMemory,
+    mirroring: Mirroring,
+    battery: bool,
+}
+
+impl NROM {
+    pub fn new(prg_rom: Vec<u8>, chr: ChrMemory, mirroring: Mirroring, battery: bool) -> Self {
+        NROM {
+            prg_rom,
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            chr,
+            mirroring,
+            battery,
+        }
+    }
+}
+
+impl Mapper for NROM {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF => Some(self.prg_ram[(address & 0x1FFF) as usize]),
+            0x8000..=0xFFFF => Some(self.prg_rom[(address - 0x8000) as usize % self.prg_rom.len()]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) -> bool {
+        match address {
+            0x6000..=0x7FFF => self.prg_ram[(address & 0x1FFF) as usize] = value,
+            0x8000..=0xFFFF => {}
+            _ => return false,
+        }
+        true
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(0, CHR_BANK_SIZE, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(0, CHR_BANK_SIZE, address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        self.battery.then_some(&self.prg_ram)
+    }
+
+    fn save_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.battery.then_some(&mut self.prg_ram)
+    }
+}
@@ -0,0 +1,70 @@
+use super::{bank_count, read_banked, ChrMemory, Mapper, Mirroring};
+
+const PRG_BANK_SIZE: usize = 16 * 1024;
+const CHR_BANK_SIZE: usize = 8 * 1024;
+
+/// Mapper 2, a switchable 16K PRG bank at $8000 and the last one fixed at $C000
+pub struct UxROM {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    mirroring: Mirroring,
+    prg_bank: usize,
+}
+
+impl UxROM {
+    pub fn new(prg_rom: Vec<u8>, chr: ChrMemory, mirroring: Mirroring) -> Self {
+        UxROM {
+            prg_rom,
+            chr,
+            mirroring,
+            prg_bank: 0,
+        }
+    }
+}
+
+impl Mapper for UxROM {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        let bank = match address {
+            0x8000..=0xBFFF => self.prg_bank,
+            0xC000..=0xFFFF => bank_count(&self.prg_rom, PRG_BANK_SIZE) - 1,
+            _ => return None,
+        };
+        Some(read_banked(&self.prg_rom, bank, PRG_BANK_SIZE, address))
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) -> bool {
+        if address < 0x8000 {
+            return false;
+        }
+        self.prg_bank = value as usize;
+        true
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(0, CHR_BANK_SIZE, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(0, CHR_BANK_SIZE, address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switches_the_bank_at_8000() {
+        let prg_rom = (0..8).flat_map(|bank| [bank; PRG_BANK_SIZE]).collect();
+        let mut uxrom = UxROM::new(prg_rom, ChrMemory::new(Vec::new(), 0), Mirroring::Vertical);
+        assert_eq!(uxrom.cpu_read(0x8000), Some(0));
+        assert_eq!(uxrom.cpu_read(0xFFFF), Some(7));
+        uxrom.cpu_write(0xC123, 5);
+        assert_eq!(uxrom.cpu_read(0xBFFF), Some(5));
+        assert_eq!(uxrom.cpu_read(0xC000), Some(7));
+    }
+}
@@ -1,30 +1,352 @@
-use crate::cpu::CPU;
+use std::collections::HashSet;
+
+use crate::controller::{ButtonState, Controller, Port};
+use crate::cpu::{Jam, JamPolicy, CPU};
+use crate::error::NesError;
+use crate::interconnect::Interconnect;
+use crate::region::Region;
+use crate::save::SaveFile;
+use crate::trace::{nestest_line, Tracer};
+
+/// How often save RAM gets flushed while running, about every 10 seconds
+const SAVE_INTERVAL_FRAMES: u64 = 600;
 
 pub trait Powerable {
     fn power_on(&mut self);
     fn reset(&mut self);
 }
 
+/// Why one of the stepping or running functions returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The requested cycle or instruction was executed
+    Stepped,
+    /// The PPU finished a frame
+    FrameDone,
+    /// Execution reached an instruction at a breakpoint, which hasn't been executed yet
+    Breakpoint(u16),
+    /// The CPU is locked up, only a reset gets it going again
+    Jammed(Jam),
+    /// The requested number of cycles was executed
+    BudgetExhausted,
+    /// The `run_until` predicate returned true
+    ConditionMet,
+}
+
+/// The console, which owns the CPU and the bus with everything else on it. Every CPU cycle
+/// advances the master clock, the APU and cartridge tick along with it and the PPU runs the dots
+/// that fit in the cycle.
 #[derive(Default)]
 pub struct NES {
     cpu: CPU,
+    bus: Interconnect,
+    /// `None` picks the region the ROM header asks for
+    region_setting: Option<Region>,
+    rom_region: Region,
+    save_file: Option<SaveFile>,
+    next_save_frame: u64,
+    /// The first periodic save that failed since `take_save_error`
+    save_error: Option<NesError>,
+    breakpoints: HashSet<u16>,
 }
 
 impl NES {
-    pub fn load_rom(&mut self, ines: Vec<u8>) {
-        self.cpu.load_rom(ines)
+    /// Powers the console on, but starts execution at `pc` instead of the address in the reset
+    /// vector once the reset sequence finishes (e.g. $C000 for nestest's automation mode).
+    pub fn power_on_at(&mut self, pc: u16) {
+        self.power_on();
+        // Only read at the end of the reset sequence
+        self.cpu.set_start_pc(Some(pc));
+    }
+
+    pub fn set_jam_policy(&mut self, jam_policy: JamPolicy) {
+        self.cpu.set_jam_policy(jam_policy);
+    }
+
+    pub fn load_rom(&mut self, ines: Vec<u8>) -> Result<(), NesError> {
+        let header = self.bus.load_rom(ines)?;
+        self.rom_region = Region::from_timing(header.timing);
+        self.apply_region();
+        Ok(())
+    }
+
+    /// Forces the console to behave like an NTSC, PAL or Dendy one. `None`, the default, uses the
+    /// timing in the ROM header.
+    pub fn set_region(&mut self, region: Option<Region>) {
+        self.region_setting = region;
+        self.apply_region();
+    }
+
+    /// The region the console is running as
+    pub fn region(&self) -> Region {
+        self.bus.region()
+    }
+
+    fn apply_region(&mut self) {
+        self.bus
+            .set_region(self.region_setting.unwrap_or(self.rom_region));
+    }
+
+    /// Sets how many master clock cycles the PPU runs ahead of the CPU, less than a CPU cycle.
+    /// Real consoles power on with a random alignment, picking one makes timing-sensitive test
+    /// ROMs deterministic. 0 by default.
+    pub fn set_cpu_ppu_alignment(&mut self, ppu_phase: u64) {
+        self.bus.set_ppu_phase(ppu_phase);
+    }
+
+    /// Loads the cartridge's save RAM from `save_file`, which it will be flushed to periodically,
+    /// on `save` and when the NES is dropped. Does nothing for cartridges without a battery.
+    ///
+    /// Periodic saves keep running when one fails, `take_save_error` returns the error. Errors
+    /// while dropping are lost, call `save` before that to see them.
+    pub fn attach_save_file(&mut self, mut save_file: SaveFile) -> Result<(), NesError> {
+        if let Some(ram) = self.bus.cartridge_mut().save_ram_mut() {
+            save_file.load(ram)?;
+            self.save_file = Some(save_file);
+        }
+        Ok(())
+    }
+
+    /// Writes save RAM to the save file if it changed
+    pub fn save(&mut self) -> Result<(), NesError> {
+        match (&mut self.save_file, self.bus.cartridge().save_ram()) {
+            (Some(save_file), Some(ram)) => Ok(save_file.flush(ram)?),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn take_save_error(&mut self) -> Option<NesError> {
+        self.save_error.take()
+    }
+
+    /// Makes reads and writes of unmapped addresses stop execution with an error instead of
+    /// behaving like the console, which reads the open bus and ignores the write
+    pub fn set_strict(&mut self, strict: bool) {
+        self.bus.set_strict(strict);
+    }
+
+    /// The last picture the PPU rendered, 256x240 palette indices
+    pub fn frame_buffer(&self) -> &[u8] {
+        self.bus.ppu().frame_buffer()
+    }
+
+    /// Plugs a device into `port`, `None` leaves the port empty
+    pub fn plug_controller(&mut self, port: Port, controller: Option<Box<dyn Controller>>) {
+        self.bus.port_mut(port).plug(controller);
+    }
+
+    /// Sets the pressed buttons of the controller in `port`
+    pub fn set_buttons(&mut self, port: Port, buttons: ButtonState) {
+        if let Some(controller) = self.bus.port_mut(port).controller_mut() {
+            controller.set_buttons(buttons);
+        }
+    }
+
+    /// Sample rate of the audio stream, 44.1 kHz by default
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.bus.apu_mut().set_sample_rate(sample_rate);
+    }
+
+    /// Audio produced since the last call, mono f32 samples at the sample rate
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.bus.apu_mut().take_samples()
+    }
+
+    /// Attaches a tracer that gets to see every instruction and bus access, or detaches it with
+    /// `None`. Returns the previous one.
+    pub fn set_tracer(&mut self, tracer: Option<Box<dyn Tracer>>) -> Option<Box<dyn Tracer>> {
+        self.bus.set_tracer(tracer)
+    }
+
+    /// Reads CPU memory without side effects, see `Interconnect::peek`
+    pub fn peek(&mut self, address: u16) -> u8 {
+        self.bus.peek(address)
     }
 
-    pub fn run(&mut self) {
-        self.cpu.run();
+    /// Writes CPU memory like the CPU would, registers included, but without clocking anything.
+    /// ROM doesn't change. In strict mode a write to an unmapped address is an error.
+    pub fn poke(&mut self, address: u16, value: u8) -> Result<(), NesError> {
+        self.bus.write_mem(address, value);
+        match self.bus.take_error() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// The instruction at PC and the state before it in the format of nestest.log
+    pub fn trace_line(&mut self) -> String {
+        nestest_line(&self.cpu, &mut self.bus)
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    /// For changing registers between instructions
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    /// Number of frames the PPU has completed
+    pub fn frame(&self) -> u64 {
+        self.bus.ppu().frame()
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) {
+        self.breakpoints.remove(&address);
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Runs a single CPU cycle
+    pub fn step_cycle(&mut self) -> Result<StopReason, NesError> {
+        if let Some(jam) = self.cpu.jam() {
+            return Ok(StopReason::Jammed(jam));
+        }
+        self.do_cycle()?;
+        Ok(StopReason::Stepped)
+    }
+
+    /// Runs until the instruction in progress, or the next one, is finished. Breakpoints are
+    /// ignored.
+    pub fn step_instruction(&mut self) -> Result<StopReason, NesError> {
+        loop {
+            if let Some(jam) = self.cpu.jam() {
+                return Ok(StopReason::Jammed(jam));
+            }
+            self.do_cycle()?;
+            if self.cpu.instruction_done() {
+                return Ok(StopReason::Stepped);
+            }
+        }
+    }
+
+    /// Runs until the PPU finishes the frame in progress, then up to the end of the instruction
+    pub fn run_frame(&mut self) -> Result<StopReason, NesError> {
+        let frame = self.frame();
+        self.run(u64::MAX, |nes| {
+            (nes.frame() != frame).then_some(StopReason::FrameDone)
+        })
+    }
+
+    /// Runs `cycles` CPU cycles, which can end in the middle of an instruction
+    pub fn run_for_cycles(&mut self, cycles: u64) -> Result<StopReason, NesError> {
+        self.run(cycles, |_| None)
+    }
+
+    /// Runs until `predicate` returns true, which is checked after every instruction
+    pub fn run_until(
+        &mut self,
+        mut predicate: impl FnMut(&NES) -> bool,
+    ) -> Result<StopReason, NesError> {
+        self.run(u64::MAX, |nes| {
+            predicate(nes).then_some(StopReason::ConditionMet)
+        })
+    }
+
+    /// Runs until `stop` returns a reason, the cycle budget runs out, the CPU jams or reaches a
+    /// breakpoint. `stop` and the breakpoints are checked between instructions. In strict mode
+    /// an unmapped access stops right after the cycle it happened in.
+    fn run(
+        &mut self,
+        cycle_budget: u64,
+        mut stop: impl FnMut(&NES) -> Option<StopReason>,
+    ) -> Result<StopReason, NesError> {
+        let end = self.cpu.cycle.saturating_add(cycle_budget);
+        let result = loop {
+            if let Some(jam) = self.cpu.jam() {
+                break Ok(StopReason::Jammed(jam));
+            }
+            if self.cpu.cycle >= end {
+                break Ok(StopReason::BudgetExhausted);
+            }
+            if let Err(e) = self.do_cycle() {
+                break Err(e);
+            }
+            if !self.cpu.instruction_done() {
+                continue;
+            }
+            if !self.breakpoints.is_empty() && self.breakpoints.contains(&self.cpu.reg_pc) {
+                break Ok(StopReason::Breakpoint(self.cpu.reg_pc));
+            }
+            if let Some(reason) = stop(self) {
+                break Ok(reason);
+            }
+        };
+        self.save_periodically();
+        result
+    }
+
+    fn do_cycle(&mut self) -> Result<(), NesError> {
+        self.cpu.do_cycle(&mut self.bus);
+        match self.bus.take_error() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    fn save_periodically(&mut self) {
+        let frame = self.bus.ppu().frame();
+        if frame < self.next_save_frame {
+            return;
+        }
+        self.next_save_frame = frame + SAVE_INTERVAL_FRAMES;
+        if let Err(e) = self.save() {
+            self.save_error.get_or_insert(e);
+        }
+    }
+}
+
+impl Drop for NES {
+    fn drop(&mut self) {
+        // Nowhere to report errors to
+        let _ = self.save();
     }
 }
 
 impl Powerable for NES {
     fn power_on(&mut self) {
+        self.bus.power_on();
+        self.cpu.set_start_pc(None);
         self.cpu.power_on();
+        self.next_save_frame = SAVE_INTERVAL_FRAMES;
     }
     fn reset(&mut self) {
+        self.bus.reset();
         self.cpu.reset();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom::TestRom;
+
+    #[test]
+    fn keeps_failed_periodic_saves_for_the_caller() {
+        // Loops on a JMP at $8000
+        let ines = TestRom::new(1)
+            .battery()
+            .code(0x8000, &[0x4C, 0x00, 0x80])
+            .build();
+        let mut nes = NES::default();
+        nes.power_on();
+        nes.load_rom(ines).unwrap();
+        // Its directory doesn't exist, so it can't be written
+        let path = std::env::temp_dir().join("nesty-missing-directory/game.sav");
+        nes.attach_save_file(SaveFile::new(path)).unwrap();
+        nes.poke(0x6000, 0x42).unwrap();
+
+        nes.next_save_frame = 1;
+        nes.run_frame().unwrap();
+        assert!(matches!(nes.take_save_error(), Some(NesError::Io(_))));
+        assert!(nes.take_save_error().is_none());
+        assert!(nes.save().is_err());
+    }
+}
@@ -1,4 +1,7 @@
+use crate::cartridge::Cartridge;
+use crate::mapper::Mirroring;
 use crate::nes::Powerable;
+use crate::region::Region;
 
 use bitfield_struct::bitfield;
 
@@ -181,70 +184,790 @@ struct RegPPUStatus {
     in_vblank: bool,
 }
 
+/// Layout of the internal v and t registers ("loopy" registers), also used as the VRAM address
+#[bitfield(u16)]
+struct VramAddr {
+    #[bits(5)]
+    coarse_x: u8,
+    #[bits(5)]
+    coarse_y: u8,
+    #[bits(2)]
+    nametable: u8,
+    #[bits(3)]
+    fine_y: u8,
+    #[bits(1)]
+    __: u8,
+}
+
+#[bitfield(u8)]
+struct SpriteAttributes {
+    #[bits(2)]
+    palette: u8,
+    #[bits(3)]
+    __: u8,
+    #[bits(1)]
+    behind_background: bool,
+    #[bits(1)]
+    flip_horizontally: bool,
+    #[bits(1)]
+    flip_vertically: bool,
+}
+
+/// A sprite fetched during the previous scanline, ready to be drawn on the current one
+#[derive(Default, Clone, Copy)]
+struct SpriteUnit {
+    x: u8,
+    attributes: SpriteAttributes,
+    /// Already flipped horizontally if needed, so the leftmost pixel is always bit 7
+    pattern_lo: u8,
+    pattern_hi: u8,
+}
+
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+
 const VRAM_SIZE: usize = 2 * 1024;
+const OAM_SIZE: usize = 256;
+const SECONDARY_OAM_SIZE: usize = 32;
+const PALETTE_SIZE: usize = 32;
+const SPRITES_PER_SCANLINE: usize = 8;
+
+const LAST_DOT: u16 = 340;
+const VBLANK_SCANLINE: u16 = 241;
+/// The Dendy idles for 51 scanlines after the picture, which keeps its vblank 20 scanlines long
+const DENDY_VBLANK_SCANLINE: u16 = 291;
+const NTSC_PRE_RENDER_SCANLINE: u16 = 261;
+/// PAL and the Dendy have 312 scanlines per frame
+const PAL_PRE_RENDER_SCANLINE: u16 = 311;
+/// Roughly 600ms, how long the open bus keeps its value
+const OPEN_BUS_DECAY_FRAMES: u64 = 36;
 
 #[derive(Default)]
 pub struct PPU {
     memory: Vec<u8>,
+    palette: [u8; PALETTE_SIZE],
+    oam: Vec<u8>,
+    secondary_oam: [u8; SECONDARY_OAM_SIZE],
 
     reg_ppuctrl: RegPPUCtrl,
     reg_ppumask: RegPPUMask,
     reg_ppustatus: RegPPUStatus,
     reg_oamaddr: u8,
-    reg_oamdata: u8,
-    reg_ppuscroll: u8,
-    reg_ppuaddr: u8,
-    reg_ppudata: u8,
-    reg_oamdma: u8,
+    /// Holds the result of the previous $2007 read, which is what the next one returns
+    read_buffer: u8,
+    /// Reading PPUSTATUS just before vblank starts keeps the flag from being set that frame
+    suppress_vblank: bool,
+
+    /// The data bus between the CPU and the PPU, left floating when nothing drives it
+    open_bus: u8,
+    /// Frame in which each bit of the open bus was last driven
+    open_bus_refreshed: [u64; 8],
+
+    reg_v: VramAddr,
+    reg_t: VramAddr,
+    reg_x: u8,
+    reg_w: bool,
+
+    region: Region,
+    scanline: u16,
+    dot: u16,
+    odd_frame: bool,
+    frame: u64,
+
+    // Background pipeline
+    nametable_latch: u8,
+    attribute_latch: u8,
+    pattern_lo_latch: u8,
+    pattern_hi_latch: u8,
+    bg_pattern_lo: u16,
+    bg_pattern_hi: u16,
+    bg_attribute_lo: u16,
+    bg_attribute_hi: u16,
+
+    // Sprite pipeline
+    sprite_count: usize,
+    sprite_0_next_line: bool,
+    sprite_0_on_line: bool,
+    sprites: [SpriteUnit; SPRITES_PER_SCANLINE],
+
+    frame_buffer: Vec<u8>,
 }
 
 impl PPU {
-    pub fn read_reg(&self, reg: u8) -> u8 {
-        match reg {
-            0 => self.reg_ppuctrl.into_bits(),
-            1 => self.reg_ppumask.into_bits(),
-            2 => self.reg_ppustatus.into_bits(),
-            3 => self.reg_oamaddr,
-            4 => self.reg_oamdata,
-            5 => self.reg_ppuscroll,
-            6 => self.reg_ppuaddr,
-            7 => self.reg_ppudata,
-            _ => panic!("Failed to read reg"),
-        }
-    }
-    pub fn write_reg(&mut self, reg: u8, value: u8) {
-        match reg {
-            0 => self.reg_ppuctrl = RegPPUCtrl::from_bits(value),
+    pub fn nmi_output(&self) -> bool {
+        self.reg_ppustatus.in_vblank() && self.reg_ppuctrl.nmi_enable()
+    }
+
+    /// The last rendered picture, one palette index (0-63) per pixel, row by row
+    pub fn frame_buffer(&self) -> &[u8] {
+        &self.frame_buffer
+    }
+
+    /// Number of frames whose picture was completed, incremented when vblank starts
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    pub fn dot(&self) -> u16 {
+        self.dot
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    fn vblank_scanline(&self) -> u16 {
+        match self.region {
+            Region::Ntsc | Region::Pal => VBLANK_SCANLINE,
+            Region::Dendy => DENDY_VBLANK_SCANLINE,
+        }
+    }
+
+    fn pre_render_scanline(&self) -> u16 {
+        match self.region {
+            Region::Ntsc => NTSC_PRE_RENDER_SCANLINE,
+            Region::Pal | Region::Dendy => PAL_PRE_RENDER_SCANLINE,
+        }
+    }
+
+    pub fn read_reg(&mut self, reg: u8, cartridge: &mut Cartridge) -> u8 {
+        let open_bus = self.decayed_open_bus();
+        match reg & 7 {
+            2 => {
+                let value = (self.reg_ppustatus.into_bits() & 0xE0) | (open_bus & 0x1F);
+                self.reg_ppustatus.set_in_vblank(false);
+                self.reg_w = false;
+                if self.scanline == self.vblank_scanline() && self.dot == 1 {
+                    self.suppress_vblank = true;
+                }
+                self.drive_open_bus(value, 0xE0);
+                value
+            }
+            4 => {
+                let mut value = self.oam[self.reg_oamaddr as usize];
+                // Bits 2-4 of the sprite attributes don't exist
+                if self.reg_oamaddr & 3 == 2 {
+                    value &= 0xE3;
+                }
+                self.drive_open_bus(value, 0xFF);
+                value
+            }
+            7 => {
+                let address = self.reg_v.into_bits() & 0x3FFF;
+                let value = if address < 0x3F00 {
+                    let value = self.read_buffer;
+                    self.read_buffer = self.read_vram(cartridge, address);
+                    self.drive_open_bus(value, 0xFF);
+                    value
+                } else {
+                    // Palette reads are direct, but the buffer is filled from the nametable
+                    // underneath. Palette entries are 6 bits, the rest is open bus.
+                    self.read_buffer = self.read_vram(cartridge, address & 0x2FFF);
+                    let value = (self.read_vram(cartridge, address) & 0x3F) | (open_bus & 0xC0);
+                    self.drive_open_bus(value, 0x3F);
+                    value
+                };
+                self.increment_vram_addr();
+                value
+            }
+            // Write-only registers
+            _ => open_bus,
+        }
+    }
+
+    pub fn write_reg(&mut self, reg: u8, value: u8, cartridge: &mut Cartridge) {
+        self.drive_open_bus(value, 0xFF);
+        match reg & 7 {
+            0 => {
+                self.reg_ppuctrl = RegPPUCtrl::from_bits(value);
+                self.reg_t.set_nametable(value & 3);
+            }
             1 => self.reg_ppumask = RegPPUMask::from_bits(value),
-            2 => self.reg_ppustatus = RegPPUStatus::from_bits(value),
+            2 => {} // Read-only
             3 => self.reg_oamaddr = value,
-            4 => self.reg_oamdata = value,
-            5 => self.reg_ppuscroll = value,
-            6 => self.reg_ppuaddr = value,
-            7 => self.reg_ppudata = value,
-            _ => panic!("Failed to write reg"),
+            4 => {
+                if self.rendering_enabled() && self.on_render_scanline() {
+                    // Writes are ignored, but the address gets bumped to the next sprite
+                    self.reg_oamaddr = self.reg_oamaddr.wrapping_add(4);
+                } else {
+                    self.oam[self.reg_oamaddr as usize] = value;
+                    self.reg_oamaddr = self.reg_oamaddr.wrapping_add(1);
+                }
+            }
+            5 => {
+                if !self.reg_w {
+                    self.reg_t.set_coarse_x(value >> 3);
+                    self.reg_x = value & 7;
+                } else {
+                    self.reg_t.set_coarse_y(value >> 3);
+                    self.reg_t.set_fine_y(value & 7);
+                }
+                self.reg_w = !self.reg_w;
+            }
+            6 => {
+                let t = self.reg_t.into_bits();
+                if !self.reg_w {
+                    // The top bit of the 15 bit address is cleared
+                    self.reg_t = VramAddr::from_bits((t & 0x00FF) | (value as u16 & 0x3F) << 8);
+                } else {
+                    self.reg_t = VramAddr::from_bits((t & 0xFF00) | value as u16);
+                    self.reg_v = self.reg_t;
+                }
+                self.reg_w = !self.reg_w;
+            }
+            _ => {
+                let address = self.reg_v.into_bits() & 0x3FFF;
+                self.write_vram(cartridge, address, value);
+                self.increment_vram_addr();
+            }
         }
     }
+
+    /// Bits that haven't been driven for a while read back as 0
+    fn decayed_open_bus(&mut self) -> u8 {
+        for bit in 0..8 {
+            if self.frame - self.open_bus_refreshed[bit] >= OPEN_BUS_DECAY_FRAMES {
+                self.open_bus &= !(1 << bit);
+            }
+        }
+        self.open_bus
+    }
+
+    fn drive_open_bus(&mut self, value: u8, mask: u8) {
+        self.open_bus = (self.open_bus & !mask) | (value & mask);
+        for bit in 0..8 {
+            if mask & (1 << bit) != 0 {
+                self.open_bus_refreshed[bit] = self.frame;
+            }
+        }
+    }
+
+    fn increment_vram_addr(&mut self) {
+        if self.rendering_enabled() && self.on_render_scanline() {
+            // The rendering logic also owns v, both of its increments happen at once
+            self.increment_horizontal();
+            self.increment_vertical();
+            return;
+        }
+        let step = match self.reg_ppuctrl.increment_mode() {
+            RegPPUIncrementMode::AddOneGoingAcross => 1,
+            RegPPUIncrementMode::Add32GoingDown => 32,
+        };
+        self.reg_v = VramAddr::from_bits(self.reg_v.into_bits().wrapping_add(step) & 0x7FFF);
+    }
+
+    /// Advances the PPU by one dot
+    pub fn tick(&mut self, cartridge: &mut Cartridge) {
+        let rendering = self.rendering_enabled();
+        match self.scanline {
+            0..=239 => {
+                if rendering {
+                    self.render_dot(cartridge);
+                }
+                // After the shift of this dot, so pixel x comes from the shifters shifted x times
+                if (1..=256).contains(&self.dot) {
+                    self.output_pixel();
+                }
+            }
+            scanline if scanline == self.vblank_scanline() && self.dot == 1 => {
+                self.reg_ppustatus.set_in_vblank(!self.suppress_vblank);
+                self.suppress_vblank = false;
+                self.frame += 1;
+            }
+            scanline if scanline == self.pre_render_scanline() => {
+                if self.dot == 1 {
+                    self.reg_ppustatus.set_in_vblank(false);
+                    self.reg_ppustatus.set_spr_0_hit(false);
+                    self.reg_ppustatus.set_spr_overflow(false);
+                }
+                if rendering {
+                    self.render_dot(cartridge);
+                    if (280..=304).contains(&self.dot) {
+                        self.copy_vertical_bits();
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        self.dot += 1;
+        // The last dot of the pre-render scanline is skipped on odd frames while rendering, only
+        // on NTSC
+        if self.region == Region::Ntsc
+            && self.scanline == NTSC_PRE_RENDER_SCANLINE
+            && self.dot == LAST_DOT
+            && self.odd_frame
+            && rendering
+        {
+            self.dot += 1;
+        }
+        if self.dot > LAST_DOT {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline > self.pre_render_scanline() {
+                self.scanline = 0;
+                self.odd_frame = !self.odd_frame;
+            }
+        }
+    }
+
+    fn rendering_enabled(&self) -> bool {
+        self.reg_ppumask.bg_enable() || self.reg_ppumask.spr_enable()
+    }
+
+    fn on_render_scanline(&self) -> bool {
+        self.scanline < SCREEN_HEIGHT as u16 || self.scanline == self.pre_render_scanline()
+    }
+
+    fn read_vram(&mut self, cartridge: &mut Cartridge, address: u16) -> u8 {
+        cartridge.notify_ppu_address(address & 0x3FFF);
+        match address & 0x3FFF {
+            0x0000..=0x1FFF => cartridge.ppu_read(address),
+            0x2000..=0x3EFF => match nametable_offset(cartridge.mirroring(), address) {
+                offset if offset < VRAM_SIZE => self.memory[offset],
+                offset => cartridge.read_vram(offset - VRAM_SIZE),
+            },
+            _ => self.palette[palette_index(address)],
+        }
+    }
+
+    fn write_vram(&mut self, cartridge: &mut Cartridge, address: u16, value: u8) {
+        cartridge.notify_ppu_address(address & 0x3FFF);
+        match address & 0x3FFF {
+            0x0000..=0x1FFF => cartridge.ppu_write(address, value),
+            0x2000..=0x3EFF => match nametable_offset(cartridge.mirroring(), address) {
+                offset if offset < VRAM_SIZE => self.memory[offset] = value,
+                offset => cartridge.write_vram(offset - VRAM_SIZE, value),
+            },
+            _ => self.palette[palette_index(address)] = value & 0x3F,
+        }
+    }
+
+    /// Memory accesses and scrolling done on a dot of the visible and pre-render scanlines
+    fn render_dot(&mut self, cartridge: &mut Cartridge) {
+        let dot = self.dot;
+        if matches!(dot, 2..=257 | 322..=337) {
+            self.shift_background();
+        }
+        if matches!(dot, 9..=257 | 329..=337) && (dot - 1).is_multiple_of(8) {
+            self.reload_background();
+        }
+        if matches!(dot, 1..=256 | 321..=336) {
+            match (dot - 1) % 8 {
+                0 => self.fetch_nametable(cartridge),
+                2 => self.fetch_attribute(cartridge),
+                4 => self.pattern_lo_latch = self.fetch_bg_pattern(cartridge, 0),
+                6 => self.pattern_hi_latch = self.fetch_bg_pattern(cartridge, 8),
+                7 => self.increment_horizontal(),
+                _ => {}
+            }
+        }
+        match dot {
+            256 => self.increment_vertical(),
+            257 => {
+                self.copy_horizontal_bits();
+                self.evaluate_sprites();
+            }
+            // Unused nametable fetches, MMC5 relies on them
+            338 | 340 => self.fetch_nametable(cartridge),
+            _ => {}
+        }
+        if (257..=320).contains(&dot) {
+            let slot = ((dot - 257) / 8) as usize;
+            match (dot - 257) % 8 {
+                5 => self.fetch_sprite_pattern(cartridge, slot, 0),
+                7 => self.fetch_sprite_pattern(cartridge, slot, 8),
+                _ => {}
+            }
+        }
+    }
+
+    fn fetch_nametable(&mut self, cartridge: &mut Cartridge) {
+        let address = 0x2000 | (self.reg_v.into_bits() & 0x0FFF);
+        self.nametable_latch = self.read_vram(cartridge, address);
+    }
+
+    fn fetch_attribute(&mut self, cartridge: &mut Cartridge) {
+        let v = self.reg_v;
+        let address = 0x23C0
+            | (v.nametable() as u16) << 10
+            | ((v.coarse_y() >> 2) as u16) << 3
+            | (v.coarse_x() >> 2) as u16;
+        // Each byte covers 4x4 tiles, split into 2x2 tile quadrants
+        let shift = ((v.coarse_y() & 2) << 1) | (v.coarse_x() & 2);
+        self.attribute_latch = (self.read_vram(cartridge, address) >> shift) & 3;
+    }
+
+    fn fetch_bg_pattern(&mut self, cartridge: &mut Cartridge, plane: u16) -> u8 {
+        let table = match self.reg_ppuctrl.bg_tile_select() {
+            RegPPUBgTileSelect::Address0000 => 0x0000,
+            RegPPUBgTileSelect::Address1000 => 0x1000,
+        };
+        let address = table + self.nametable_latch as u16 * 16 + plane + self.reg_v.fine_y() as u16;
+        self.read_vram(cartridge, address)
+    }
+
+    fn shift_background(&mut self) {
+        self.bg_pattern_lo <<= 1;
+        self.bg_pattern_hi <<= 1;
+        self.bg_attribute_lo <<= 1;
+        self.bg_attribute_hi <<= 1;
+    }
+
+    fn reload_background(&mut self) {
+        self.bg_pattern_lo = (self.bg_pattern_lo & 0xFF00) | self.pattern_lo_latch as u16;
+        self.bg_pattern_hi = (self.bg_pattern_hi & 0xFF00) | self.pattern_hi_latch as u16;
+        // The attribute bits are the same for all 8 pixels of the tile
+        let fill = |bit: u8| {
+            if self.attribute_latch & bit != 0 {
+                0xFF
+            } else {
+                0x00
+            }
+        };
+        self.bg_attribute_lo = (self.bg_attribute_lo & 0xFF00) | fill(1);
+        self.bg_attribute_hi = (self.bg_attribute_hi & 0xFF00) | fill(2);
+    }
+
+    fn increment_horizontal(&mut self) {
+        let v = &mut self.reg_v;
+        if v.coarse_x() == 31 {
+            v.set_coarse_x(0);
+            v.set_nametable(v.nametable() ^ 1);
+        } else {
+            v.set_coarse_x(v.coarse_x() + 1);
+        }
+    }
+
+    fn increment_vertical(&mut self) {
+        let v = &mut self.reg_v;
+        if v.fine_y() < 7 {
+            v.set_fine_y(v.fine_y() + 1);
+            return;
+        }
+        v.set_fine_y(0);
+        match v.coarse_y() {
+            29 => {
+                v.set_coarse_y(0);
+                v.set_nametable(v.nametable() ^ 2);
+            }
+            // Rows 30 and 31 are attribute data, this wraps without switching nametables
+            31 => v.set_coarse_y(0),
+            coarse_y => v.set_coarse_y(coarse_y + 1),
+        }
+    }
+
+    fn copy_horizontal_bits(&mut self) {
+        let t = self.reg_t;
+        let v = &mut self.reg_v;
+        v.set_coarse_x(t.coarse_x());
+        v.set_nametable((v.nametable() & 2) | (t.nametable() & 1));
+    }
+
+    fn copy_vertical_bits(&mut self) {
+        let t = self.reg_t;
+        let v = &mut self.reg_v;
+        v.set_coarse_y(t.coarse_y());
+        v.set_fine_y(t.fine_y());
+        v.set_nametable((v.nametable() & 1) | (t.nametable() & 2));
+    }
+
+    fn sprite_height(&self) -> u16 {
+        match self.reg_ppuctrl.spr_height() {
+            RegPPUSprHeight::EightByEight => 8,
+            RegPPUSprHeight::EightBySixteen => 16,
+        }
+    }
+
+    /// Fills the secondary OAM with the sprites of the next scanline
+    fn evaluate_sprites(&mut self) {
+        self.secondary_oam = [0xFF; SECONDARY_OAM_SIZE];
+        self.sprite_count = 0;
+        self.sprite_0_next_line = false;
+        // Nothing is evaluated on the pre-render scanline, so no sprites are drawn on scanline 0
+        if self.scanline == self.pre_render_scanline() {
+            return;
+        }
+
+        let height = self.sprite_height();
+        let in_range = |y: u8| self.scanline.wrapping_sub(y as u16) < height;
+        let mut n = 0;
+        while n < 64 && self.sprite_count < SPRITES_PER_SCANLINE {
+            let sprite = &self.oam[n * 4..n * 4 + 4];
+            if in_range(sprite[0]) {
+                let slot = self.sprite_count * 4;
+                self.secondary_oam[slot..slot + 4].copy_from_slice(sprite);
+                self.sprite_0_next_line |= n == 0;
+                self.sprite_count += 1;
+            }
+            n += 1;
+        }
+        // Once 8 sprites are found the PPU keeps looking for a 9th one, but it wrongly
+        // increments the byte offset along with the sprite index when a sprite isn't in range
+        let mut m = 0;
+        while n < 64 {
+            if in_range(self.oam[n * 4 + m]) {
+                self.reg_ppustatus.set_spr_overflow(true);
+                break;
+            }
+            n += 1;
+            m = (m + 1) & 3;
+        }
+    }
+
+    fn fetch_sprite_pattern(&mut self, cartridge: &mut Cartridge, slot: usize, plane: u16) {
+        let y = self.secondary_oam[slot * 4];
+        let tile = self.secondary_oam[slot * 4 + 1] as u16;
+        let attributes = SpriteAttributes::from_bits(self.secondary_oam[slot * 4 + 2]);
+        let height = self.sprite_height();
+
+        // Empty slots still fetch tile $FF
+        let mut row = if slot < self.sprite_count {
+            self.scanline.wrapping_sub(y as u16) & (height - 1)
+        } else {
+            0
+        };
+        if slot < self.sprite_count && attributes.flip_vertically() {
+            row = height - 1 - row;
+        }
+        let address = match self.reg_ppuctrl.spr_height() {
+            RegPPUSprHeight::EightByEight => {
+                let table = match self.reg_ppuctrl.spr_tile_select() {
+                    RegPPUSprTileSelect::Address0000 => 0x0000,
+                    RegPPUSprTileSelect::Address1000 => 0x1000,
+                };
+                table + tile * 16 + row
+            }
+            RegPPUSprHeight::EightBySixteen => {
+                let table = (tile & 1) * 0x1000;
+                let tile = (tile & 0xFE) + row / 8;
+                table + tile * 16 + row % 8
+            }
+        };
+        let mut pattern = self.read_vram(cartridge, address + plane);
+
+        if slot >= self.sprite_count {
+            pattern = 0;
+        } else if attributes.flip_horizontally() {
+            pattern = pattern.reverse_bits();
+        }
+        let unit = &mut self.sprites[slot];
+        unit.x = self.secondary_oam[slot * 4 + 3];
+        unit.attributes = attributes;
+        match plane {
+            0 => unit.pattern_lo = pattern,
+            _ => unit.pattern_hi = pattern,
+        }
+        if slot == 0 {
+            self.sprite_0_on_line = self.sprite_0_next_line;
+        }
+    }
+
+    fn background_pixel(&self, x: usize) -> u8 {
+        let mask = self.reg_ppumask;
+        if !mask.bg_enable() || (x < 8 && !mask.bg_left_col_enable()) {
+            return 0;
+        }
+        let bit = 15 - self.reg_x;
+        let pixel = ((self.bg_pattern_hi >> bit) & 1) << 1 | ((self.bg_pattern_lo >> bit) & 1);
+        let palette =
+            ((self.bg_attribute_hi >> bit) & 1) << 1 | ((self.bg_attribute_lo >> bit) & 1);
+        (palette << 2 | pixel) as u8
+    }
+
+    /// The first opaque sprite pixel at `x`, with the index of the sprite it came from
+    fn sprite_pixel(&self, x: usize) -> Option<(usize, u8)> {
+        let mask = self.reg_ppumask;
+        if !mask.spr_enable() || (x < 8 && !mask.spr_left_col_enable()) {
+            return None;
+        }
+        self.sprites[..self.sprite_count]
+            .iter()
+            .enumerate()
+            .find_map(|(index, sprite)| {
+                let column = x.checked_sub(sprite.x as usize).filter(|&c| c < 8)?;
+                let bit = 7 - column;
+                let pixel =
+                    ((sprite.pattern_hi >> bit) & 1) << 1 | ((sprite.pattern_lo >> bit) & 1);
+                (pixel != 0).then_some((index, sprite.attributes.palette() << 2 | pixel))
+            })
+    }
+
+    fn output_pixel(&mut self) {
+        let x = (self.dot - 1) as usize;
+        let y = self.scanline as usize;
+
+        let bg = self.background_pixel(x);
+        let sprite = self.sprite_pixel(x);
+        let bg_opaque = bg & 3 != 0;
+        let palette_index = match sprite {
+            Some((index, pixel)) => {
+                if bg_opaque && index == 0 && self.sprite_0_on_line && x != 255 {
+                    self.reg_ppustatus.set_spr_0_hit(true);
+                }
+                if bg_opaque && self.sprites[index].attributes.behind_background() {
+                    bg
+                } else {
+                    0x10 | pixel
+                }
+            }
+            None if bg_opaque => bg,
+            None => 0,
+        };
+
+        let mut color = self.palette[palette_index as usize] & 0x3F;
+        if self.reg_ppumask.greyscale() {
+            color &= 0x30;
+        }
+        self.frame_buffer[y * SCREEN_WIDTH + x] = color;
+    }
+}
+
+/// Decodes a nametable address ($3000-$3EFF mirrors $2000-$2EFF) into an offset in the 2K of
+/// PPU VRAM, or past it into the cartridge's VRAM for four-screen boards
+fn nametable_offset(mirroring: Mirroring, address: u16) -> usize {
+    let table = (address as usize >> 10) & 3;
+    let offset = address as usize & 0x3FF;
+    let bank = match mirroring {
+        Mirroring::Horizontal => table >> 1,
+        Mirroring::Vertical => table & 1,
+        Mirroring::SingleScreenA => 0,
+        Mirroring::SingleScreenB => 1,
+        Mirroring::FourScreen => table,
+    };
+    bank * 0x400 + offset
+}
+
+/// The backdrop entries of the sprite palettes ($3F10/$3F14/$3F18/$3F1C) are the ones of the
+/// background palettes
+fn palette_index(address: u16) -> usize {
+    let index = (address & 0x1F) as usize;
+    match index & 0x13 {
+        0x10 => index & 0x0F,
+        _ => index,
+    }
 }
 
 impl Powerable for PPU {
     fn power_on(&mut self) {
         self.memory = vec![0; VRAM_SIZE];
+        self.palette = [0; PALETTE_SIZE];
+        self.oam = vec![0; OAM_SIZE];
+        self.frame_buffer = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
 
         self.reg_ppuctrl = RegPPUCtrl::from_bits(0);
         self.reg_ppumask = RegPPUMask::from_bits(0);
         self.reg_ppustatus.set_spr_0_hit(false);
         self.reg_oamaddr = 0;
-        self.reg_ppuscroll = 0;
-        self.reg_ppuaddr = 0;
-        self.reg_ppudata = 0;
+        self.read_buffer = 0;
+        self.suppress_vblank = false;
+        self.open_bus = 0;
+        self.open_bus_refreshed = [0; 8];
+
+        self.reg_v = VramAddr::new();
+        self.reg_t = VramAddr::new();
+        self.reg_x = 0;
+        self.reg_w = false;
+        self.scanline = 0;
+        self.dot = 0;
+        self.odd_frame = false;
+        self.frame = 0;
+        self.sprite_count = 0;
     }
     fn reset(&mut self) {
         self.memory = vec![0; VRAM_SIZE];
 
         self.reg_ppuctrl = RegPPUCtrl::from_bits(0);
         self.reg_ppumask = RegPPUMask::from_bits(0);
-        self.reg_ppuscroll = 0;
-        self.reg_ppudata = 0;
+        self.read_buffer = 0;
+        self.suppress_vblank = false;
+        self.reg_x = 0;
+        self.reg_t = VramAddr::new();
+
+        self.reg_w = false;
+        self.scanline = 0;
+        self.dot = 0;
+        self.odd_frame = false;
+        self.sprite_count = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::nrom::NROM;
+    use crate::mapper::ChrMemory;
+
+    const BLACK: u8 = 0x0F;
+    const WHITE: u8 = 0x30;
+
+    /// A PPU with tile 1 as a vertical line in the leftmost column, placed at tile column
+    /// `tile_x` of the top row, and sprite 0 using the same tile at `sprite_x`
+    fn render(tile_x: u16, sprite_x: u8) -> (PPU, Cartridge) {
+        let mut cartridge = Cartridge::default();
+        let chr = ChrMemory::new(Vec::new(), 0);
+        cartridge.insert(Box::new(NROM::new(
+            vec![0; 0x4000],
+            chr,
+            Mirroring::Vertical,
+            false,
+        )));
+        let mut ppu = PPU::default();
+        ppu.power_on();
+
+        let mut write_vram = |ppu: &mut PPU, address: u16, values: &[u8]| {
+            ppu.write_reg(6, (address >> 8) as u8, &mut cartridge);
+            ppu.write_reg(6, address as u8, &mut cartridge);
+            for &value in values {
+                ppu.write_reg(7, value, &mut cartridge);
+            }
+        };
+        write_vram(&mut ppu, 0x0010, &[0x80; 8]);
+        write_vram(&mut ppu, 0x2000 + tile_x, &[1]);
+        write_vram(&mut ppu, 0x3F00, &[BLACK, WHITE]);
+        write_vram(&mut ppu, 0x3F10, &[BLACK, WHITE]);
+        // Sprite 0 covers scanlines 1-8
+        ppu.write_reg(3, 0, &mut cartridge);
+        for value in [0, 1, 0, sprite_x] {
+            ppu.write_reg(4, value, &mut cartridge);
+        }
+        ppu.write_reg(5, 0, &mut cartridge);
+        ppu.write_reg(5, 0, &mut cartridge);
+        ppu.write_reg(0, 0, &mut cartridge);
+        // Background and sprites, including the leftmost 8 pixels
+        ppu.write_reg(1, 0x1E, &mut cartridge);
+
+        // The first frame starts without the pre-render scanline setting up scrolling
+        while ppu.frame() < 2 {
+            ppu.tick(&mut cartridge);
+        }
+        (ppu, cartridge)
+    }
+
+    #[test]
+    fn background_pixels_are_not_shifted() {
+        for tile_x in [0, 1, 31] {
+            let (ppu, _) = render(tile_x, 0xFF);
+            let x = tile_x as usize * 8;
+            assert_eq!(ppu.frame_buffer()[x], WHITE, "pixel {}", x);
+            assert_eq!(ppu.frame_buffer()[x + 1], BLACK, "pixel {}", x + 1);
+            if x > 0 {
+                assert_eq!(ppu.frame_buffer()[x - 1], BLACK, "pixel {}", x - 1);
+            }
+        }
+    }
+
+    #[test]
+    fn sprite_0_hit_needs_overlapping_pixels() {
+        let (mut ppu, mut cartridge) = render(1, 8);
+        assert_eq!(ppu.read_reg(2, &mut cartridge) & 0x40, 0x40);
+        let (mut ppu, mut cartridge) = render(1, 9);
+        assert_eq!(ppu.read_reg(2, &mut cartridge) & 0x40, 0);
+        let (mut ppu, mut cartridge) = render(1, 7);
+        assert_eq!(ppu.read_reg(2, &mut cartridge) & 0x40, 0);
     }
 }
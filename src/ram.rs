@@ -8,19 +8,13 @@ pub struct RAM {
 }
 
 impl RAM {
+    /// The 2K are mirrored across the whole address range
     pub fn read_mem(&self, address: u16) -> u8 {
-        *self
-            .memory
-            .get(address as usize)
-            .expect("Memory address out of range")
+        self.memory[address as usize % RAM_SIZE]
     }
 
     pub fn write_mem(&mut self, address: u16, value: u8) {
-        let elem = self
-            .memory
-            .get_mut(address as usize)
-            .expect("Memory address out of range");
-        *elem = value;
+        self.memory[address as usize % RAM_SIZE] = value;
     }
 }
 
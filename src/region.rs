@@ -0,0 +1,47 @@
+use crate::clock::{ClockDividers, DENDY_DIVIDERS, NTSC_DIVIDERS, PAL_DIVIDERS};
+use crate::ines::Timing;
+
+const NTSC_MASTER_CLOCK_RATE: f64 = 21_477_272.0;
+const PAL_MASTER_CLOCK_RATE: f64 = 26_601_712.0;
+
+/// The kind of console the system behaves like, which decides its clock rates and frame timing
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    /// The Famiclone sold in Russia, with PAL clocks but NTSC-like frame timing
+    Dendy,
+}
+
+impl Region {
+    /// The region a ROM header asks for. Multi-region games get NTSC.
+    pub fn from_timing(timing: Timing) -> Region {
+        match timing {
+            Timing::Ntsc | Timing::MultipleRegion => Region::Ntsc,
+            Timing::Pal => Region::Pal,
+            Timing::Dendy => Region::Dendy,
+        }
+    }
+
+    pub fn clock_dividers(self) -> ClockDividers {
+        match self {
+            Region::Ntsc => NTSC_DIVIDERS,
+            Region::Pal => PAL_DIVIDERS,
+            Region::Dendy => DENDY_DIVIDERS,
+        }
+    }
+
+    /// In Hz
+    pub fn master_clock_rate(self) -> f64 {
+        match self {
+            Region::Ntsc => NTSC_MASTER_CLOCK_RATE,
+            Region::Pal | Region::Dendy => PAL_MASTER_CLOCK_RATE,
+        }
+    }
+
+    /// In Hz
+    pub fn cpu_clock_rate(self) -> f64 {
+        self.master_clock_rate() / self.clock_dividers().cpu as f64
+    }
+}
@@ -0,0 +1,51 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// The file battery-backed PRG-RAM is kept in between sessions
+pub struct SaveFile {
+    path: PathBuf,
+    /// What the file currently holds, so unchanged RAM isn't written again
+    saved: Vec<u8>,
+}
+
+impl SaveFile {
+    pub fn new(path: PathBuf) -> Self {
+        SaveFile {
+            path,
+            saved: Vec::new(),
+        }
+    }
+
+    /// `<rom>.sav` next to the ROM
+    pub fn for_rom(rom_path: &Path) -> Self {
+        Self::new(rom_path.with_extension("sav"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Fills `ram` with the file's contents, leaving it untouched if there's no file yet
+    pub fn load(&mut self, ram: &mut [u8]) -> io::Result<()> {
+        let data = match fs::read(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let len = data.len().min(ram.len());
+        ram[..len].copy_from_slice(&data[..len]);
+        self.saved = ram.to_vec();
+        Ok(())
+    }
+
+    /// Writes `ram` to the file if it changed since the last load or flush
+    pub fn flush(&mut self, ram: &[u8]) -> io::Result<()> {
+        if self.saved == ram {
+            return Ok(());
+        }
+        fs::write(&self.path, ram)?;
+        self.saved = ram.to_vec();
+        Ok(())
+    }
+}
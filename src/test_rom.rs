@@ -0,0 +1,72 @@
+//! Builds small iNES images for tests. Also included by the benchmarks, so it only uses std.
+
+const PRG_BANK_SIZE: usize = 16 * 1024;
+
+/// An iNES image with PRG-ROM full of NOPs and no CHR-ROM, so boards get CHR-RAM. The NMI,
+/// reset and IRQ vectors all point at $8000 until they're changed.
+pub struct TestRom {
+    header: [u8; 16],
+    prg_rom: Vec<u8>,
+}
+
+impl TestRom {
+    /// `prg_banks` 16K banks of PRG-ROM, mapper 0
+    pub fn new(prg_banks: u8) -> TestRom {
+        let mut header = [0; 16];
+        header[..4].copy_from_slice(b"NES\x1A");
+        header[4] = prg_banks;
+        let rom = TestRom {
+            header,
+            prg_rom: vec![0xEA; prg_banks as usize * PRG_BANK_SIZE],
+        };
+        rom.nmi_vector(0x8000)
+            .reset_vector(0x8000)
+            .irq_vector(0x8000)
+    }
+
+    pub fn mapper(mut self, mapper: u8) -> Self {
+        self.header[6] = self.header[6] & 0x0F | mapper << 4;
+        self.header[7] = self.header[7] & 0x0F | mapper & 0xF0;
+        self
+    }
+
+    /// Battery-backed PRG-RAM
+    pub fn battery(self) -> Self {
+        let flags6 = self.header[6] | 0x02;
+        self.header(6, flags6)
+    }
+
+    /// Sets any header byte, e.g. for NES 2.0 fields
+    pub fn header(mut self, index: usize, value: u8) -> Self {
+        self.header[index] = value;
+        self
+    }
+
+    /// Puts `bytes` where the CPU sees them at `address` with the last bank at the end of the
+    /// address space, e.g. $8000 is the start of PRG-ROM for NROM
+    pub fn code(mut self, address: u16, bytes: &[u8]) -> Self {
+        let len = self.prg_rom.len();
+        let start = (len - (0x10000 - address as usize) % len) % len;
+        self.prg_rom[start..start + bytes.len()].copy_from_slice(bytes);
+        self
+    }
+
+    pub fn nmi_vector(self, address: u16) -> Self {
+        self.code(0xFFFA, &address.to_le_bytes())
+    }
+
+    pub fn reset_vector(self, address: u16) -> Self {
+        self.code(0xFFFC, &address.to_le_bytes())
+    }
+
+    /// Also where BRK goes
+    pub fn irq_vector(self, address: u16) -> Self {
+        self.code(0xFFFE, &address.to_le_bytes())
+    }
+
+    pub fn build(self) -> Vec<u8> {
+        let mut ines = self.header.to_vec();
+        ines.extend(self.prg_rom);
+        ines
+    }
+}
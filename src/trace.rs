@@ -0,0 +1,160 @@
+use std::io::Write;
+
+use crate::cpu::CPU;
+use crate::disasm::Instruction;
+use crate::instructions::{AddressingMode, InstructionType};
+use crate::interconnect::Interconnect;
+use crate::utils::build_u16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// Follows execution. Nothing is traced, and nothing costs anything, unless a tracer is attached
+/// with `NES::set_tracer`.
+pub trait Tracer {
+    /// Called right before the CPU fetches the opcode at `cpu.reg_pc`, but not when the fetch is
+    /// thrown away for an interrupt. `bus` should only be looked at with `Interconnect::peek`.
+    fn instruction(&mut self, cpu: &CPU, bus: &mut Interconnect);
+
+    /// Called for every access on the CPU bus, including dummy reads and DMA
+    fn bus_access(&mut self, _address: u16, _value: u8, _kind: AccessKind) {}
+}
+
+/// Writes a line per instruction in the format of Nintendulator, which nestest.log uses
+pub struct NestestTracer<W: Write> {
+    output: W,
+    failed: bool,
+}
+
+impl<W: Write> NestestTracer<W> {
+    pub fn new(output: W) -> Self {
+        NestestTracer {
+            output,
+            failed: false,
+        }
+    }
+}
+
+impl<W: Write> Tracer for NestestTracer<W> {
+    fn instruction(&mut self, cpu: &CPU, bus: &mut Interconnect) {
+        if self.failed {
+            return;
+        }
+        let line = nestest_line(cpu, bus);
+        if let Err(e) = writeln!(self.output, "{}", line) {
+            eprintln!("Failed to write trace: {}", e);
+            self.failed = true;
+        }
+    }
+}
+
+/// Formats the instruction at PC and the CPU state before it like a line of nestest.log:
+///
+/// `C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7`
+pub fn nestest_line(cpu: &CPU, bus: &mut Interconnect) -> String {
+    let instruction = Instruction::read(cpu.reg_pc, |address| bus.peek(address));
+    let disassembly = match operand_with_values(&instruction, cpu, bus) {
+        operand if operand.is_empty() => instruction.mnemonic(),
+        operand => format!("{} {}", instruction.mnemonic(), operand),
+    };
+    format!(
+        "{:04X}  {:<8} {}{:<31} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+        instruction.address,
+        instruction.hex_bytes(),
+        if instruction.opcode().unofficial { '*' } else { ' ' },
+        disassembly,
+        cpu.reg_a,
+        cpu.reg_x,
+        cpu.reg_y,
+        cpu.status.into_bits(),
+        cpu.reg_s,
+        bus.ppu().scanline(),
+        bus.ppu().dot(),
+        cpu.cycle
+    )
+}
+
+/// The operand of `instruction`, followed by the effective address and the value there the way
+/// Nintendulator shows them
+fn operand_with_values(instruction: &Instruction, cpu: &CPU, bus: &mut Interconnect) -> String {
+    let opcode = instruction.opcode();
+    let byte = instruction.operand() as u8;
+    let word = instruction.operand();
+    let zero_page_word = |bus: &mut Interconnect, pointer: u8| {
+        build_u16(
+            bus.peek(pointer.wrapping_add(1) as u16),
+            bus.peek(pointer as u16),
+        )
+    };
+    match opcode.addr_mode {
+        AddressingMode::Implicit
+        | AddressingMode::Illegal
+        | AddressingMode::Accumulator
+        | AddressingMode::Immediate => instruction.operand_text(None),
+        AddressingMode::ZeroPage => format!("${:02X} = {:02X}", byte, bus.peek(byte as u16)),
+        AddressingMode::ZeroPageIndexedX | AddressingMode::ZeroPageIndexedY => {
+            let (register, index) = match opcode.addr_mode {
+                AddressingMode::ZeroPageIndexedX => ('X', cpu.reg_x),
+                _ => ('Y', cpu.reg_y),
+            };
+            let address = byte.wrapping_add(index);
+            format!(
+                "${:02X},{} @ {:02X} = {:02X}",
+                byte,
+                register,
+                address,
+                bus.peek(address as u16)
+            )
+        }
+        AddressingMode::Absolute => match opcode.inst_type {
+            InstructionType::JMP | InstructionType::JSR => format!("${:04X}", word),
+            _ => format!("${:04X} = {:02X}", word, bus.peek(word)),
+        },
+        AddressingMode::AbsoluteIndexedX | AddressingMode::AbsoluteIndexedY => {
+            let (register, index) = match opcode.addr_mode {
+                AddressingMode::AbsoluteIndexedX => ('X', cpu.reg_x),
+                _ => ('Y', cpu.reg_y),
+            };
+            let address = word.wrapping_add(index as u16);
+            format!(
+                "${:04X},{} @ {:04X} = {:02X}",
+                word,
+                register,
+                address,
+                bus.peek(address)
+            )
+        }
+        AddressingMode::IndexedIndirect => {
+            let pointer = byte.wrapping_add(cpu.reg_x);
+            let address = zero_page_word(bus, pointer);
+            format!(
+                "(${:02X},X) @ {:02X} = {:04X} = {:02X}",
+                byte,
+                pointer,
+                address,
+                bus.peek(address)
+            )
+        }
+        AddressingMode::IndirectIndexed => {
+            let base = zero_page_word(bus, byte);
+            let address = base.wrapping_add(cpu.reg_y as u16);
+            format!(
+                "(${:02X}),Y = {:04X} @ {:04X} = {:02X}",
+                byte,
+                base,
+                address,
+                bus.peek(address)
+            )
+        }
+        AddressingMode::Indirect => {
+            // The high byte of the pointer isn't incremented, so $xxFF wraps around
+            let msb_address = (word & 0xFF00) | (word.wrapping_add(1) & 0x00FF);
+            let target = build_u16(bus.peek(msb_address), bus.peek(word));
+            format!("(${:04X}) = {:04X}", word, target)
+        }
+        AddressingMode::Relative => format!("${:04X}", instruction.branch_target()),
+    }
+}
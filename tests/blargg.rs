@@ -0,0 +1,80 @@
+//! Runs every test ROM under `tests/roms/blargg` (or `BLARGG_ROMS`), e.g. the cpu, ppu_vbl_nmi,
+//! apu and mmc3_test suites, and prints a pass/fail table. ROMs have to report through the $6000
+//! protocol. The ROMs aren't distributed with the crate, so the test is ignored by default; run it
+//! with `cargo test --test blargg -- --ignored`. It fails when there are no ROMs to run.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use nesty::blargg::{run_test_rom, TestOutcome, DEFAULT_TIMEOUT_FRAMES};
+
+fn rom_directory() -> PathBuf {
+    env::var_os("BLARGG_ROMS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/roms/blargg"))
+}
+
+/// All the .nes files in `directory` and its subdirectories, sorted
+fn find_roms(directory: &Path, roms: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_roms(&path, roms)?;
+        } else if path.extension().is_some_and(|extension| extension == "nes") {
+            roms.push(path);
+        }
+    }
+    roms.sort();
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs the test ROMs, see the module docs"]
+fn blargg_test_roms_pass() {
+    let directory = rom_directory();
+    let mut roms = Vec::new();
+    if let Err(e) = find_roms(&directory, &mut roms) {
+        panic!("can't read {}: {}", directory.display(), e);
+    }
+    assert!(!roms.is_empty(), "no test ROMs in {}", directory.display());
+
+    let mut failures = 0;
+    println!("{:<56} {:<10} Message", "ROM", "Result");
+    for rom in &roms {
+        let name = rom.strip_prefix(&directory).unwrap_or(rom).display();
+        let (result, message) = match fs::read(rom)
+            .map_err(Into::into)
+            .and_then(|ines| run_test_rom(ines, DEFAULT_TIMEOUT_FRAMES))
+        {
+            Ok(report) => {
+                let result = match report.outcome {
+                    TestOutcome::Passed => "passed".to_string(),
+                    TestOutcome::Failed(code) => format!("failed #{}", code),
+                    TestOutcome::TimedOut => "timed out".to_string(),
+                    TestOutcome::Jammed(jam) => format!("jam ${:04X}", jam.pc),
+                };
+                if report.outcome != TestOutcome::Passed {
+                    failures += 1;
+                }
+                // Messages start with the name of the test, the last line says what went wrong
+                let message = report.message.lines().last().unwrap_or("").to_string();
+                (result, message)
+            }
+            Err(e) => {
+                failures += 1;
+                ("error".to_string(), e.to_string())
+            }
+        };
+        println!("{:<56} {:<10} {}", name, result, message);
+    }
+    println!("{} of {} passed", roms.len() - failures, roms.len());
+    assert_eq!(
+        failures,
+        0,
+        "{} of {} test ROMs failed",
+        failures,
+        roms.len()
+    );
+}
@@ -0,0 +1,177 @@
+//! Runs nestest from its automation entry point at $C000 and compares the trace, line by line,
+//! with the golden nestest.log from Nintendulator.
+//!
+//! The ROM and the log aren't distributed with the crate, so the test is ignored by default. Run
+//! it with `cargo test --test nestest -- --ignored` after putting them in `tests/roms/nestest.nes`
+//! and `tests/roms/nestest.log`, or wherever `NESTEST_ROM` and `NESTEST_LOG` point. It fails
+//! when they're missing.
+
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use nesty::cpu::CPU;
+use nesty::interconnect::Interconnect;
+use nesty::nes::{StopReason, NES};
+use nesty::trace::{nestest_line, Tracer};
+
+const ENTRY_POINT: u16 = 0xC000;
+/// The log ends after about 27000 cycles, this only guards against running away
+const MAX_CYCLES: u64 = 1_000_000;
+const FLAG_NAMES: &[u8; 8] = b"NV-BDIZC";
+
+#[derive(Default)]
+struct Comparison {
+    expected: Vec<String>,
+    checked: usize,
+    divergence: Option<String>,
+}
+
+impl Comparison {
+    fn done(&self) -> bool {
+        self.divergence.is_some() || self.checked >= self.expected.len()
+    }
+}
+
+/// Checks every traced instruction against the next line of the log as it runs
+struct GoldenTracer {
+    comparison: Rc<RefCell<Comparison>>,
+}
+
+impl Tracer for GoldenTracer {
+    fn instruction(&mut self, cpu: &CPU, bus: &mut Interconnect) {
+        let mut comparison = self.comparison.borrow_mut();
+        if comparison.done() {
+            return;
+        }
+        let actual = nestest_line(cpu, bus);
+        let expected = &comparison.expected[comparison.checked];
+        if actual != *expected {
+            comparison.divergence = Some(actual);
+        } else {
+            comparison.checked += 1;
+        }
+    }
+}
+
+fn path_from_env(variable: &str, default: &str) -> PathBuf {
+    env::var_os(variable)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(default))
+}
+
+/// The value after `key` in a log line, e.g. "24" for "P:". PPU positions contain a space, so
+/// they run up to the next field.
+fn field<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let start = line.find(&format!(" {}", key))? + key.len() + 1;
+    let rest = &line[start..];
+    let end = match key {
+        "PPU:" => rest.find(" CYC:")?,
+        _ => rest.find(' ').unwrap_or(rest.len()),
+    };
+    Some(rest[..end].trim())
+}
+
+fn describe_flags(p: u8) -> String {
+    FLAG_NAMES
+        .iter()
+        .enumerate()
+        .map(|(i, &name)| match p & (0x80 >> i) {
+            0 => name.to_ascii_lowercase() as char,
+            _ => name as char,
+        })
+        .collect()
+}
+
+/// Lists the fields that differ between two log lines
+fn register_diff(expected: &str, actual: &str) -> Vec<String> {
+    let mut differences = Vec::new();
+    // PC, the instruction bytes and the disassembly
+    let (expected_code, actual_code) = (
+        expected.split(" A:").next().unwrap_or(expected).trim_end(),
+        actual.split(" A:").next().unwrap_or(actual).trim_end(),
+    );
+    if expected_code != actual_code {
+        differences.push(format!(
+            "instruction: expected \"{}\", got \"{}\"",
+            expected_code, actual_code
+        ));
+    }
+    for key in ["A:", "X:", "Y:", "P:", "SP:", "PPU:", "CYC:"] {
+        let (expected_value, actual_value) = (field(expected, key), field(actual, key));
+        if expected_value == actual_value {
+            continue;
+        }
+        let parsed_flags = (
+            expected_value.and_then(|value| u8::from_str_radix(value, 16).ok()),
+            actual_value.and_then(|value| u8::from_str_radix(value, 16).ok()),
+        );
+        differences.push(match (key, parsed_flags) {
+            ("P:", (Some(expected_p), Some(actual_p))) => format!(
+                "P: expected {:02X} ({}), got {:02X} ({})",
+                expected_p,
+                describe_flags(expected_p),
+                actual_p,
+                describe_flags(actual_p)
+            ),
+            _ => format!(
+                "{} expected {}, got {}",
+                key,
+                expected_value.unwrap_or("nothing"),
+                actual_value.unwrap_or("nothing")
+            ),
+        });
+    }
+    differences
+}
+
+#[test]
+#[ignore = "needs nestest.nes and nestest.log, see the module docs"]
+fn nestest_matches_golden_log() {
+    let rom_path = path_from_env("NESTEST_ROM", "tests/roms/nestest.nes");
+    let log_path = path_from_env("NESTEST_LOG", "tests/roms/nestest.log");
+    let rom =
+        fs::read(&rom_path).unwrap_or_else(|e| panic!("can't read {}: {}", rom_path.display(), e));
+    let log = fs::read_to_string(&log_path)
+        .unwrap_or_else(|e| panic!("can't read {}: {}", log_path.display(), e));
+
+    let comparison = Rc::new(RefCell::new(Comparison {
+        expected: log
+            .lines()
+            .map(|line| line.trim_end().to_string())
+            .collect(),
+        ..Comparison::default()
+    }));
+    let mut nes = NES::default();
+    nes.load_rom(rom).expect("nestest.nes should load");
+    nes.power_on_at(ENTRY_POINT);
+    nes.set_tracer(Some(Box::new(GoldenTracer {
+        comparison: comparison.clone(),
+    })));
+    let reason = nes
+        .run_until(|nes| comparison.borrow().done() || nes.cpu().cycle > MAX_CYCLES)
+        .expect("nestest shouldn't touch unmapped memory");
+
+    let comparison = comparison.borrow();
+    if let Some(actual) = &comparison.divergence {
+        let expected = &comparison.expected[comparison.checked];
+        panic!(
+            "nestest diverged at line {} of {}\nexpected: {}\nactual:   {}\n{}",
+            comparison.checked + 1,
+            log_path.display(),
+            expected,
+            actual,
+            register_diff(expected, actual).join("\n")
+        );
+    }
+    assert!(
+        comparison.checked == comparison.expected.len(),
+        "nestest stopped ({:?}) after {} of {} lines",
+        reason,
+        comparison.checked,
+        comparison.expected.len()
+    );
+    assert_eq!(reason, StopReason::ConditionMet);
+}